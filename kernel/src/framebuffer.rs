@@ -0,0 +1,46 @@
+//! Presents the framebuffer of an emulated guest app on a display.
+
+use alloc::boxed::Box;
+use driver_interface::display::DisplayResource;
+use embedded_graphics::{
+    pixelcolor::{raw::RawU16, Rgb565},
+    prelude::{Pixel, Point},
+};
+use riscv::framebuffer::Framebuffer;
+
+/// Adapts the framebuffer device of an emulated guest to a [`DisplayResource`], so the pixels the
+/// guest flushes appear on the panel.
+#[derive(Debug)]
+pub struct FramebufferDisplay {
+    display: Box<dyn DisplayResource>,
+}
+
+impl FramebufferDisplay {
+    pub fn new(display: Box<dyn DisplayResource>) -> Self {
+        FramebufferDisplay { display }
+    }
+
+    /// Create a framebuffer device with the size of the display, to be attached to the bus of
+    /// the emulator.
+    pub fn framebuffer(&self) -> Framebuffer {
+        let size = self.display.bounding_box().size;
+        Framebuffer::new(size.width, size.height)
+    }
+
+    /// Draw the area the guest flushed since the last call on the display.
+    pub fn present(&mut self, framebuffer: &mut Framebuffer) {
+        let Some(rect) = framebuffer.take_flush() else {
+            return;
+        };
+
+        let origin = self.display.bounding_box().top_left;
+        let framebuffer = &*framebuffer;
+        let mut pixels = (rect.y..rect.y + rect.height)
+            .flat_map(|y| (rect.x..rect.x + rect.width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let color = Rgb565::from(RawU16::new(framebuffer.pixel(x, y)));
+                Pixel(origin + Point::new(x as i32, y as i32), color)
+            });
+        DisplayResource::draw_iter(&mut *self.display, &mut pixels).unwrap();
+    }
+}
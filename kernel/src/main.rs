@@ -23,6 +23,7 @@ cfg_if::cfg_if! {
 
 pub mod allocator;
 pub mod console;
+pub mod framebuffer;
 pub mod task;
 
 /// Called by the arch layer once early initialization is done.
@@ -0,0 +1,1073 @@
+//! The asm module encodes, assembles, and disassembles the RV32 instructions the emulator
+//! executes: RV32I, M, A, Zicsr, Zifencei, and the privileged instructions.
+//!
+//! Each instruction has an encoder named after its mnemonic, with `.` replaced by `_`, that takes
+//! the operands in the order of the assembly syntax, with `offset(rs1)` as `rs1, offset`:
+//!
+//! ```
+//! use riscv::asm::{self, Reg};
+//!
+//! assert_eq!(0x00500f93, asm::addi(Reg::T6, Reg::Zero, 5));
+//! assert_eq!(0x0062a223, asm::sw(Reg::T1, Reg::T0, 4));
+//! ```
+//!
+//! The encoders panic if an immediate doesn't fit its field. Offsets of branches and jumps are
+//! relative to the instruction.
+//!
+//! [`assemble`] assembles a program written in the standard syntax, one statement per line:
+//!
+//! ```text
+//! # Sum 1 to 10.
+//!         li a0, 0
+//!         li t0, 10
+//! loop:   add a0, a0, t0          # Labels name the offset of the next statement.
+//!         addi t0, t0, -1
+//!         bnez t0, loop
+//!         .word 0x0000007f        # The words of the directive are written as they are.
+//! ```
+//!
+//! Besides the instructions, it accepts the pseudo-instructions `nop`, `li`, `la`, `mv`, `not`,
+//! `neg`, `j`, `jal <offset>`, `jr`, `ret`, `call`, `tail`, `beqz`, `bnez`, `csrr`, and `csrw`.
+//! `la`, `call`, and `tail` are a pc-relative `auipc` and the instruction after it. The atomic
+//! instructions take the `.aq`, `.rl`, and `.aqrl` suffixes, as in `amoswap.w.aq`. [`disassemble`]
+//! writes the instructions without pseudo-instructions, with the ABI names of the registers, and
+//! it round-trips: the text it returns assembles to the same instruction.
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt;
+
+use crate::csr::{CsrAddress, CSR_NAMES};
+
+/// An integer register.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub enum Reg {
+    #[default]
+    Zero,
+    Ra,
+    Sp,
+    Gp,
+    Tp,
+    T0,
+    T1,
+    T2,
+    S0,
+    S1,
+    A0,
+    A1,
+    A2,
+    A3,
+    A4,
+    A5,
+    A6,
+    A7,
+    S2,
+    S3,
+    S4,
+    S5,
+    S6,
+    S7,
+    S8,
+    S9,
+    S10,
+    S11,
+    T3,
+    T4,
+    T5,
+    T6,
+}
+
+/// The registers in the order of their numbers.
+const REGS: [Reg; 32] = [
+    Reg::Zero,
+    Reg::Ra,
+    Reg::Sp,
+    Reg::Gp,
+    Reg::Tp,
+    Reg::T0,
+    Reg::T1,
+    Reg::T2,
+    Reg::S0,
+    Reg::S1,
+    Reg::A0,
+    Reg::A1,
+    Reg::A2,
+    Reg::A3,
+    Reg::A4,
+    Reg::A5,
+    Reg::A6,
+    Reg::A7,
+    Reg::S2,
+    Reg::S3,
+    Reg::S4,
+    Reg::S5,
+    Reg::S6,
+    Reg::S7,
+    Reg::S8,
+    Reg::S9,
+    Reg::S10,
+    Reg::S11,
+    Reg::T3,
+    Reg::T4,
+    Reg::T5,
+    Reg::T6,
+];
+
+/// The ABI names of the registers in the order of their numbers.
+const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+impl Reg {
+    /// Return the register `x<index>`, or `None` if there is no such register.
+    pub fn from_index(index: u32) -> Option<Reg> {
+        REGS.get(index as usize).copied()
+    }
+
+    /// Return the number of the register.
+    pub fn index(&self) -> u32 {
+        *self as u32
+    }
+
+    /// Return the ABI name of the register.
+    pub fn name(&self) -> &'static str {
+        ABI_NAMES[*self as usize]
+    }
+
+    /// Parse a register by `x<n>`, its ABI name, or `fp`, which is `s0`.
+    pub fn parse(s: &str) -> Option<Reg> {
+        match s.strip_prefix('x') {
+            Some(n) if n.bytes().all(|b| b.is_ascii_digit()) => Reg::from_index(n.parse().ok()?),
+            _ if s == "fp" => Some(Reg::S0),
+            _ => ABI_NAMES
+                .iter()
+                .position(|name| *name == s)
+                .map(|i| REGS[i]),
+        }
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Return the address of the CSR named `name` in assembly.
+pub fn csr_address(name: &str) -> Option<CsrAddress> {
+    CSR_NAMES
+        .iter()
+        .find(|(csr, _)| *csr == name)
+        .map(|(_, addr)| *addr)
+}
+
+/// How the operands of an instruction are written and encoded.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Format {
+    /// `rd, rs1, rs2`
+    R,
+    /// `rd, rs1, imm`
+    I,
+    /// `rd, rs1, shamt`
+    Shift,
+    /// `rd, imm(rs1)`
+    Load,
+    /// `rs2, imm(rs1)`
+    Store,
+    /// `rs1, rs2, offset`
+    Branch,
+    /// `rd, imm`, the upper 20 bits of a value.
+    U,
+    /// `rd, offset`
+    J,
+    /// `rd, csr, rs1`
+    Csr,
+    /// `rd, csr, zimm`
+    CsrI,
+    /// `rd, rs2, (rs1)`
+    Amo,
+    /// `rd, (rs1)`
+    Lr,
+    /// `pred, succ`, the sets of `i`, `o`, `r`, and `w` accesses the fence orders.
+    Fence,
+    /// `rs1, rs2`
+    Vma,
+    /// No operands.
+    None,
+}
+
+impl Format {
+    /// Return the mask of the bits that identify an instruction of the format.
+    fn mask(&self) -> u32 {
+        match self {
+            Format::R | Format::Shift => 0xfe00707f,
+            Format::U | Format::J => 0x0000007f,
+            Format::Amo => 0xf800707f,
+            Format::Lr => 0xf9f0707f,
+            Format::Vma => 0xfe007fff,
+            Format::None => 0xffffffff,
+            _ => 0x0000707f,
+        }
+    }
+}
+
+/// The operands of an instruction. `csr` is the CSR of `Csr` and `CsrI`, `imm` is the
+/// immediate, the offset, `zimm`, or `pred << 4 | succ`, and `aqrl` is `aq << 1 | rl` of `Amo`
+/// and `Lr`.
+#[derive(Debug, Default, Copy, Clone)]
+struct Operands {
+    rd: Reg,
+    rs1: Reg,
+    rs2: Reg,
+    csr: CsrAddress,
+    imm: i32,
+    aqrl: u32,
+}
+
+/// The suffixes of the atomic mnemonics that set the aq and rl bits, with `aq << 1 | rl`.
+const ORDERINGS: &[(&str, u32)] = &[(".aqrl", 0b11), (".aq", 0b10), (".rl", 0b01)];
+
+/// Split the ordering suffix off `mnemonic`, and return the rest and `aq << 1 | rl`.
+fn split_ordering(mnemonic: &str) -> (&str, u32) {
+    ORDERINGS
+        .iter()
+        .find_map(|(suffix, aqrl)| Some((mnemonic.strip_suffix(suffix)?, *aqrl)))
+        .unwrap_or((mnemonic, 0))
+}
+
+/// Return an error unless `imm` is in `min..=max` and a multiple of `align`.
+fn check_range(imm: i32, min: i32, max: i32, align: i32) -> Result<u32, String> {
+    if imm < min || max < imm {
+        return Err(format!("{} is out of range {}..={}", imm, min, max));
+    }
+    if imm % align != 0 {
+        return Err(format!("{} is not a multiple of {}", imm, align));
+    }
+    Ok(imm as u32)
+}
+
+/// Encode an instruction whose fixed bits are `bits`.
+fn encode(format: Format, bits: u32, operands: &Operands) -> Result<u32, String> {
+    let rd = operands.rd.index() << 7;
+    let rs1 = operands.rs1.index() << 15;
+    let rs2 = operands.rs2.index() << 20;
+    let csr = (operands.csr as u32 & 0xfff) << 20;
+    let imm = operands.imm;
+    let inst = match format {
+        Format::R => bits | rd | rs1 | rs2,
+        Format::I | Format::Load => bits | rd | rs1 | check_range(imm, -2048, 2047, 1)? << 20,
+        Format::Shift => bits | rd | rs1 | check_range(imm, 0, 31, 1)? << 20,
+        Format::Store => {
+            let imm = check_range(imm, -2048, 2047, 1)?;
+            bits | rs1 | rs2 | (imm & 0x1f) << 7 | (imm >> 5) << 25
+        }
+        Format::Branch => {
+            let imm = check_range(imm, -4096, 4094, 2)?;
+            bits | rs1
+                | rs2
+                | ((imm >> 12) & 1) << 31
+                | ((imm >> 5) & 0x3f) << 25
+                | ((imm >> 1) & 0xf) << 8
+                | ((imm >> 11) & 1) << 7
+        }
+        Format::U => bits | rd | check_range(imm, 0, 0xfffff, 1)? << 12,
+        Format::J => {
+            let imm = check_range(imm, -(1 << 20), (1 << 20) - 2, 2)?;
+            bits | rd
+                | ((imm >> 20) & 1) << 31
+                | ((imm >> 1) & 0x3ff) << 21
+                | ((imm >> 11) & 1) << 20
+                | ((imm >> 12) & 0xff) << 12
+        }
+        Format::Csr => bits | rd | rs1 | csr,
+        Format::CsrI => bits | rd | check_range(imm, 0, 31, 1)? << 15 | csr,
+        Format::Amo => bits | operands.aqrl << 25 | rd | rs1 | rs2,
+        Format::Lr => bits | operands.aqrl << 25 | rd | rs1,
+        Format::Fence => bits | check_range(imm, 0, 0xff, 1)? << 20,
+        Format::Vma => bits | rs1 | rs2,
+        Format::None => bits,
+    };
+    Ok(inst)
+}
+
+/// Decode the operands of `inst` of `format`.
+fn decode(format: Format, inst: u32) -> Operands {
+    // The register fields are 5 bits, so they always name a register.
+    let reg = |shift: u32| REGS[((inst >> shift) & 0x1f) as usize];
+    let imm = match format {
+        Format::I | Format::Load => (inst as i32) >> 20,
+        Format::Shift => ((inst >> 20) & 0x1f) as i32,
+        Format::CsrI => ((inst >> 15) & 0x1f) as i32,
+        Format::Store => ((inst as i32) >> 25) << 5 | ((inst >> 7) & 0x1f) as i32,
+        Format::Branch => {
+            ((inst as i32) >> 31) << 12
+                | (((inst >> 7) & 1) << 11) as i32
+                | (((inst >> 25) & 0x3f) << 5) as i32
+                | (((inst >> 8) & 0xf) << 1) as i32
+        }
+        Format::U => (inst >> 12) as i32,
+        Format::J => {
+            ((inst as i32) >> 31) << 20
+                | (inst & 0xff000) as i32
+                | (((inst >> 20) & 1) << 11) as i32
+                | (((inst >> 21) & 0x3ff) << 1) as i32
+        }
+        Format::Fence => ((inst >> 20) & 0xff) as i32,
+        _ => 0,
+    };
+    Operands {
+        rd: reg(7),
+        rs1: reg(15),
+        rs2: reg(20),
+        csr: (inst >> 20) as CsrAddress,
+        imm,
+        aqrl: (inst >> 25) & 0b11,
+    }
+}
+
+/// Encode an instruction with the operands of its encoder, which panics on an invalid operand.
+fn encode_operands(format: Format, mnemonic: &str, bits: u32, operands: Operands) -> u32 {
+    encode(format, bits, &operands).unwrap_or_else(|e| panic!("{}: {}", mnemonic, e))
+}
+
+/// Define the table of the instructions, and an encoder for each of them.
+macro_rules! instructions {
+    ($($format:ident { $($name:ident $mnemonic:literal $bits:literal,)* })*) => {
+        /// The mnemonic, the format, and the fixed bits of each instruction.
+        const INSTRUCTIONS: &[(&str, Format, u32)] = &[
+            $($(($mnemonic, Format::$format, $bits),)*)*
+        ];
+
+        $($(encoder!($format, $name, $mnemonic, $bits);)*)*
+    };
+}
+
+/// Define the encoder of an instruction of a format.
+macro_rules! encoder {
+    (R, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " rd, rs1, rs2`.")]
+        pub fn $name(rd: Reg, rs1: Reg, rs2: Reg) -> u32 {
+            let operands = Operands {
+                rd,
+                rs1,
+                rs2,
+                ..Operands::default()
+            };
+            encode_operands(Format::R, $mnemonic, $bits, operands)
+        }
+    };
+    (I, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " rd, rs1, imm`.")]
+        pub fn $name(rd: Reg, rs1: Reg, imm: i32) -> u32 {
+            let operands = Operands {
+                rd,
+                rs1,
+                imm,
+                ..Operands::default()
+            };
+            encode_operands(Format::I, $mnemonic, $bits, operands)
+        }
+    };
+    (Shift, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " rd, rs1, shamt`.")]
+        pub fn $name(rd: Reg, rs1: Reg, shamt: u32) -> u32 {
+            let imm = shamt.min(i32::MAX as u32) as i32;
+            let operands = Operands {
+                rd,
+                rs1,
+                imm,
+                ..Operands::default()
+            };
+            encode_operands(Format::Shift, $mnemonic, $bits, operands)
+        }
+    };
+    (Load, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " rd, offset(rs1)`.")]
+        pub fn $name(rd: Reg, rs1: Reg, offset: i32) -> u32 {
+            let operands = Operands {
+                rd,
+                rs1,
+                imm: offset,
+                ..Operands::default()
+            };
+            encode_operands(Format::Load, $mnemonic, $bits, operands)
+        }
+    };
+    (Store, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " rs2, offset(rs1)`.")]
+        pub fn $name(rs2: Reg, rs1: Reg, offset: i32) -> u32 {
+            let operands = Operands {
+                rs1,
+                rs2,
+                imm: offset,
+                ..Operands::default()
+            };
+            encode_operands(Format::Store, $mnemonic, $bits, operands)
+        }
+    };
+    (Branch, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " rs1, rs2, offset`.")]
+        pub fn $name(rs1: Reg, rs2: Reg, offset: i32) -> u32 {
+            let operands = Operands {
+                rs1,
+                rs2,
+                imm: offset,
+                ..Operands::default()
+            };
+            encode_operands(Format::Branch, $mnemonic, $bits, operands)
+        }
+    };
+    (U, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " rd, imm`, where `imm` is the upper 20 bits.")]
+        pub fn $name(rd: Reg, imm: u32) -> u32 {
+            let imm = imm.min(i32::MAX as u32) as i32;
+            let operands = Operands {
+                rd,
+                imm,
+                ..Operands::default()
+            };
+            encode_operands(Format::U, $mnemonic, $bits, operands)
+        }
+    };
+    (J, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " rd, offset`.")]
+        pub fn $name(rd: Reg, offset: i32) -> u32 {
+            let operands = Operands {
+                rd,
+                imm: offset,
+                ..Operands::default()
+            };
+            encode_operands(Format::J, $mnemonic, $bits, operands)
+        }
+    };
+    (Csr, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " rd, csr, rs1`.")]
+        pub fn $name(rd: Reg, csr: CsrAddress, rs1: Reg) -> u32 {
+            assert!(csr <= 0xfff, "{}: invalid CSR {:#x}", $mnemonic, csr);
+            let operands = Operands {
+                rd,
+                rs1,
+                csr,
+                ..Operands::default()
+            };
+            encode_operands(Format::Csr, $mnemonic, $bits, operands)
+        }
+    };
+    (CsrI, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " rd, csr, zimm`.")]
+        pub fn $name(rd: Reg, csr: CsrAddress, zimm: u32) -> u32 {
+            assert!(csr <= 0xfff, "{}: invalid CSR {:#x}", $mnemonic, csr);
+            let imm = zimm.min(i32::MAX as u32) as i32;
+            let operands = Operands {
+                rd,
+                csr,
+                imm,
+                ..Operands::default()
+            };
+            encode_operands(Format::CsrI, $mnemonic, $bits, operands)
+        }
+    };
+    (Amo, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " rd, rs2, (rs1)`.")]
+        pub fn $name(rd: Reg, rs2: Reg, rs1: Reg) -> u32 {
+            let operands = Operands {
+                rd,
+                rs1,
+                rs2,
+                ..Operands::default()
+            };
+            encode_operands(Format::Amo, $mnemonic, $bits, operands)
+        }
+    };
+    (Lr, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " rd, (rs1)`.")]
+        pub fn $name(rd: Reg, rs1: Reg) -> u32 {
+            let operands = Operands {
+                rd,
+                rs1,
+                ..Operands::default()
+            };
+            encode_operands(Format::Lr, $mnemonic, $bits, operands)
+        }
+    };
+    (Fence, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " pred, succ`, where the bits 3 to 0 of the ")]
+        #[doc = "sets are `i`, `o`, `r`, and `w`."]
+        pub fn $name(pred: u32, succ: u32) -> u32 {
+            assert!(pred <= 0xf && succ <= 0xf, "{}: invalid set", $mnemonic);
+            let operands = Operands {
+                imm: (pred << 4 | succ) as i32,
+                ..Operands::default()
+            };
+            encode_operands(Format::Fence, $mnemonic, $bits, operands)
+        }
+    };
+    (Vma, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, " rs1, rs2`.")]
+        pub fn $name(rs1: Reg, rs2: Reg) -> u32 {
+            let operands = Operands {
+                rs1,
+                rs2,
+                ..Operands::default()
+            };
+            encode_operands(Format::Vma, $mnemonic, $bits, operands)
+        }
+    };
+    (None, $name:ident, $mnemonic:literal, $bits:literal) => {
+        #[doc = concat!("Encode `", $mnemonic, "`.")]
+        pub fn $name() -> u32 {
+            $bits
+        }
+    };
+}
+
+instructions! {
+    U {
+        lui "lui" 0x00000037,
+        auipc "auipc" 0x00000017,
+    }
+    J {
+        jal "jal" 0x0000006f,
+    }
+    Load {
+        jalr "jalr" 0x00000067,
+        lb "lb" 0x00000003,
+        lh "lh" 0x00001003,
+        lw "lw" 0x00002003,
+        lbu "lbu" 0x00004003,
+        lhu "lhu" 0x00005003,
+    }
+    Branch {
+        beq "beq" 0x00000063,
+        bne "bne" 0x00001063,
+        blt "blt" 0x00004063,
+        bge "bge" 0x00005063,
+        bltu "bltu" 0x00006063,
+        bgeu "bgeu" 0x00007063,
+    }
+    Store {
+        sb "sb" 0x00000023,
+        sh "sh" 0x00001023,
+        sw "sw" 0x00002023,
+    }
+    I {
+        addi "addi" 0x00000013,
+        slti "slti" 0x00002013,
+        sltiu "sltiu" 0x00003013,
+        xori "xori" 0x00004013,
+        ori "ori" 0x00006013,
+        andi "andi" 0x00007013,
+    }
+    Shift {
+        slli "slli" 0x00001013,
+        srli "srli" 0x00005013,
+        srai "srai" 0x40005013,
+    }
+    R {
+        add "add" 0x00000033,
+        sub "sub" 0x40000033,
+        sll "sll" 0x00001033,
+        slt "slt" 0x00002033,
+        sltu "sltu" 0x00003033,
+        xor "xor" 0x00004033,
+        srl "srl" 0x00005033,
+        sra "sra" 0x40005033,
+        or "or" 0x00006033,
+        and "and" 0x00007033,
+        mul "mul" 0x02000033,
+        mulh "mulh" 0x02001033,
+        mulhsu "mulhsu" 0x02002033,
+        mulhu "mulhu" 0x02003033,
+        div "div" 0x02004033,
+        divu "divu" 0x02005033,
+        rem "rem" 0x02006033,
+        remu "remu" 0x02007033,
+    }
+    Fence {
+        fence "fence" 0x0000000f,
+    }
+    None {
+        fence_i "fence.i" 0x0000100f,
+        ecall "ecall" 0x00000073,
+        ebreak "ebreak" 0x00100073,
+        uret "uret" 0x00200073,
+        mret "mret" 0x30200073,
+        dret "dret" 0x7b200073,
+        wfi "wfi" 0x10500073,
+    }
+    Vma {
+        sfence_vma "sfence.vma" 0x12000073,
+    }
+    Csr {
+        csrrw "csrrw" 0x00001073,
+        csrrs "csrrs" 0x00002073,
+        csrrc "csrrc" 0x00003073,
+    }
+    CsrI {
+        csrrwi "csrrwi" 0x00005073,
+        csrrsi "csrrsi" 0x00006073,
+        csrrci "csrrci" 0x00007073,
+    }
+    Lr {
+        lr_w "lr.w" 0x1000202f,
+    }
+    Amo {
+        sc_w "sc.w" 0x1800202f,
+        amoswap_w "amoswap.w" 0x0800202f,
+        amoadd_w "amoadd.w" 0x0000202f,
+        amoxor_w "amoxor.w" 0x2000202f,
+        amoand_w "amoand.w" 0x6000202f,
+        amoor_w "amoor.w" 0x4000202f,
+        amomin_w "amomin.w" 0x8000202f,
+        amomax_w "amomax.w" 0xa000202f,
+        amominu_w "amominu.w" 0xc000202f,
+        amomaxu_w "amomaxu.w" 0xe000202f,
+    }
+}
+
+/// Write the set of accesses of a fence as `i`, `o`, `r`, and `w`, or `0` for none.
+fn fence_set(set: i32) -> String {
+    let set: String = "iorw"
+        .chars()
+        .enumerate()
+        .filter(|(i, _)| set & (8 >> i) != 0)
+        .map(|(_, access)| access)
+        .collect();
+    if set.is_empty() {
+        "0".to_string()
+    } else {
+        set
+    }
+}
+
+/// Write the name of a CSR, or its address if it has no name.
+fn csr_name(addr: CsrAddress) -> String {
+    match CSR_NAMES.iter().find(|(_, csr)| *csr == addr) {
+        Some((name, _)) => name.to_string(),
+        None => format!("{:#x}", addr),
+    }
+}
+
+/// Disassemble `inst`. Returns `None` if it isn't an instruction the assembler writes, such as an
+/// unknown opcode or a fence with bits outside `pred` and `succ`. The aq and rl bits of an atomic
+/// instruction are written as a `.aq`, `.rl`, or `.aqrl` suffix of its mnemonic.
+pub fn disassemble(inst: u32) -> Option<String> {
+    let (mnemonic, format, bits) = INSTRUCTIONS
+        .iter()
+        .find(|(_, format, bits)| inst & format.mask() == *bits)?;
+    let o = decode(*format, inst);
+    // Reject the instructions with bits the operands don't cover.
+    if encode(*format, *bits, &o).ok()? != inst {
+        return None;
+    }
+    let operands = match format {
+        Format::R => format!("{}, {}, {}", o.rd, o.rs1, o.rs2),
+        Format::I | Format::Shift => format!("{}, {}, {}", o.rd, o.rs1, o.imm),
+        Format::Load => format!("{}, {}({})", o.rd, o.imm, o.rs1),
+        Format::Store => format!("{}, {}({})", o.rs2, o.imm, o.rs1),
+        Format::Branch => format!("{}, {}, {}", o.rs1, o.rs2, o.imm),
+        Format::U => format!("{}, {:#x}", o.rd, o.imm),
+        Format::J => format!("{}, {}", o.rd, o.imm),
+        Format::Csr => format!("{}, {}, {}", o.rd, csr_name(o.csr), o.rs1),
+        Format::CsrI => format!("{}, {}, {}", o.rd, csr_name(o.csr), o.imm),
+        Format::Amo => format!("{}, {}, ({})", o.rd, o.rs2, o.rs1),
+        Format::Lr => format!("{}, ({})", o.rd, o.rs1),
+        Format::Fence => format!("{}, {}", fence_set(o.imm >> 4), fence_set(o.imm & 0xf)),
+        Format::Vma => format!("{}, {}", o.rs1, o.rs2),
+        Format::None => return Some(mnemonic.to_string()),
+    };
+    let ordering = match format {
+        Format::Amo | Format::Lr => ORDERINGS
+            .iter()
+            .find(|(_, aqrl)| *aqrl == o.aqrl)
+            .map_or("", |(suffix, _)| suffix),
+        _ => "",
+    };
+    Some(format!("{}{} {}", mnemonic, ordering, operands))
+}
+
+/// An error in the source of [`assemble`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    /// The line of the error, starting at 1.
+    pub line: usize,
+    /// What is wrong.
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// A statement of the source: an instruction, a pseudo-instruction, or a directive.
+struct Statement<'a> {
+    /// The line of the statement, starting at 1.
+    line: usize,
+    /// The offset of the statement from the start of the program.
+    offset: u32,
+    mnemonic: &'a str,
+    operands: Vec<&'a str>,
+}
+
+/// Parse a decimal, negative decimal, or `0x`-prefixed hexadecimal number.
+fn parse_number(s: &str) -> Result<i64, String> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    };
+    match value {
+        Ok(value)
+            if digits.starts_with(|c: char| c.is_ascii_digit()) && value <= u32::MAX as i64 =>
+        {
+            Ok(if negative { -value } else { value })
+        }
+        _ => Err(format!("invalid number `{}`", s)),
+    }
+}
+
+/// Parse a number that fits in an `i32` as it is, or as the bits of a `u32`.
+fn parse_imm(s: &str) -> Result<i32, String> {
+    let value = parse_number(s)?;
+    if value < i32::MIN as i64 {
+        return Err(format!("invalid number `{}`", s));
+    }
+    Ok(value as u32 as i32)
+}
+
+fn parse_reg(s: &str) -> Result<Reg, String> {
+    Reg::parse(s).ok_or(format!("invalid register `{}`", s))
+}
+
+fn parse_csr(s: &str) -> Result<CsrAddress, String> {
+    match csr_address(s) {
+        Some(addr) => Ok(addr),
+        None => match parse_number(s) {
+            Ok(addr @ 0..=0xfff) => Ok(addr as CsrAddress),
+            _ => Err(format!("invalid CSR `{}`", s)),
+        },
+    }
+}
+
+/// Parse `offset(rs1)`, where the offset can be omitted.
+fn parse_address(s: &str) -> Result<(i32, Reg), String> {
+    let (offset, rest) = s
+        .split_once('(')
+        .ok_or(format!("expected `offset(register)`, found `{}`", s))?;
+    let reg = rest
+        .strip_suffix(')')
+        .ok_or(format!("expected `offset(register)`, found `{}`", s))?;
+    let offset = match offset.trim() {
+        "" => 0,
+        offset => parse_imm(offset)?,
+    };
+    Ok((offset, parse_reg(reg.trim())?))
+}
+
+/// Parse `(rs1)`.
+fn parse_base(s: &str) -> Result<Reg, String> {
+    match parse_address(s)? {
+        (0, reg) if s.starts_with('(') => Ok(reg),
+        _ => Err(format!("expected `(register)`, found `{}`", s)),
+    }
+}
+
+/// Parse the set of accesses of a fence.
+fn parse_fence_set(s: &str) -> Result<i32, String> {
+    if s == "0" {
+        return Ok(0);
+    }
+    let mut set = 0;
+    for access in s.chars() {
+        let bit = match access {
+            'i' => 8,
+            'o' => 4,
+            'r' => 2,
+            'w' => 1,
+            _ => return Err(format!("invalid fence set `{}`", s)),
+        };
+        set |= bit;
+    }
+    Ok(set)
+}
+
+/// The pseudo-instructions the assembler expands.
+const PSEUDO_INSTRUCTIONS: &[&str] = &[
+    "nop", "li", "la", "mv", "not", "neg", "j", "jr", "ret", "call", "tail", "beqz", "bnez",
+    "csrr", "csrw",
+];
+
+/// Return the number of instructions `li rd, imm` expands to.
+fn li_size(imm: i32) -> u32 {
+    if (-2048..=2047).contains(&imm) {
+        1
+    } else {
+        2
+    }
+}
+
+/// Split `imm` into the upper 20 bits for `lui` or `auipc`, and the lower 12 bits for the
+/// instruction after it. That instruction sign-extends the lower bits, so the upper bits are
+/// rounded up for them.
+fn split_imm(imm: i32) -> (u32, i32) {
+    let upper = (imm as u32).wrapping_add(0x800) >> 12;
+    (upper, imm.wrapping_sub((upper << 12) as i32))
+}
+
+impl<'a> Statement<'a> {
+    /// Return the size of the statement in bytes.
+    fn size(&self) -> Result<u32, String> {
+        match (self.mnemonic, &self.operands[..]) {
+            (".word", words) => Ok(4 * words.len() as u32),
+            ("li", [_, imm]) => Ok(4 * li_size(parse_imm(imm)?)),
+            ("la", [_, _]) | ("call", [_]) | ("tail", [_]) => Ok(8),
+            _ => Ok(4),
+        }
+    }
+
+    /// Return the offset of the branch or jump target `s`, a label or a number.
+    fn target(&self, s: &str, labels: &BTreeMap<&str, u32>) -> Result<i32, String> {
+        if s.starts_with(|c: char| c == '-' || c.is_ascii_digit()) {
+            return parse_imm(s);
+        }
+        match labels.get(s) {
+            Some(addr) => Ok(addr.wrapping_sub(self.offset) as i32),
+            None => Err(format!("unknown label `{}`", s)),
+        }
+    }
+
+    /// Expand a pseudo-instruction to the instructions it stands for, with their operands. Other
+    /// statements are returned as they are.
+    fn expand(&self, labels: &BTreeMap<&str, u32>) -> Result<Vec<(&'a str, Vec<String>)>, String> {
+        let operands: Vec<String> = self.operands.iter().map(|s| s.to_string()).collect();
+        let op = |i: usize| operands[i].clone();
+        let expansion = match (self.mnemonic, operands.len()) {
+            ("nop", 0) => vec![("addi", vec!["zero".into(), "zero".into(), "0".into()])],
+            ("li", 2) => {
+                let imm = parse_imm(&op(1))?;
+                if li_size(imm) == 1 {
+                    vec![("addi", vec![op(0), "zero".into(), imm.to_string()])]
+                } else {
+                    let (upper, lower) = split_imm(imm);
+                    vec![
+                        ("lui", vec![op(0), upper.to_string()]),
+                        ("addi", vec![op(0), op(0), lower.to_string()]),
+                    ]
+                }
+            }
+            // The target is relative to the auipc.
+            ("la", 2) => {
+                let (upper, lower) = split_imm(self.target(&op(1), labels)?);
+                vec![
+                    ("auipc", vec![op(0), upper.to_string()]),
+                    ("addi", vec![op(0), op(0), lower.to_string()]),
+                ]
+            }
+            ("call", 1) => {
+                let (upper, lower) = split_imm(self.target(&op(0), labels)?);
+                vec![
+                    ("auipc", vec!["ra".into(), upper.to_string()]),
+                    ("jalr", vec!["ra".into(), format!("{}(ra)", lower)]),
+                ]
+            }
+            ("tail", 1) => {
+                let (upper, lower) = split_imm(self.target(&op(0), labels)?);
+                vec![
+                    ("auipc", vec!["t1".into(), upper.to_string()]),
+                    ("jalr", vec!["zero".into(), format!("{}(t1)", lower)]),
+                ]
+            }
+            ("mv", 2) => vec![("addi", vec![op(0), op(1), "0".into()])],
+            ("not", 2) => vec![("xori", vec![op(0), op(1), "-1".into()])],
+            ("neg", 2) => vec![("sub", vec![op(0), "zero".into(), op(1)])],
+            ("j", 1) => vec![("jal", vec!["zero".into(), op(0)])],
+            ("jal", 1) => vec![("jal", vec!["ra".into(), op(0)])],
+            ("jr", 1) => vec![("jalr", vec!["zero".into(), format!("0({})", op(0))])],
+            ("ret", 0) => vec![("jalr", vec!["zero".into(), "0(ra)".into()])],
+            ("beqz", 2) => vec![("beq", vec![op(0), "zero".into(), op(1)])],
+            ("bnez", 2) => vec![("bne", vec![op(0), "zero".into(), op(1)])],
+            ("csrr", 2) => vec![("csrrs", vec![op(0), op(1), "zero".into()])],
+            ("csrw", 2) => vec![("csrrw", vec!["zero".into(), op(0), op(1)])],
+            (mnemonic, _) if PSEUDO_INSTRUCTIONS.contains(&mnemonic) => {
+                return Err(format!(
+                    "invalid operands for `{}`: `{}`",
+                    mnemonic,
+                    self.operands.join(", ")
+                ))
+            }
+            _ => vec![(self.mnemonic, operands)],
+        };
+        Ok(expansion)
+    }
+
+    /// Encode the statement.
+    fn encode(&self, labels: &BTreeMap<&str, u32>) -> Result<Vec<u32>, String> {
+        if self.mnemonic == ".word" {
+            return self
+                .operands
+                .iter()
+                .map(|word| parse_imm(word).map(|word| word as u32))
+                .collect();
+        }
+        let mut words = Vec::new();
+        for (i, (mnemonic, operands)) in self.expand(labels)?.into_iter().enumerate() {
+            let statement = Statement {
+                line: self.line,
+                offset: self.offset + 4 * i as u32,
+                mnemonic,
+                operands: operands.iter().map(|s| s.as_str()).collect(),
+            };
+            words.push(statement.encode_instruction(labels)?);
+        }
+        Ok(words)
+    }
+
+    /// Encode the statement as an instruction.
+    fn encode_instruction(&self, labels: &BTreeMap<&str, u32>) -> Result<u32, String> {
+        // Only the atomic instructions take an ordering suffix.
+        let (mnemonic, aqrl) = split_ordering(self.mnemonic);
+        let (_, format, bits) = INSTRUCTIONS
+            .iter()
+            .find(|(name, format, _)| {
+                *name == mnemonic && (aqrl == 0 || matches!(format, Format::Amo | Format::Lr))
+            })
+            .ok_or(format!("unknown instruction `{}`", self.mnemonic))?;
+        let mut o = Operands {
+            aqrl,
+            ..Operands::default()
+        };
+        match (format, &self.operands[..]) {
+            (Format::R, [rd, rs1, rs2]) => {
+                (o.rd, o.rs1, o.rs2) = (parse_reg(rd)?, parse_reg(rs1)?, parse_reg(rs2)?);
+            }
+            (Format::I | Format::Shift, [rd, rs1, imm]) => {
+                (o.rd, o.rs1, o.imm) = (parse_reg(rd)?, parse_reg(rs1)?, parse_imm(imm)?);
+            }
+            (Format::Load, [rd, address]) => {
+                o.rd = parse_reg(rd)?;
+                (o.imm, o.rs1) = parse_address(address)?;
+            }
+            (Format::Store, [rs2, address]) => {
+                o.rs2 = parse_reg(rs2)?;
+                (o.imm, o.rs1) = parse_address(address)?;
+            }
+            (Format::Branch, [rs1, rs2, target]) => {
+                (o.rs1, o.rs2) = (parse_reg(rs1)?, parse_reg(rs2)?);
+                o.imm = self.target(target, labels)?;
+            }
+            (Format::U, [rd, imm]) => (o.rd, o.imm) = (parse_reg(rd)?, parse_imm(imm)?),
+            (Format::J, [rd, target]) => {
+                (o.rd, o.imm) = (parse_reg(rd)?, self.target(target, labels)?);
+            }
+            (Format::Csr, [rd, csr, rs1]) => {
+                (o.rd, o.csr, o.rs1) = (parse_reg(rd)?, parse_csr(csr)?, parse_reg(rs1)?);
+            }
+            (Format::CsrI, [rd, csr, zimm]) => {
+                (o.rd, o.csr, o.imm) = (parse_reg(rd)?, parse_csr(csr)?, parse_imm(zimm)?);
+            }
+            (Format::Amo, [rd, rs2, base]) => {
+                (o.rd, o.rs2, o.rs1) = (parse_reg(rd)?, parse_reg(rs2)?, parse_base(base)?);
+            }
+            (Format::Lr, [rd, base]) => (o.rd, o.rs1) = (parse_reg(rd)?, parse_base(base)?),
+            (Format::Fence, []) => o.imm = 0xff,
+            (Format::Fence, [pred, succ]) => {
+                o.imm = parse_fence_set(pred)? << 4 | parse_fence_set(succ)?;
+            }
+            (Format::Vma, []) => {}
+            (Format::Vma, [rs1, rs2]) => (o.rs1, o.rs2) = (parse_reg(rs1)?, parse_reg(rs2)?),
+            (Format::None, []) => {}
+            _ => {
+                return Err(format!(
+                    "invalid operands for `{}`: `{}`",
+                    self.mnemonic,
+                    self.operands.join(", ")
+                ))
+            }
+        }
+        encode(*format, *bits, &o)
+    }
+}
+
+/// Split the operands of a statement at the commas.
+fn split_operands(operands: &str) -> Vec<&str> {
+    if operands.trim().is_empty() {
+        return Vec::new();
+    }
+    operands.split(',').map(|operand| operand.trim()).collect()
+}
+
+/// Return true if `s` can be the name of a label.
+fn is_label(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Assemble `source` into the little-endian bytes of the program. A label is the offset of the
+/// statement it names from the start of the program. See the [module](self) for the syntax.
+pub fn assemble(source: &str) -> Result<Vec<u8>, Error> {
+    let mut labels = BTreeMap::new();
+    let mut statements = Vec::new();
+    let mut offset = 0u32;
+    for (number, line) in source.lines().enumerate() {
+        let error = |message: String| Error {
+            line: number + 1,
+            message,
+        };
+        let mut rest = line.split('#').next().unwrap().trim();
+        while let Some((label, statement)) = rest.split_once(':') {
+            let label = label.trim();
+            if !is_label(label) {
+                return Err(error(format!("invalid label `{}`", label)));
+            }
+            if labels.insert(label, offset).is_some() {
+                return Err(error(format!("duplicate label `{}`", label)));
+            }
+            rest = statement.trim();
+        }
+        if rest.is_empty() {
+            continue;
+        }
+        let (mnemonic, operands) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let operands = match mnemonic {
+            // The words of the directive can also be separated by spaces.
+            ".word" => operands
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|word| !word.is_empty())
+                .collect(),
+            _ => split_operands(operands),
+        };
+        let statement = Statement {
+            line: number + 1,
+            offset,
+            mnemonic,
+            operands,
+        };
+        offset += statement.size().map_err(error)?;
+        statements.push(statement);
+    }
+
+    let mut bytes = Vec::with_capacity(offset as usize);
+    for statement in statements.iter() {
+        let words = statement.encode(&labels).map_err(|message| Error {
+            line: statement.line,
+            message,
+        })?;
+        for word in words {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,44 @@
+//! Print the coverage report of the coverage files that `riscv-run --coverage` or the test suite
+//! wrote.
+//!
+//! The hits of all the files add up, and the report lists the instructions, the illegal-encoding
+//! checks, and the trap causes that no run exercised.
+
+use std::process;
+
+use riscv::coverage::Coverage;
+
+const USAGE: &str = "\
+usage: riscv-coverage <file>...
+
+Run the test suite with RISCV_COVERAGE=<file> to record its coverage.";
+
+/// The exit code for invalid arguments or an invalid coverage file.
+const EXIT_USAGE: i32 = 2;
+
+/// Merge the coverage files at `paths`.
+fn merge(paths: &[String]) -> Result<Coverage, String> {
+    let mut coverage = Coverage::new();
+    for path in paths {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        let hits = Coverage::decode(&text).ok_or(format!("invalid coverage file {}", path))?;
+        coverage.merge(&hits);
+    }
+    Ok(coverage)
+}
+
+fn main() {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() || paths.iter().any(|arg| arg == "-h" || arg == "--help") {
+        eprintln!("{}", USAGE);
+        process::exit(EXIT_USAGE);
+    }
+    match merge(&paths) {
+        Ok(coverage) => print!("{}", coverage),
+        Err(e) => {
+            eprintln!("riscv-coverage: {}", e);
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
@@ -0,0 +1,403 @@
+//! Run a guest program on the emulator from the command line.
+//!
+//! The program is an RV32 ELF file, or a raw image loaded at the start of DRAM. Output the guest
+//! writes to the UART or through semihosting is printed to stdout. When the guest exits, the
+//! registers are dumped to stderr and the guest's exit code becomes the exit code of this process.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+use elf::abi::{EM_RISCV, PT_LOAD};
+use elf::endian::LittleEndian;
+use elf::file::Class;
+use elf::ElfBytes;
+use riscv::bus::{self, DRAM_BASE};
+use riscv::cpu::{BYTE, WORD};
+use riscv::dram::DRAM_SIZE;
+use riscv::emulator::Emulator;
+use riscv::replay::Log;
+use riscv::semihosting::StdHost;
+use riscv::uart::Uart;
+
+const USAGE: &str = "\
+usage: riscv-run [options] <program>
+
+options:
+  --raw               load the program as a raw image instead of an ELF file
+  --base <addr>       the address which DRAM starts (default: 0x10000)
+  --mem-size <size>   the size of DRAM, with an optional K or M suffix (default: 32K)
+  --entry <addr>      the entry point of a raw image (default: the start of DRAM)
+  --harts <n>         the number of harts (default: 1)
+  --max-insts <n>     stop after <n> steps
+  --trace <file>      write the committed instructions and register writes to <file>
+  --record <file>     record the inputs of the guest to <file> to replay the run later
+  --replay <file>     replay the inputs of the guest recorded in <file>
+  --coverage <file>   append the decoder arms and the trap causes the guest hit to <file>
+  -h, --help          print this message";
+
+/// The exit code when the guest doesn't exit within the instruction limit.
+const EXIT_TIMEOUT: i32 = 124;
+/// The exit code when the guest raises a fatal exception, or waits for an interrupt forever.
+const EXIT_FATAL: i32 = 125;
+/// The exit code for invalid arguments or an invalid program.
+const EXIT_USAGE: i32 = 2;
+
+/// The command-line options.
+struct Options {
+    program: String,
+    raw: bool,
+    base: u32,
+    mem_size: u32,
+    entry: Option<u32>,
+    harts: usize,
+    max_insts: Option<u64>,
+    trace: Option<String>,
+    record: Option<String>,
+    replay: Option<String>,
+    coverage: Option<String>,
+}
+
+impl Options {
+    /// Parse the command-line arguments.
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            program: String::new(),
+            raw: false,
+            base: DRAM_BASE,
+            mem_size: DRAM_SIZE,
+            entry: None,
+            harts: 1,
+            max_insts: None,
+            trace: None,
+            record: None,
+            replay: None,
+            coverage: None,
+        };
+        let mut program = None;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for `{}`", arg));
+            match arg.as_str() {
+                "--raw" => options.raw = true,
+                "--base" => options.base = parse_address(&value()?)?,
+                "--mem-size" => options.mem_size = parse_number(&value()?)? as u32,
+                "--entry" => options.entry = Some(parse_address(&value()?)?),
+                "--harts" => options.harts = parse_number(&value()?)? as usize,
+                "--max-insts" => options.max_insts = Some(parse_number(&value()?)?),
+                "--trace" => options.trace = Some(value()?),
+                "--record" => options.record = Some(value()?),
+                "--replay" => options.replay = Some(value()?),
+                "--coverage" => options.coverage = Some(value()?),
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+                _ if program.is_some() => return Err(format!("unexpected argument `{}`", arg)),
+                _ => program = Some(arg),
+            }
+        }
+        options.program = program.ok_or("missing program")?;
+        if options.record.is_some() && options.replay.is_some() {
+            return Err("`--record` and `--replay` can't be combined".into());
+        }
+        if options.harts == 0 {
+            return Err("at least one hart is needed".into());
+        }
+        if options.mem_size == 0 || options.base.checked_add(options.mem_size - 1).is_none() {
+            return Err("DRAM doesn't fit in the address space".into());
+        }
+        if let Some((device, window)) = bus::overlapping_device(options.base, options.mem_size) {
+            return Err(format!(
+                "DRAM overlaps the {} at {:#x}..={:#x}",
+                device,
+                window.start(),
+                window.end()
+            ));
+        }
+        Ok(options)
+    }
+}
+
+/// Parse a decimal or `0x`-prefixed hexadecimal number with an optional `K` or `M` suffix.
+fn parse_number(s: &str) -> Result<u64, String> {
+    let (digits, scale) = match s.as_bytes().last() {
+        Some(b'k' | b'K') => (&s[..s.len() - 1], 1 << 10),
+        Some(b'm' | b'M') => (&s[..s.len() - 1], 1 << 20),
+        _ => (s, 1),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    };
+    value
+        .ok()
+        .and_then(|value| value.checked_mul(scale))
+        .filter(|&value| value <= u32::MAX as u64 + 1)
+        .ok_or(format!("invalid number `{}`", s))
+}
+
+/// Parse an address, which is a number that fits in 32 bits.
+fn parse_address(s: &str) -> Result<u32, String> {
+    u32::try_from(parse_number(s)?).map_err(|_| format!("invalid address `{}`", s))
+}
+
+/// Load the program into the DRAM and set the program counter to its entry point.
+fn load(emu: &mut Emulator, options: &Options) -> Result<(), String> {
+    let data = std::fs::read(&options.program)
+        .map_err(|e| format!("failed to read {}: {}", options.program, e))?;
+
+    if options.raw {
+        if data.len() as u64 > emu.bus.dram_size() as u64 {
+            return Err("the image is larger than DRAM".into());
+        }
+        emu.initialize_dram(data);
+        emu.initialize_pc(options.entry.unwrap_or(options.base));
+        return Ok(());
+    }
+
+    let file = ElfBytes::<LittleEndian>::minimal_parse(&data)
+        .map_err(|e| format!("invalid ELF file: {}", e))?;
+    if file.ehdr.class != Class::ELF32 || file.ehdr.e_machine != EM_RISCV {
+        return Err("not an RV32 ELF file".into());
+    }
+    let segments = file.segments().ok_or("no program headers")?;
+    for segment in segments.iter().filter(|segment| segment.p_type == PT_LOAD) {
+        let bytes = file
+            .segment_data(&segment)
+            .map_err(|e| format!("invalid segment: {}", e))?;
+        let start = segment.p_paddr as u32;
+        let size = segment.p_memsz as u32;
+        // The DRAM is contiguous, so the segment is in it if its first and last bytes are.
+        let dram = emu.bus.dram();
+        let in_dram = size == 0
+            || dram.contains(start)
+                && start
+                    .checked_add(size - 1)
+                    .is_some_and(|end| dram.contains(end));
+        if !in_dram {
+            return Err(format!("the segment at {:#x} is outside DRAM", start));
+        }
+        // The part of the segment that isn't in the file is zero-filled.
+        for offset in 0..size {
+            let byte = bytes.get(offset as usize).copied().unwrap_or(0);
+            let addr = start.wrapping_add(offset);
+            emu.bus
+                .write(addr, byte as u32, BYTE)
+                .map_err(|_| format!("the segment at {:#x} is outside DRAM", start))?;
+        }
+    }
+    emu.initialize_pc(options.entry.unwrap_or(file.ehdr.e_entry as u32));
+    Ok(())
+}
+
+/// Return the address of the `tohost` symbol, which riscv-tests programs write their result to.
+fn tohost(options: &Options) -> Option<u32> {
+    if options.raw {
+        return None;
+    }
+    let data = std::fs::read(&options.program).ok()?;
+    let file = ElfBytes::<LittleEndian>::minimal_parse(&data).ok()?;
+    let (symtab, strtab) = file.symbol_table().ok()??;
+    symtab
+        .iter()
+        .find(|symbol| {
+            strtab
+                .get(symbol.st_name as usize)
+                .is_ok_and(|name| name == "tohost")
+        })
+        .map(|symbol| symbol.st_value as u32)
+}
+
+/// The commit trace: one line per committed instruction with the registers it wrote.
+struct Trace {
+    out: BufWriter<File>,
+    /// The program counter and the integer registers of each hart before the step.
+    before: Vec<(u32, Vec<u32>)>,
+}
+
+impl Trace {
+    /// Create the trace file at `path`.
+    fn create(path: &str) -> Result<Trace, String> {
+        let file = File::create(path).map_err(|e| format!("failed to create {}: {}", path, e))?;
+        Ok(Trace {
+            out: BufWriter::new(file),
+            before: Vec::new(),
+        })
+    }
+
+    /// Record the state of the harts before a step.
+    fn begin(&mut self, emu: &Emulator) {
+        self.before = emu
+            .harts
+            .iter()
+            .map(|cpu| {
+                let xregs = (0..cpu.xregs.count() as u32)
+                    .map(|i| cpu.xregs.read(i))
+                    .collect();
+                (cpu.pc, xregs)
+            })
+            .collect();
+    }
+
+    /// Write the instructions the harts committed in the step.
+    fn commit(&mut self, emu: &mut Emulator, running: &[bool]) -> io::Result<()> {
+        for (hart, (pc, xregs)) in self.before.iter().enumerate() {
+            if !running[hart] {
+                continue;
+            }
+            let inst = emu.bus.read(*pc, WORD).unwrap_or(0);
+            write!(self.out, "core {:3}: {:#010x} ({:#010x})", hart, pc, inst)?;
+            let cpu = &emu.harts[hart];
+            for (i, &old) in xregs.iter().enumerate() {
+                let new = cpu.xregs.read(i as u32);
+                if new != old {
+                    write!(self.out, " x{:<2} {:#010x}", i, new)?;
+                }
+            }
+            writeln!(self.out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Print what the guest wrote to the UART.
+fn flush_uart(emu: &mut Emulator) {
+    if let Some(uart) = emu.bus.uart.as_mut() {
+        let output = uart.take_output();
+        if !output.is_empty() {
+            let mut stdout = io::stdout();
+            // The guest can't do anything about a failed write to the console.
+            let _ = stdout.write_all(&output).and_then(|_| stdout.flush());
+        }
+    }
+}
+
+/// Dump the registers of all the harts to stderr.
+fn dump(emu: &Emulator) {
+    for cpu in emu.harts.iter() {
+        eprintln!("hart {}: pc={:#x}", cpu.hartid(), cpu.pc);
+        eprintln!("{}", cpu.xregs);
+        eprintln!("{}", cpu.state);
+    }
+}
+
+/// Run the program and return the exit code.
+fn run(options: &Options) -> Result<i32, String> {
+    let mut emu = Emulator::with_memory(options.harts, options.base, options.mem_size);
+    emu.semihosting = Some(Box::new(StdHost::new()));
+    emu.bus.uart = Some(Uart::new());
+    load(&mut emu, options)?;
+
+    if options.record.is_some() {
+        emu.start_recording();
+    }
+    if let Some(path) = options.replay.as_deref() {
+        let data = std::fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        let log = Log::decode(&data).ok_or(format!("invalid replay log {}", path))?;
+        emu.start_replay(log);
+    }
+    if options.coverage.is_some() {
+        emu.enable_coverage();
+    }
+
+    let tohost = tohost(options);
+    let mut trace = match options.trace.as_deref() {
+        Some(path) => Some(Trace::create(path)?),
+        None => None,
+    };
+
+    let mut steps = 0;
+    let code = loop {
+        if options.max_insts.is_some_and(|max| steps >= max) {
+            eprintln!("riscv-run: stopped after {} steps", steps);
+            break EXIT_TIMEOUT;
+        }
+        steps += 1;
+
+        // Skip the time the harts would spend waiting in WFI.
+        if emu.is_idle() {
+            match emu.bus.cycles_until_event() {
+                Some(cycles) => emu.fast_forward(cycles),
+                None => {
+                    eprintln!("riscv-run: all the harts wait for an interrupt that never comes");
+                    break EXIT_FATAL;
+                }
+            }
+        }
+
+        let running: Vec<bool> = emu
+            .harts
+            .iter()
+            .map(|cpu| !cpu.idle && !cpu.is_halted())
+            .collect();
+        if let Some(trace) = trace.as_mut() {
+            trace.begin(&emu);
+        }
+        let result = emu.step();
+        if let Some(trace) = trace.as_mut() {
+            trace
+                .commit(&mut emu, &running)
+                .map_err(|e| format!("failed to write the trace: {}", e))?;
+        }
+        flush_uart(&mut emu);
+
+        if let Err(exception) = result {
+            eprintln!("riscv-run: fatal exception {:?}", exception);
+            break EXIT_FATAL;
+        }
+        if let Some(code) = emu.exit_code {
+            break code as i32;
+        }
+        // riscv-tests report 1 on success and `(testnum << 1) | 1` on failure.
+        if let Some(value) = tohost.and_then(|addr| emu.bus.read(addr, WORD).ok()) {
+            if value != 0 {
+                break (value >> 1) as i32;
+            }
+        }
+    };
+
+    if let Some(path) = options.record.as_deref() {
+        let log = emu.stop_journal().unwrap_or_default();
+        std::fs::write(path, log.encode())
+            .map_err(|e| format!("failed to write {}: {}", path, e))?;
+    }
+    if let Some(path) = options.coverage.as_deref() {
+        let hits = emu.coverage().unwrap_or_default().encode();
+        // Appending lets the runs of a test suite add up to one coverage file.
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(hits.as_bytes()))
+            .map_err(|e| format!("failed to write {}: {}", path, e))?;
+    }
+    if emu.has_diverged() {
+        eprintln!("riscv-run: the run diverged from the replay log");
+    }
+    if let Some(mut trace) = trace {
+        trace
+            .out
+            .flush()
+            .map_err(|e| format!("failed to write the trace: {}", e))?;
+    }
+    dump(&emu);
+    Ok(code)
+}
+
+fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("riscv-run: {}\n\n{}", e, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    match run(&options) {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("riscv-run: {}", e);
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
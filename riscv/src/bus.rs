@@ -1,28 +1,205 @@
 //! The bus module contains the system bus which can access the memroy or memory-mapped peripheral
 //! devices.
 
-use alloc::vec::Vec;
+use alloc::{boxed::Box, vec::Vec};
+use core::ops::RangeInclusive;
 
+use crate::clint::{Clint, CLINT_BASE, CLINT_SIZE};
+use crate::cpu::{AccessType, WORD};
+use crate::custom::CustomExtension;
 use crate::dram::{Dram, DRAM_SIZE};
 use crate::exception::Exception;
+use crate::framebuffer::{
+    Framebuffer, FRAMEBUFFER_BASE, FRAMEBUFFER_SIZE, FRAMEBUFFER_VRAM, FRAMEBUFFER_VRAM_SIZE,
+};
+use crate::hook::{Access, HookId, MemoryHook};
+use crate::input::{Input, INPUT_BASE, INPUT_SIZE};
+use crate::replay::Journal;
+use crate::uart::{Uart, UART_BASE, UART_SIZE};
 
 // QEMU virt machine:
 // https://github.com/qemu/qemu/blob/master/hw/riscv/virt.c#L46-L63
 
 /// The address which DRAM starts.
 pub const DRAM_BASE: u32 = 0x10000;
-/// The address which DRAM ends.
-const DRAM_END: u32 = DRAM_BASE + DRAM_SIZE;
+/// The address which the CLINT ends.
+const CLINT_END: u32 = CLINT_BASE + CLINT_SIZE - 1;
+/// The address which the framebuffer registers end.
+const FRAMEBUFFER_END: u32 = FRAMEBUFFER_BASE + FRAMEBUFFER_SIZE - 1;
+/// The address which the pixel buffer window ends.
+const FRAMEBUFFER_VRAM_END: u32 = FRAMEBUFFER_VRAM + FRAMEBUFFER_VRAM_SIZE - 1;
+/// The address which the input device ends.
+const INPUT_END: u32 = INPUT_BASE + INPUT_SIZE - 1;
+/// The address which the UART ends.
+const UART_END: u32 = UART_BASE + UART_SIZE - 1;
 
-/// The system bus.
+/// The register windows of the devices, which DRAM must not overlap. The CLINT is decoded before
+/// DRAM, and the other devices after it, so an overlap would hide one of them.
+const DEVICE_WINDOWS: [(&str, RangeInclusive<u32>); 5] = [
+    ("CLINT", CLINT_BASE..=CLINT_END),
+    ("framebuffer", FRAMEBUFFER_BASE..=FRAMEBUFFER_END),
+    ("input device", INPUT_BASE..=INPUT_END),
+    ("framebuffer VRAM", FRAMEBUFFER_VRAM..=FRAMEBUFFER_VRAM_END),
+    ("UART", UART_BASE..=UART_END),
+];
+
+/// Return the name and the register window of the first device that `size` bytes of DRAM at
+/// `base` would overlap.
+pub fn overlapping_device(base: u32, size: u32) -> Option<(&'static str, RangeInclusive<u32>)> {
+    let end = base as u64 + size as u64;
+    DEVICE_WINDOWS
+        .iter()
+        .find(|(_, window)| (*window.start() as u64) < end && *window.end() >= base)
+        .cloned()
+}
+
+/// The system bus, shared by all the harts.
 pub struct Bus {
     dram: Dram,
+    pub(crate) clint: Clint,
+    /// The reservation made by LR of each hart, as `(hart ID, word address)`.
+    reservations: Vec<(u32, u32)>,
+    /// The framebuffer, if the machine has a display.
+    pub framebuffer: Option<Framebuffer>,
+    /// The input device, if the machine has one.
+    pub input: Option<Input>,
+    /// The UART, if the machine has one.
+    pub uart: Option<Uart>,
+    /// The memory hooks in the order they were added, with the address range each one watches.
+    hooks: Vec<(HookId, RangeInclusive<u32>, Box<dyn MemoryHook>)>,
+    /// The identifier of the next hook.
+    next_hook_id: usize,
+    /// The journal of the run if it is being recorded or replayed.
+    pub(crate) journal: Option<Journal>,
+    /// The extension that implements the instructions in the custom opcodes. They raise an
+    /// illegal-instruction exception if this is `None`.
+    pub custom_extension: Option<Box<dyn CustomExtension>>,
 }
 
 impl Bus {
-    /// Create a new bus object.
+    /// Create a new bus object for a single hart.
     pub fn new() -> Bus {
-        Self { dram: Dram::new() }
+        Self::with_harts(1)
+    }
+
+    /// Create a new bus object for `harts` harts.
+    pub fn with_harts(harts: usize) -> Bus {
+        Self::with_memory(harts, DRAM_BASE, DRAM_SIZE)
+    }
+
+    /// Create a new bus object for `harts` harts with `size` bytes of DRAM starting at `base`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if DRAM overlaps the register window of a device.
+    pub fn with_memory(harts: usize, base: u32, size: u32) -> Bus {
+        if let Some((device, window)) = overlapping_device(base, size) {
+            panic!(
+                "DRAM at {:#x} of {:#x} bytes overlaps the {} at {:#x}..={:#x}",
+                base,
+                size,
+                device,
+                window.start(),
+                window.end()
+            );
+        }
+        Self {
+            dram: Dram::with_size(base, size),
+            clint: Clint::new(harts),
+            reservations: Vec::new(),
+            framebuffer: None,
+            input: None,
+            uart: None,
+            hooks: Vec::new(),
+            next_hook_id: 0,
+            journal: None,
+            custom_extension: None,
+        }
+    }
+
+    /// Create a copy of the bus and its devices. The DRAM of the copy shares its pages with this
+    /// one until either of them writes to a page. The memory hooks, the custom extension, and the
+    /// journal of a recording or a replay aren't copied.
+    pub fn fork(&self) -> Bus {
+        Self {
+            dram: self.dram.clone(),
+            clint: self.clint.clone(),
+            reservations: self.reservations.clone(),
+            framebuffer: self.framebuffer.clone(),
+            input: self.input.clone(),
+            uart: self.uart.clone(),
+            hooks: Vec::new(),
+            next_hook_id: 0,
+            journal: None,
+            custom_extension: None,
+        }
+    }
+
+    /// Run a cycle on peripheral devices.
+    pub fn devices_increment(&mut self) {
+        self.clint.increment();
+        if let Some(framebuffer) = self.framebuffer.as_mut() {
+            framebuffer.increment();
+        }
+    }
+
+    /// Run `cycles` cycles on peripheral devices at once.
+    pub fn devices_advance(&mut self, cycles: u64) {
+        self.clint.advance(cycles);
+        if let Some(framebuffer) = self.framebuffer.as_mut() {
+            framebuffer.advance(cycles);
+        }
+    }
+
+    /// Return the number of cycles until the next device event that can raise an interrupt, such
+    /// as a timer deadline, or `None` if no device will raise one on its own.
+    pub fn cycles_until_event(&self) -> Option<u64> {
+        let framebuffer = self.framebuffer.as_ref();
+        let vsync = framebuffer.and_then(|framebuffer| framebuffer.cycles_until_interrupt());
+        match (self.clint.ticks_until_timer(), vsync) {
+            (Some(timer), Some(vsync)) => Some(timer.min(vsync)),
+            (timer, vsync) => timer.or(vsync),
+        }
+    }
+
+    /// Return true if a device raises an external interrupt for the hart. Device interrupts are
+    /// routed to hart 0.
+    pub fn is_external_interrupting(&self, hartid: u32) -> bool {
+        let framebuffer = self.framebuffer.as_ref();
+        let input = self.input.as_ref();
+        hartid == 0
+            && (framebuffer.is_some_and(|framebuffer| framebuffer.is_interrupting())
+                || input.is_some_and(|input| input.is_interrupting()))
+    }
+
+    /// Register a reservation of the word at `addr` for the hart, replacing the reservation it
+    /// held before.
+    pub fn reserve(&mut self, hartid: u32, addr: u32) {
+        self.reservations.retain(|&(hart, _)| hart != hartid);
+        self.reservations.push((hartid, addr & !0b11));
+    }
+
+    /// Invalidate the reservation of the hart, and return true if it was a reservation of the word
+    /// at `addr`.
+    pub fn take_reservation(&mut self, hartid: u32, addr: u32) -> bool {
+        let reserved = self.reservations.contains(&(hartid, addr & !0b11));
+        self.reservations.retain(|&(hart, _)| hart != hartid);
+        reserved
+    }
+
+    /// Return the DRAM.
+    pub fn dram(&self) -> &Dram {
+        &self.dram
+    }
+
+    /// Return the address which DRAM starts.
+    pub fn dram_base(&self) -> u32 {
+        self.dram.base()
+    }
+
+    /// Return the size of DRAM in bytes.
+    pub fn dram_size(&self) -> u32 {
+        self.dram.size()
     }
 
     /// Set the binary data to the memory.
@@ -30,19 +207,141 @@ impl Bus {
         self.dram.initialize(data);
     }
 
+    /// Add a hook on the accesses of the harts that overlap `range`. Hooks are called in the order
+    /// they were added, and each one gets the value returned by the previous one.
+    pub fn add_hook(&mut self, range: RangeInclusive<u32>, hook: Box<dyn MemoryHook>) -> HookId {
+        let id = HookId(self.next_hook_id);
+        self.next_hook_id += 1;
+        self.hooks.push((id, range, hook));
+        id
+    }
+
+    /// Remove the hook `id`, and return it if it was registered.
+    pub fn remove_hook(&mut self, id: HookId) -> Option<Box<dyn MemoryHook>> {
+        let index = self
+            .hooks
+            .iter()
+            .position(|(hook_id, _, _)| *hook_id == id)?;
+        Some(self.hooks.remove(index).2)
+    }
+
+    /// Pass `value` through the hooks that watch the access. Returns `None` if a hook faults it.
+    fn run_hooks(&mut self, access: Access, value: u32) -> Option<u32> {
+        let end = access.addr.saturating_add(access.size as u32 / 8 - 1);
+        self.hooks
+            .iter_mut()
+            .filter(|(_, range, _)| *range.start() <= end && access.addr <= *range.end())
+            .try_fold(value, |value, (_, _, hook)| match access.access_type {
+                AccessType::Store => hook.write(&access, value),
+                _ => hook.read(&access, value),
+            })
+    }
+
+    /// Fetch the instruction at `addr` for the hart `hartid`, through the memory hooks.
+    pub fn fetch(&mut self, hartid: u32, addr: u32) -> Result<u32, Exception> {
+        // The device raises a load access fault, which is an instruction access fault for a fetch.
+        let value = self
+            .read(addr, WORD)
+            .map_err(|_| Exception::InstructionAccessFault(addr))?;
+        let access = Access {
+            hartid,
+            access_type: AccessType::Instruction,
+            addr,
+            size: WORD,
+        };
+        self.run_hooks(access, value)
+            .ok_or(Exception::InstructionAccessFault(addr))
+    }
+
+    /// Load `size`-bit data at `addr` for the hart `hartid`, through the memory hooks. A load from
+    /// a device is journaled when the run is recorded or replayed.
+    pub fn load(&mut self, hartid: u32, addr: u32, size: u8) -> Result<u32, Exception> {
+        let mut value = self.read(addr, size)?;
+        if let Some(journal) = self.journal.as_mut() {
+            if !self.dram.contains(addr) {
+                value = journal.mmio(value);
+            }
+        }
+        let access = Access {
+            hartid,
+            access_type: AccessType::Load,
+            addr,
+            size,
+        };
+        self.run_hooks(access, value)
+            .ok_or(Exception::LoadAccessFault(addr))
+    }
+
+    /// Store `size`-bit data to `addr` for the hart `hartid`, through the memory hooks.
+    pub fn store(&mut self, hartid: u32, addr: u32, value: u32, size: u8) -> Result<(), Exception> {
+        let access = Access {
+            hartid,
+            access_type: AccessType::Store,
+            addr,
+            size,
+        };
+        let value = self
+            .run_hooks(access, value)
+            .ok_or(Exception::StoreAMOAccessFault(addr))?;
+        self.write(addr, value, size)
+    }
+
+    /// Return true if `addr` is in the pixel buffer of the framebuffer.
+    fn is_vram(&self, addr: u32) -> bool {
+        self.framebuffer.as_ref().is_some_and(|framebuffer| {
+            (FRAMEBUFFER_VRAM..FRAMEBUFFER_VRAM + framebuffer.vram_size()).contains(&addr)
+        })
+    }
+
     /// Load a `size`-bit data from the device that connects to the system bus.
     pub fn read(&mut self, addr: u32, size: u8) -> Result<u32, Exception> {
         match addr {
-            DRAM_BASE..=DRAM_END => self.dram.read(addr, size),
-            _ => Err(Exception::LoadAccessFault),
+            CLINT_BASE..=CLINT_END => self.clint.read(addr, size),
+            _ if self.dram.contains(addr) => self.dram.read(addr, size),
+            FRAMEBUFFER_BASE..=FRAMEBUFFER_END => match self.framebuffer.as_ref() {
+                Some(framebuffer) => framebuffer.read(addr, size),
+                None => Err(Exception::LoadAccessFault(addr)),
+            },
+            INPUT_BASE..=INPUT_END => match self.input.as_ref() {
+                Some(input) => input.read(addr, size),
+                None => Err(Exception::LoadAccessFault(addr)),
+            },
+            UART_BASE..=UART_END => match self.uart.as_ref() {
+                Some(uart) => uart.read(addr, size),
+                None => Err(Exception::LoadAccessFault(addr)),
+            },
+            _ if self.is_vram(addr) => self.framebuffer.as_ref().unwrap().read_vram(addr, size),
+            _ => Err(Exception::LoadAccessFault(addr)),
         }
     }
 
     /// Store a `size`-bit data to the device that connects to the system bus.
     pub fn write(&mut self, addr: u32, value: u32, size: u8) -> Result<(), Exception> {
+        // "The SC must fail if a write from some other device to the bytes accessed by the LR can
+        // be observed to occur between the LR and SC."
+        self.reservations
+            .retain(|&(_, reserved)| reserved != addr & !0b11);
+
         match addr {
-            DRAM_BASE..=DRAM_END => self.dram.write(addr, value, size),
-            _ => Err(Exception::StoreAMOAccessFault),
+            CLINT_BASE..=CLINT_END => self.clint.write(addr, value, size),
+            _ if self.dram.contains(addr) => self.dram.write(addr, value, size),
+            FRAMEBUFFER_BASE..=FRAMEBUFFER_END => match self.framebuffer.as_mut() {
+                Some(framebuffer) => framebuffer.write(addr, value, size),
+                None => Err(Exception::StoreAMOAccessFault(addr)),
+            },
+            INPUT_BASE..=INPUT_END => match self.input.as_mut() {
+                Some(input) => input.write(addr, value, size),
+                None => Err(Exception::StoreAMOAccessFault(addr)),
+            },
+            UART_BASE..=UART_END => match self.uart.as_mut() {
+                Some(uart) => uart.write(addr, value, size),
+                None => Err(Exception::StoreAMOAccessFault(addr)),
+            },
+            _ if self.is_vram(addr) => {
+                let framebuffer = self.framebuffer.as_mut().unwrap();
+                framebuffer.write_vram(addr, value, size)
+            }
+            _ => Err(Exception::StoreAMOAccessFault(addr)),
         }
     }
 }
@@ -0,0 +1,151 @@
+//! The clint module contains the core-local interruptor (CLINT). The CLINT generates software and
+//! timer interrupts for each hart.
+
+use alloc::vec::Vec;
+
+use crate::cpu::WORD;
+use crate::exception::Exception;
+
+/// The address which the CLINT starts.
+pub const CLINT_BASE: u32 = 0x200_0000;
+/// The size of the CLINT.
+pub const CLINT_SIZE: u32 = 0x10000;
+
+/// The machine software interrupt pending register of hart 0. Hart `n` is at `MSIP + 4 * n`.
+pub const CLINT_MSIP: u32 = CLINT_BASE;
+/// The timer compare register of hart 0. Hart `n` is at `MTIMECMP + 8 * n`.
+pub const CLINT_MTIMECMP: u32 = CLINT_BASE + 0x4000;
+/// The timer register, shared by all the harts.
+pub const CLINT_MTIME: u32 = CLINT_BASE + 0xbff8;
+
+/// The core-local interruptor.
+#[derive(Clone)]
+pub struct Clint {
+    /// The MSIP register of each hart. Only bit 0 is writable.
+    msip: Vec<u32>,
+    /// The MTIMECMP register of each hart.
+    mtimecmp: Vec<u64>,
+    /// The MTIME register.
+    mtime: u64,
+}
+
+impl Clint {
+    /// Create a new CLINT for `harts` harts.
+    pub fn new(harts: usize) -> Self {
+        Self {
+            msip: vec![0; harts],
+            // No timer interrupt is pending until software sets the compare register.
+            mtimecmp: vec![u64::MAX; harts],
+            mtime: 0,
+        }
+    }
+
+    /// Increment the MTIME register.
+    pub fn increment(&mut self) {
+        self.mtime = self.mtime.wrapping_add(1);
+    }
+
+    /// Add `ticks` to the MTIME register.
+    pub fn advance(&mut self, ticks: u64) {
+        self.mtime = self.mtime.wrapping_add(ticks);
+    }
+
+    /// Return the value of the MTIME register.
+    pub fn mtime(&self) -> u64 {
+        self.mtime
+    }
+
+    /// Return the number of ticks until the next timer deadline of any hart, or `None` if no
+    /// deadline is ahead.
+    pub fn ticks_until_timer(&self) -> Option<u64> {
+        self.mtimecmp
+            .iter()
+            .filter(|&&mtimecmp| mtimecmp > self.mtime && mtimecmp != u64::MAX)
+            .map(|&mtimecmp| mtimecmp - self.mtime)
+            .min()
+    }
+
+    /// Return true if a machine software interrupt is pending for the hart.
+    pub fn is_software_interrupting(&self, hartid: u32) -> bool {
+        self.msip
+            .get(hartid as usize)
+            .is_some_and(|&msip| msip != 0)
+    }
+
+    /// Return true if a machine timer interrupt is pending for the hart.
+    pub fn is_timer_interrupting(&self, hartid: u32) -> bool {
+        self.mtimecmp
+            .get(hartid as usize)
+            .is_some_and(|&mtimecmp| self.mtime >= mtimecmp)
+    }
+
+    /// Load a word from a CLINT register. Only word accesses are supported.
+    pub fn read(&self, addr: u32, size: u8) -> Result<u32, Exception> {
+        let value = match (size, self.register(addr)) {
+            (WORD, Some(Register::Msip(hart))) => self.msip[hart],
+            (WORD, Some(Register::Mtimecmp(hart, high))) => half(self.mtimecmp[hart], high),
+            (WORD, Some(Register::Mtime(high))) => half(self.mtime, high),
+            _ => return Err(Exception::LoadAccessFault(addr)),
+        };
+        Ok(value)
+    }
+
+    /// Store a word to a CLINT register. Only word accesses are supported.
+    pub fn write(&mut self, addr: u32, value: u32, size: u8) -> Result<(), Exception> {
+        match (size, self.register(addr)) {
+            (WORD, Some(Register::Msip(hart))) => self.msip[hart] = value & 1,
+            (WORD, Some(Register::Mtimecmp(hart, high))) => {
+                self.mtimecmp[hart] = set_half(self.mtimecmp[hart], high, value)
+            }
+            (WORD, Some(Register::Mtime(high))) => self.mtime = set_half(self.mtime, high, value),
+            _ => return Err(Exception::StoreAMOAccessFault(addr)),
+        }
+        Ok(())
+    }
+
+    /// Decode the register at `addr`.
+    fn register(&self, addr: u32) -> Option<Register> {
+        if addr & 0b11 != 0 {
+            return None;
+        }
+        let harts = self.msip.len() as u32;
+        if (CLINT_MSIP..CLINT_MSIP + 4 * harts).contains(&addr) {
+            Some(Register::Msip(((addr - CLINT_MSIP) / 4) as usize))
+        } else if (CLINT_MTIMECMP..CLINT_MTIMECMP + 8 * harts).contains(&addr) {
+            let offset = addr - CLINT_MTIMECMP;
+            Some(Register::Mtimecmp(
+                (offset / 8) as usize,
+                offset & 0b100 != 0,
+            ))
+        } else if (CLINT_MTIME..CLINT_MTIME + 8).contains(&addr) {
+            Some(Register::Mtime(addr != CLINT_MTIME))
+        } else {
+            None
+        }
+    }
+}
+
+/// A CLINT register. The boolean selects the upper 32 bits of a 64-bit register.
+enum Register {
+    Msip(usize),
+    Mtimecmp(usize, bool),
+    Mtime(bool),
+}
+
+/// Return the lower or upper 32 bits of `value`.
+fn half(value: u64, high: bool) -> u32 {
+    if high {
+        (value >> 32) as u32
+    } else {
+        value as u32
+    }
+}
+
+/// Replace the lower or upper 32 bits of `value`.
+fn set_half(value: u64, high: bool, half: u32) -> u64 {
+    if high {
+        (value & 0xffff_ffff) | ((half as u64) << 32)
+    } else {
+        (value & !0xffff_ffff) | half as u64
+    }
+}
@@ -0,0 +1,143 @@
+//! The config module contains the configuration of the ISA a hart implements, so that a hart can
+//! run with a subset of the extensions this emulator implements, such as RV32IM in M-mode only.
+
+/// An ISA extension. The single-letter extensions are numbered by their bit in `misa`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Extension {
+    /// Atomic instructions.
+    A = 0,
+    /// Compressed instructions.
+    C = 2,
+    /// Double-precision floating-point.
+    D = 3,
+    /// The RV32E base ISA with 16 integer registers.
+    E = 4,
+    /// Single-precision floating-point.
+    F = 5,
+    /// The RV32I base ISA.
+    I = 8,
+    /// Integer multiplication and division.
+    M = 12,
+    /// Supervisor mode.
+    S = 18,
+    /// User mode.
+    U = 20,
+    /// Control and status register instructions.
+    Zicsr = 32,
+    /// The instruction-fetch fence.
+    Zifencei = 33,
+    /// Address generation bit manipulation.
+    Zba = 34,
+    /// Basic bit manipulation.
+    Zbb = 35,
+    /// Carry-less multiplication.
+    Zbc = 36,
+    /// Single-bit instructions.
+    Zbs = 37,
+}
+
+/// The extensions this emulator implements. Enabling any other extension in a configuration
+/// panics.
+const IMPLEMENTED: u64 = bit(Extension::I)
+    | bit(Extension::E)
+    | bit(Extension::M)
+    | bit(Extension::A)
+    | bit(Extension::U)
+    | bit(Extension::Zicsr)
+    | bit(Extension::Zifencei);
+
+/// The bits of the single-letter extensions, which are reported in `misa`.
+const MISA_EXTENSIONS: u64 = (1 << 26) - 1;
+
+/// Return the bit of `extension` in a set of extensions.
+const fn bit(extension: Extension) -> u64 {
+    1 << extension as u64
+}
+
+/// The ISA configuration of a hart.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct CpuConfig {
+    extensions: u64,
+}
+
+impl Default for CpuConfig {
+    /// All the extensions this emulator implements: RV32IMA with Zicsr, Zifencei and U-mode.
+    fn default() -> Self {
+        Self {
+            extensions: IMPLEMENTED & !bit(Extension::E),
+        }
+    }
+}
+
+impl CpuConfig {
+    /// Create a configuration with `extensions` enabled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if one of `extensions` isn't implemented, or if `extensions` has no base ISA.
+    pub fn new(extensions: &[Extension]) -> Self {
+        let config = extensions
+            .iter()
+            .fold(Self { extensions: 0 }, |config, &extension| {
+                config.with(extension)
+            });
+        config.assert_base_isa();
+        config
+    }
+
+    /// Return true if this emulator implements `extension`.
+    pub fn is_implemented(extension: Extension) -> bool {
+        IMPLEMENTED & bit(extension) != 0
+    }
+
+    /// Return the configuration with `extension` enabled. The base ISA is either RV32I or RV32E,
+    /// so enabling one of them disables the other.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `extension` isn't implemented.
+    pub fn with(mut self, extension: Extension) -> Self {
+        assert!(
+            Self::is_implemented(extension),
+            "the {:?} extension is not implemented",
+            extension
+        );
+        match extension {
+            Extension::I => self.extensions &= !bit(Extension::E),
+            Extension::E => self.extensions &= !bit(Extension::I),
+            _ => {}
+        }
+        self.extensions |= bit(extension);
+        self
+    }
+
+    /// Return the configuration with `extension` disabled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `extension` is the base ISA.
+    pub fn without(mut self, extension: Extension) -> Self {
+        self.extensions &= !bit(extension);
+        self.assert_base_isa();
+        self
+    }
+
+    /// Panic unless the base ISA, RV32I or RV32E, is enabled. A hart can't run without one.
+    fn assert_base_isa(&self) {
+        assert!(
+            self.has(Extension::I) || self.has(Extension::E),
+            "a configuration needs the I or E base ISA"
+        );
+    }
+
+    /// Return true if `extension` is enabled.
+    pub fn has(&self, extension: Extension) -> bool {
+        self.extensions & bit(extension) != 0
+    }
+
+    /// Return the value of `misa`: XLEN is 32, and the enabled single-letter extensions.
+    pub fn misa(&self) -> u32 {
+        // MXL[1:0]=1 (XLEN is 32)
+        (1 << 30) | (self.extensions & MISA_EXTENSIONS) as u32
+    }
+}
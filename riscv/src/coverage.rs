@@ -0,0 +1,318 @@
+//! The coverage module records which parts of the ISA a guest or a test suite exercised: the arms
+//! of the decoder for each instruction, the arms that reject an illegal encoding, and the trap
+//! causes. The report lists what was never hit, such as instructions no test executes.
+//!
+//! Coverage is enabled per hart with [`Emulator::enable_coverage`]. Hits can be encoded as text
+//! and merged, so that the coverage of many runs, such as the test binaries of a suite, adds up
+//! to one report.
+//!
+//! [`Emulator::enable_coverage`]: crate::emulator::Emulator::enable_coverage
+
+use alloc::{collections::BTreeMap, string::String};
+use core::fmt::{self, Write};
+
+use crate::exception::Exception;
+use crate::interrupt::Interrupt;
+
+/// The instructions the decoder implements, by the mnemonic its arm counts.
+pub const INSTRUCTIONS: &[&str] = &[
+    // RV32I
+    "lui",
+    "auipc",
+    "jal",
+    "jalr",
+    "beq",
+    "bne",
+    "blt",
+    "bge",
+    "bltu",
+    "bgeu",
+    "lb",
+    "lh",
+    "lw",
+    "lbu",
+    "lhu",
+    "sb",
+    "sh",
+    "sw",
+    "addi",
+    "slti",
+    "sltiu",
+    "xori",
+    "ori",
+    "andi",
+    "slli",
+    "srli",
+    "srai",
+    "add",
+    "sub",
+    "sll",
+    "slt",
+    "sltu",
+    "xor",
+    "srl",
+    "sra",
+    "or",
+    "and",
+    "fence",
+    "ecall",
+    "ebreak",
+    // Zifencei and Zicsr
+    "fence.i",
+    "csrrw",
+    "csrrs",
+    "csrrc",
+    "csrrwi",
+    "csrrsi",
+    "csrrci",
+    // M
+    "mul",
+    "mulh",
+    "mulhsu",
+    "mulhu",
+    "div",
+    "divu",
+    "rem",
+    "remu",
+    // A
+    "lr.w",
+    "sc.w",
+    "amoswap.w",
+    "amoadd.w",
+    "amoxor.w",
+    "amoand.w",
+    "amoor.w",
+    "amomin.w",
+    "amomax.w",
+    "amominu.w",
+    "amomaxu.w",
+    // Privileged and debug
+    "mret",
+    "dret",
+    "wfi",
+    // Custom extension
+    "custom",
+];
+
+/// The checks that reject an instruction as illegal.
+pub const ILLEGAL_ENCODINGS: &[&str] = &[
+    "unknown opcode",
+    "load funct3",
+    "misc-mem funct3",
+    "op-imm funct3",
+    "slli funct7",
+    "srli/srai funct7",
+    "store funct3",
+    "amo funct5",
+    "op funct3/funct7",
+    "branch funct3",
+    "system funct3",
+    "system funct7",
+    "ecall in debug mode",
+    "dret outside debug mode",
+    "mret outside M-mode",
+    "uret without N",
+    "sfence.vma without S",
+    "hfence without H",
+    "disabled extension",
+    "register beyond RV32E",
+    "unknown csr",
+    "csr privilege",
+    "csr read-only write",
+    "debug csr outside debug mode",
+    "counter disabled by mcounteren",
+    "custom opcode without extension",
+];
+
+/// The exceptions and interrupts a hart can trap on.
+pub const TRAP_CAUSES: &[&str] = &[
+    "InstructionAddressMisaligned",
+    "InstructionAccessFault",
+    "IllegalInstruction",
+    "Breakpoint",
+    "LoadAddressMisaligned",
+    "LoadAccessFault",
+    "StoreAMOAddressMisaligned",
+    "StoreAMOAccessFault",
+    "EnvironmentCallFromUMode",
+    "EnvironmentCallFromMMode",
+    "InstructionPageFault",
+    "LoadPageFault",
+    "StoreAMOPageFault",
+    "SupervisorSoftwareInterrupt",
+    "MachineSoftwareInterrupt",
+    "SupervisorTimerInterrupt",
+    "MachineTimerInterrupt",
+    "SupervisorExternalInterrupt",
+    "MachineExternalInterrupt",
+];
+
+/// What a coverage point is.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub enum Kind {
+    /// An instruction the decoder executed.
+    Instruction,
+    /// A check that rejected an instruction as illegal.
+    Illegal,
+    /// The cause of a trap.
+    Trap,
+}
+
+impl Kind {
+    /// All the kinds, in the order of the report.
+    const ALL: [Kind; 3] = [Kind::Instruction, Kind::Illegal, Kind::Trap];
+
+    /// Return the coverage points of the kind.
+    pub fn points(&self) -> &'static [&'static str] {
+        match self {
+            Kind::Instruction => INSTRUCTIONS,
+            Kind::Illegal => ILLEGAL_ENCODINGS,
+            Kind::Trap => TRAP_CAUSES,
+        }
+    }
+
+    /// Return the name of the kind in the encoded hits.
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Instruction => "instruction",
+            Kind::Illegal => "illegal",
+            Kind::Trap => "trap",
+        }
+    }
+
+    /// Return the title of the kind in the report.
+    fn title(&self) -> &'static str {
+        match self {
+            Kind::Instruction => "instructions",
+            Kind::Illegal => "illegal encodings",
+            Kind::Trap => "trap causes",
+        }
+    }
+}
+
+/// The number of times each coverage point was hit.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    hits: BTreeMap<(Kind, &'static str), u64>,
+}
+
+impl Coverage {
+    /// Create a coverage with no hits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a hit of the point `name` of `kind`.
+    pub fn hit(&mut self, kind: Kind, name: &'static str) {
+        debug_assert!(
+            kind.points().contains(&name),
+            "unknown coverage point {}",
+            name
+        );
+        *self.hits.entry((kind, name)).or_insert(0) += 1;
+    }
+
+    /// Count a trap on `exception`.
+    pub fn hit_exception(&mut self, exception: &Exception) {
+        let name = match exception {
+            Exception::InstructionAddressMisaligned(_) => "InstructionAddressMisaligned",
+            Exception::InstructionAccessFault(_) => "InstructionAccessFault",
+            Exception::IllegalInstruction(_) => "IllegalInstruction",
+            Exception::Breakpoint(_) => "Breakpoint",
+            Exception::LoadAddressMisaligned(_) => "LoadAddressMisaligned",
+            Exception::LoadAccessFault(_) => "LoadAccessFault",
+            Exception::StoreAMOAddressMisaligned(_) => "StoreAMOAddressMisaligned",
+            Exception::StoreAMOAccessFault(_) => "StoreAMOAccessFault",
+            Exception::EnvironmentCallFromUMode => "EnvironmentCallFromUMode",
+            Exception::EnvironmentCallFromMMode => "EnvironmentCallFromMMode",
+            Exception::InstructionPageFault(_) => "InstructionPageFault",
+            Exception::LoadPageFault(_) => "LoadPageFault",
+            Exception::StoreAMOPageFault(_) => "StoreAMOPageFault",
+        };
+        self.hit(Kind::Trap, name);
+    }
+
+    /// Count a trap on `interrupt`.
+    pub fn hit_interrupt(&mut self, interrupt: Interrupt) {
+        let name = match interrupt {
+            Interrupt::SupervisorSoftwareInterrupt => "SupervisorSoftwareInterrupt",
+            Interrupt::MachineSoftwareInterrupt => "MachineSoftwareInterrupt",
+            Interrupt::SupervisorTimerInterrupt => "SupervisorTimerInterrupt",
+            Interrupt::MachineTimerInterrupt => "MachineTimerInterrupt",
+            Interrupt::SupervisorExternalInterrupt => "SupervisorExternalInterrupt",
+            Interrupt::MachineExternalInterrupt => "MachineExternalInterrupt",
+        };
+        self.hit(Kind::Trap, name);
+    }
+
+    /// Return the number of hits of the point `name` of `kind`.
+    pub fn hits(&self, kind: Kind, name: &str) -> u64 {
+        let point = kind.points().iter().find(|point| **point == name);
+        point.map_or(0, |point| {
+            self.hits.get(&(kind, *point)).copied().unwrap_or(0)
+        })
+    }
+
+    /// Return the points of `kind` that were never hit.
+    pub fn missed(&self, kind: Kind) -> impl Iterator<Item = &'static str> + '_ {
+        kind.points()
+            .iter()
+            .copied()
+            .filter(move |name| !self.hits.contains_key(&(kind, *name)))
+    }
+
+    /// Add the hits of `other`.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (point, count) in other.hits.iter() {
+            *self.hits.entry(*point).or_insert(0) += count;
+        }
+    }
+
+    /// Encode the hits as text: a line of `<kind> <count> <name>` per point that was hit.
+    pub fn encode(&self) -> String {
+        let mut text = String::new();
+        for ((kind, name), count) in self.hits.iter() {
+            // Writing to a string doesn't fail.
+            let _ = writeln!(text, "{} {} {}", kind.name(), count, name);
+        }
+        text
+    }
+
+    /// Decode the hits encoded by [`Coverage::encode`]. The hits of a point that appears more
+    /// than once, as in concatenated encodings, add up. Returns `None` if a line is invalid or
+    /// names an unknown point.
+    pub fn decode(text: &str) -> Option<Coverage> {
+        let mut coverage = Coverage::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let mut fields = line.splitn(3, ' ');
+            let kind = fields.next()?;
+            let kind = Kind::ALL.into_iter().find(|k| k.name() == kind)?;
+            let count: u64 = fields.next()?.parse().ok()?;
+            let name = fields.next()?;
+            let name = kind.points().iter().find(|point| **point == name)?;
+            *coverage.hits.entry((kind, name)).or_insert(0) += count;
+        }
+        Some(coverage)
+    }
+}
+
+impl fmt::Display for Coverage {
+    /// The report: how many points of each kind were hit, and the ones that weren't.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for kind in Kind::ALL {
+            let total = kind.points().len();
+            let missed = self.missed(kind).count();
+            writeln!(
+                f,
+                "{}: {}/{} exercised",
+                kind.title(),
+                total - missed,
+                total
+            )?;
+            for name in self.missed(kind) {
+                writeln!(f, "  not exercised: {}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,20 +1,28 @@
 //! The cpu module contains the privileged mode, registers, and CPU.
 
-use alloc::{collections::BTreeMap, string::String};
-use alloc::{string::ToString, vec::Vec};
-use core::cmp::PartialEq;
+use alloc::string::ToString;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::cmp::{self, PartialEq};
 use core::fmt;
 
 use crate::{
     bus::{Bus, DRAM_BASE},
+    config::{CpuConfig, Extension},
+    coverage::{Coverage, Kind},
     csr::*,
+    custom::{self, CUSTOM_0, CUSTOM_1, CUSTOM_2, CUSTOM_3},
+    debug::DebugCause,
     dram::DRAM_SIZE,
     exception::Exception,
     interrupt::Interrupt,
+    replay::is_volatile_csr,
+    trigger::{TriggerAction, TDATA1_DMODE},
 };
 
 /// The number of registers.
 pub const REGISTERS_COUNT: usize = 32;
+/// The number of registers of the RV32E base ISA.
+pub const RV32E_REGISTERS_COUNT: usize = 16;
 
 /// 8 bits. 1 byte.
 pub const BYTE: u8 = 8;
@@ -28,12 +36,15 @@ macro_rules! inst_count {
         if $cpu.is_count {
             *$cpu.inst_counter.entry($inst_name.to_string()).or_insert(0) += 1;
         }
+        if let Some(coverage) = $cpu.coverage.as_mut() {
+            coverage.hit(Kind::Instruction, $inst_name);
+        }
     };
 }
 
 /// Access type that is used in the virtual address translation process. It decides which exception
 /// should raises (InstructionPageFault, LoadPageFault or StoreAMOPageFault).
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, PartialEq, PartialOrd, Eq, Copy, Clone)]
 pub enum AccessType {
     /// Raises the exception InstructionPageFault. It is used for an instruction fetch.
     Instruction,
@@ -52,20 +63,32 @@ pub enum Mode {
 }
 
 /// The integer registers.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct XRegisters {
-    xregs: [u32; REGISTERS_COUNT],
+    /// The registers x0 to x31, or x0 to x15 on RV32E.
+    xregs: Vec<u32>,
 }
 
 impl XRegisters {
     /// Create a new `XRegisters` object.
     pub fn new() -> Self {
-        let mut xregs = [0; REGISTERS_COUNT];
+        let mut xregs = vec![0; REGISTERS_COUNT];
         // The stack pointer is set in the default maximum memory size + the start address of dram.
         xregs[2] = DRAM_BASE + DRAM_SIZE;
         Self { xregs }
     }
 
+    /// Return the number of registers.
+    pub fn count(&self) -> usize {
+        self.xregs.len()
+    }
+
+    /// Change the number of registers to `count`, keeping the values of the remaining registers.
+    /// Added registers are zero.
+    pub fn resize(&mut self, count: usize) {
+        self.xregs.resize(count, 0);
+    }
+
     /// Read the value from a register.
     pub fn read(&self, index: u32) -> u32 {
         self.xregs[index as usize]
@@ -88,7 +111,7 @@ impl fmt::Display for XRegisters {
             " s6 ", " s7 ", " s8 ", " s9 ", " s10", " s11", " t3 ", " t4 ", " t5 ", " t6 ",
         ];
         let mut output = String::from("");
-        for i in (0..REGISTERS_COUNT).step_by(4) {
+        for i in (0..self.count()).step_by(4) {
             output = format!(
                 "{}\n{}",
                 output,
@@ -114,6 +137,7 @@ impl fmt::Display for XRegisters {
 }
 
 /// The CPU to contain registers, a program counter, status, and a privileged mode.
+#[derive(Clone)]
 pub struct Cpu {
     /// 64-bit integer registers.
     pub xregs: XRegisters,
@@ -123,11 +147,6 @@ pub struct Cpu {
     pub state: State,
     /// Privilege level.
     pub mode: Mode,
-    /// System bus.
-    pub bus: Bus,
-    /// A set of bytes that subsumes the bytes in the addressed word used in
-    /// load-reserved/store-conditional instructions.
-    reservation_set: Vec<u32>,
     /// Idle state. True when WFI is called, and becomes false when an interrupt happens.
     pub idle: bool,
     /// Counter of each instructions for debug.
@@ -136,6 +155,10 @@ pub struct Cpu {
     pub is_count: bool,
     /// Previous instruction. This is for debug.
     pub pre_inst: u32,
+    /// The coverage of the decoder and the trap causes, or `None` if coverage is disabled.
+    pub coverage: Option<Coverage>,
+    /// The ISA the hart implements.
+    config: CpuConfig,
 }
 
 impl Cpu {
@@ -146,56 +169,94 @@ impl Cpu {
             pc: 0,
             state: State::new(),
             mode: Mode::Machine,
-            bus: Bus::new(),
-            reservation_set: Vec::new(),
             idle: false,
             inst_counter: BTreeMap::new(),
             is_count: false,
             pre_inst: 0,
+            coverage: None,
+            config: CpuConfig::default(),
         }
     }
 
-    fn debug(&self, _inst: u32, _name: &str) {
-        /*
-        if (((0x20_0000_0000 & self.pc) >> 37) == 1) && (self.pc & 0xf0000000_00000000) == 0 {
-            println!(
-                "[user]    {} pc: {:#x}, inst: {:#x}, is_inst 16? {} x[0x1c] {:#x}",
-                name,
-                self.pc,
-                inst,
-                // Check if an instruction is one of the compressed instructions.
-                inst & 0b11 == 0 || inst & 0b11 == 1 || inst & 0b11 == 2,
-                self.xregs.read(0x1c),
-            );
-            return;
+    /// Create a new `Cpu` object for the hart `hartid`.
+    pub fn with_hartid(hartid: u32) -> Cpu {
+        let mut cpu = Self::new();
+        cpu.state.set_hartid(hartid);
+        cpu
+    }
+
+    /// Return the ISA the hart implements.
+    pub fn config(&self) -> CpuConfig {
+        self.config
+    }
+
+    /// Set the ISA the hart implements, and update MISA to report it. RV32E has only the
+    /// registers x0 to x15.
+    pub fn set_config(&mut self, config: CpuConfig) {
+        self.config = config;
+        self.state.set_misa(config.misa());
+        self.xregs.resize(if config.has(Extension::E) {
+            RV32E_REGISTERS_COUNT
+        } else {
+            REGISTERS_COUNT
+        });
+    }
+
+    /// Return the illegal instruction exception for `inst`, counting a hit of the check `check`
+    /// that rejected it.
+    fn illegal(&mut self, check: &'static str, inst: u32) -> Exception {
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.hit(Kind::Illegal, check);
         }
+        Exception::IllegalInstruction(inst)
+    }
 
-        if (self.pc & 0xf0000000_00000000) != 0 {
-            return;
-            /*
-            println!(
-                "[kernel]  {} pc: {:#x}, inst: {:#x}, is_inst 16? {} x[0x1c] {:#x}",
-                name,
-                self.pc,
-                inst,
-                // Check if an instruction is one of the compressed instructions.
-                inst & 0b11 == 0 || inst & 0b11 == 1 || inst & 0b11 == 2,
-                self.xregs.read(0x1c),
-            );
-            return;
-            */
+    /// Raise an illegal instruction exception if `extension` is not enabled.
+    fn require(&mut self, extension: Extension, inst: u32) -> Result<(), Exception> {
+        if !self.config.has(extension) {
+            return Err(self.illegal("disabled extension", inst));
         }
+        Ok(())
+    }
 
-        println!(
-            "[machine] {} pc: {:#x}, inst: {:#x}, is_inst 16? {} x[0x1c] {:#x}",
-            name,
-            self.pc,
-            inst,
-            // Check if an instruction is one of the compressed instructions.
-            inst & 0b11 == 0 || inst & 0b11 == 1 || inst & 0b11 == 2,
-            self.xregs.read(0x1c),
-        );
-        */
+    /// Raise an illegal instruction exception if `inst` names a register that doesn't exist. On
+    /// RV32E, "encodings that would otherwise specify registers x16-x31 are reserved".
+    fn check_registers(&mut self, inst: u32) -> Result<(), Exception> {
+        let count = self.xregs.count() as u32;
+        if count == REGISTERS_COUNT as u32 {
+            return Ok(());
+        }
+
+        let opcode = inst & 0x0000007f;
+        let rd = (inst & 0x00000f80) >> 7;
+        let rs1 = (inst & 0x000f8000) >> 15;
+        let rs2 = (inst & 0x01f00000) >> 20;
+        let funct3 = (inst & 0x00007000) >> 12;
+        // The register fields each format uses. The rs1 field of csrrwi, csrrsi, and csrrci is an
+        // immediate, and the fields of fence are hints.
+        let (uses_rd, uses_rs1, uses_rs2) = match opcode {
+            0x17 | 0x37 | 0x6f => (true, false, false),
+            0x03 | 0x13 | 0x67 => (true, true, false),
+            0x23 | 0x63 => (false, true, true),
+            0x2f | 0x33 => (true, true, true),
+            0x73 => (true, funct3 & 0b100 == 0, funct3 == 0),
+            _ => (false, false, false),
+        };
+        if (uses_rd && rd >= count) || (uses_rs1 && rs1 >= count) || (uses_rs2 && rs2 >= count) {
+            return Err(self.illegal("register beyond RV32E", inst));
+        }
+        Ok(())
+    }
+
+    /// Return the privilege mode encoded as `bits` in MPP or DCSR.prv. M-mode and U-mode are the
+    /// only modes implemented, so any other encoding maps to U-mode, or to M-mode if U-mode is
+    /// disabled.
+    fn privilege_mode(&self, bits: u32) -> Mode {
+        match bits {
+            0b11 => Mode::Machine,
+            _ if self.config.has(Extension::U) => Mode::User,
+            _ => Mode::Machine,
+        }
     }
 
     /// Reset CPU states.
@@ -203,7 +264,7 @@ impl Cpu {
         self.pc = 0;
         self.mode = Mode::Machine;
         self.state.reset();
-        for i in 0..REGISTERS_COUNT {
+        for i in 0..self.xregs.count() {
             self.xregs.write(i as u32, 0);
         }
     }
@@ -225,27 +286,14 @@ impl Cpu {
                     return None;
                 }
             }
-            _ => {}
+            // "All interrupts (including NMI) are masked" in debug mode.
+            Mode::Debug => return None,
+            // M-mode interrupts are always enabled in a less privileged mode.
+            Mode::User => {}
         }
-
-        // TODO: Take interrupts based on priorities.
-
-        // Check external interrupt for uart and virtio.
-        let irq = 0;
-        // if self.bus.uart.is_interrupting() {
-        //     irq = UART_IRQ;
-        // } else if self.bus.virtio.is_interrupting() {
-        //     // An interrupt is raised after a disk access is done.
-        //     Virtio::disk_access(self).expect("failed to access the disk");
-        //     irq = VIRTIO_IRQ;
-        // } else {
-        //     irq = 0;
-        // }
-
-        if irq != 0 {
-            // TODO: assume that hart is 0
-            // TODO: write a value to MCLAIM if the mode is machine
-            self.state.write(MIP, self.state.read(MIP) | SEIP_BIT);
+        // "If stepie is 0, interrupts (including NMI) are disabled during single stepping."
+        if self.is_stepping() && self.state.read_bits(DCSR, DCSR_STEPIE) == 0 {
+            return None;
         }
 
         // 3.1.9 Machine Interrupt Registers (mip and mie)
@@ -257,43 +305,51 @@ impl Cpu {
         // delegated privilege mode (S or U) and that mode’s interrupt enable bit (SIE or UIE in
         // mstatus) is set, or if the current privilege mode is less than the delegated privilege
         // mode."
+        // S-mode isn't implemented, so MIDELEG is read-only zero and every interrupt is taken in
+        // M-mode, including the supervisor-level ones.
         let pending = self.state.read(MIE) & self.state.read(MIP);
 
-        if (pending & MEIP_BIT) != 0 {
-            self.state.write(MIP, self.state.read(MIP) & !MEIP_BIT);
-            return Some(Interrupt::MachineExternalInterrupt);
-        }
-        if (pending & MSIP_BIT) != 0 {
-            self.state.write(MIP, self.state.read(MIP) & !MSIP_BIT);
-            return Some(Interrupt::MachineSoftwareInterrupt);
-        }
-        if (pending & MTIP_BIT) != 0 {
-            self.state.write(MIP, self.state.read(MIP) & !MTIP_BIT);
-            return Some(Interrupt::MachineTimerInterrupt);
-        }
+        // "Multiple simultaneous interrupts destined for M-mode are handled in the following
+        // decreasing priority order: MEI, MSI, MTI, SEI, SSI, STI."
+        // The pending bits are owned by their sources, so taking an interrupt doesn't clear them.
+        Interrupt::PRIORITY
+            .into_iter()
+            .find(|interrupt| pending & interrupt.pending_bit() != 0)
+    }
 
-        return None;
+    /// Read a CSR for a CSR instruction. The value of a CSR that depends on when the hart ran is
+    /// journaled when the run is recorded or replayed.
+    fn read_csr(&mut self, bus: &mut Bus, addr: CsrAddress) -> u32 {
+        // The time CSR is a read-only shadow of the memory-mapped MTIME register of the CLINT.
+        let value = match addr {
+            TIME => bus.clint.mtime() as u32,
+            TIMEH => (bus.clint.mtime() >> 32) as u32,
+            _ => self.state.read(addr),
+        };
+        match bus.journal.as_mut() {
+            Some(journal) if is_volatile_csr(addr) => journal.csr(value),
+            _ => value,
+        }
     }
 
     /// Read `size`-bit data from the system bus.
-    fn read(&mut self, addr: u32, size: u8) -> Result<u32, Exception> {
+    pub(crate) fn read(&mut self, bus: &mut Bus, addr: u32, size: u8) -> Result<u32, Exception> {
+        self.check_triggers(AccessType::Load, addr, size)?;
         let previous_mode = self.mode;
 
         // 3.1.6.3 Memory Privilege in mstatus Register
         // "When MPRV=1, load and store memory addresses are translated and protected, and
         // endianness is applied, as though the current privilege mode were set to MPP."
-        if self.state.read_mstatus(MSTATUS_MPRV) == 1 {
-            self.mode = match self.state.read_mstatus(MSTATUS_MPP) {
-                0b00 => Mode::User,
-                0b11 => Mode::Machine,
-                _ => Mode::Debug,
-            };
+        if self.is_mprv_effective() {
+            self.mode = self.privilege_mode(self.state.read_mstatus(MSTATUS_MPP));
         }
 
-        let result = self.bus.read(addr, size);
+        let result = bus.load(self.hartid(), addr, size);
+
+        self.mode = previous_mode;
 
-        if self.state.read_mstatus(MSTATUS_MPRV) == 1 {
-            self.mode = previous_mode;
+        if result.is_ok() {
+            self.state.increment_event(HPM_EVENT_LOAD);
         }
 
         result
@@ -301,66 +357,268 @@ impl Cpu {
 
     /// Write `size`-bit data to the system bus with the translation a virtual address to a physical
     /// address if it is enabled.
-    fn write(&mut self, addr: u32, value: u32, size: u8) -> Result<(), Exception> {
+    pub(crate) fn write(
+        &mut self,
+        bus: &mut Bus,
+        addr: u32,
+        value: u32,
+        size: u8,
+    ) -> Result<(), Exception> {
+        self.check_triggers(AccessType::Store, addr, size)?;
         let previous_mode = self.mode;
 
         // 3.1.6.3 Memory Privilege in mstatus Register
         // "When MPRV=1, load and store memory addresses are translated and protected, and
         // endianness is applied, as though the current privilege mode were set to MPP."
-        if self.state.read_mstatus(MSTATUS_MPRV) == 1 {
-            self.mode = match self.state.read_mstatus(MSTATUS_MPP) {
-                0b00 => Mode::User,
-                0b11 => Mode::Machine,
-                _ => Mode::Debug,
-            };
+        if self.is_mprv_effective() {
+            self.mode = self.privilege_mode(self.state.read_mstatus(MSTATUS_MPP));
         }
 
-        // "The SC must fail if a write from some other device to the bytes accessed by the LR can
-        // be observed to occur between the LR and SC."
-        if self.reservation_set.contains(&addr) {
-            self.reservation_set.retain(|&x| x != addr);
-        }
+        let result = bus.store(self.hartid(), addr, value, size);
 
-        let result = self.bus.write(addr, value, size);
+        self.mode = previous_mode;
 
-        if self.state.read_mstatus(MSTATUS_MPRV) == 1 {
-            self.mode = previous_mode;
+        if result.is_ok() {
+            self.state.increment_event(HPM_EVENT_STORE);
         }
 
         result
     }
 
-    pub fn fetch(&mut self) -> Result<u32, Exception> {
-        // The result of the read method can be `Exception::LoadAccessFault`. In fetch(), an error
-        // should be `Exception::InstructionAccessFault`.
-        match self.bus.read(self.pc, WORD) {
-            Ok(value) => Ok(value),
-            Err(_) => Err(Exception::InstructionAccessFault),
+    /// Fire the triggers that match an access of `size` bits at `addr` before the access happens.
+    /// A trigger that enters debug mode aborts the instruction with a breakpoint exception that
+    /// `execute` discards, since the hart is halted instead of trapping.
+    fn check_triggers(&mut self, access: AccessType, addr: u32, size: u8) -> Result<(), Exception> {
+        let len = size as u32 / BYTE as u32;
+        match self.state.triggers.check(access, addr, len, self.mode) {
+            None => Ok(()),
+            Some(TriggerAction::Breakpoint) => Err(Exception::Breakpoint(addr)),
+            Some(TriggerAction::EnterDebugMode) => {
+                DebugCause::Trigger.enter(self);
+                Err(Exception::Breakpoint(addr))
+            }
+        }
+    }
+
+    /// Write a value to a CSR with the instruction set. The trigger data registers of a trigger
+    /// with `dmode` set can only be written in debug mode, and only debug mode can set `dmode`.
+    fn write_csr(&mut self, csr_addr: u16, value: u32) {
+        let mut value = value;
+        if (TDATA1..=TDATA3).contains(&csr_addr) && !self.is_halted() {
+            if self.state.triggers.is_dmode_selected() {
+                return;
+            }
+            if csr_addr == TDATA1 {
+                value &= !(1 << TDATA1_DMODE.start());
+            }
+        }
+        self.state.write(csr_addr, value);
+    }
+
+    /// Return true if loads and stores are done with the privilege mode in MPP. In debug mode,
+    /// MPRV is only honored if `dcsr.mprven` is set.
+    fn is_mprv_effective(&self) -> bool {
+        self.state.read_mstatus(MSTATUS_MPRV) == 1
+            && (self.mode != Mode::Debug || self.state.read_bits(DCSR, DCSR_MPRVEN) == 1)
+    }
+
+    /// Set the program counter to a jump or branch target. The program counter is incremented
+    /// after the instruction, so the target is stored minus 4 bytes.
+    fn jump(&mut self, target: u32) -> Result<(), Exception> {
+        // "An instruction-address-misaligned exception is generated on a taken branch or
+        // unconditional jump if the target address is not four-byte aligned."
+        if target & 0b11 != 0 {
+            return Err(Exception::InstructionAddressMisaligned(target));
+        }
+        self.pc = target.wrapping_sub(4);
+        Ok(())
+    }
+
+    /// Raise an illegal instruction exception if the CSR instruction `inst` accesses `csr_addr`
+    /// that doesn't exist, with a privilege mode that can't access it, writes a read-only CSR, or
+    /// accesses a debug mode register or a user-level counter that the current privilege mode
+    /// can't access.
+    fn check_csr_access(&mut self, csr_addr: u16, inst: u32) -> Result<(), Exception> {
+        // "Attempts to access a non-existent CSR raise an illegal instruction exception."
+        if !exists(csr_addr, &self.config) {
+            return Err(self.illegal("unknown csr", inst));
         }
+
+        // 2.1 CSR Address Mapping Conventions
+        // "Attempts to access a CSR without appropriate privilege level raise illegal instruction
+        // exceptions." Debug mode has M-mode privileges.
+        let level = match self.mode {
+            Mode::User => 0,
+            Mode::Machine | Mode::Debug => 3,
+        };
+        if level < privilege_level(csr_addr) {
+            return Err(self.illegal("csr privilege", inst));
+        }
+
+        // "Attempts to write a read-only register raise illegal instruction exceptions." CSRRS
+        // and CSRRC with rs1=x0, and CSRRSI and CSRRCI with a zero immediate, don't write.
+        let funct3 = (inst & 0x00007000) >> 12;
+        let rs1 = (inst & 0x000f8000) >> 15;
+        let writes = matches!(funct3, 0x1 | 0x5) || rs1 != 0;
+        if writes && is_read_only(csr_addr) {
+            return Err(self.illegal("csr read-only write", inst));
+        }
+
+        // 4.9 Core Debug Registers
+        // "These registers are only accessible from Debug Mode."
+        if is_debug_register(csr_addr) && self.mode != Mode::Debug {
+            return Err(self.illegal("debug csr outside debug mode", inst));
+        }
+
+        let index = match csr_addr {
+            CYCLE..=0xc1f => csr_addr - CYCLE,
+            CYCLEH..=0xc9f => csr_addr - CYCLEH,
+            _ => return Ok(()),
+        };
+        // 3.1.11 Machine Counter-Enable Register (mcounteren)
+        // "When the CY, TM, IR, or HPMn bit in the mcounteren register is clear, attempts to read
+        // the cycle, time, instret, or hpmcountern register while executing in U-mode will cause
+        // an illegal instruction exception."
+        if self.mode == Mode::User && self.state.read_bit(MCOUNTEREN, index as usize) == 0 {
+            return Err(self.illegal("counter disabled by mcounteren", inst));
+        }
+        Ok(())
+    }
+
+    /// Take a conditional branch to `target`.
+    fn branch(&mut self, target: u32) -> Result<(), Exception> {
+        self.jump(target)?;
+        self.state.increment_event(HPM_EVENT_BRANCH_TAKEN);
+        Ok(())
+    }
+
+    pub fn fetch(&mut self, bus: &mut Bus) -> Result<u32, Exception> {
+        bus.fetch(self.hartid(), self.pc)
     }
 
     /// Execute a cycle on peripheral devices.
-    pub fn devices_increment(&mut self) {
-        // TODO: mtime in Clint and TIME in CSR should be the same value.
-        // Increment the timer register (mtimer) in Clint.
-        // self.bus.clint.increment(&mut self.state);
-        // Increment the value in the TIME and CYCLE registers in CSR.
-        self.state.increment_time();
+    pub fn devices_increment(&mut self, bus: &Bus) {
+        self.advance_counters(1);
+        self.update_pending_interrupts(bus);
+    }
+
+    /// Set the pending bits of the interrupts raised by the devices.
+    pub fn update_pending_interrupts(&mut self, bus: &Bus) {
+        // The software and timer interrupts of the CLINT and the device interrupts are
+        // level-triggered, so the pending bits follow the devices.
+        let hartid = self.hartid();
+        let mut mip = self.state.read(MIP) & !(MSIP_BIT | MTIP_BIT | MEIP_BIT);
+        if bus.clint.is_software_interrupting(hartid) {
+            mip |= MSIP_BIT;
+        }
+        if bus.clint.is_timer_interrupting(hartid) {
+            mip |= MTIP_BIT;
+        }
+        if bus.is_external_interrupting(hartid) {
+            mip |= MEIP_BIT;
+        }
+        self.state.set_mip(mip);
+    }
+
+    /// Add `cycles` to the CYCLE register in CSR, unless it is stopped in debug mode.
+    pub fn advance_counters(&mut self, cycles: u64) {
+        if !self.is_halted() || self.state.read_bits(DCSR, DCSR_STOPCOUNT) == 0 {
+            self.state.advance_cycle(cycles);
+        }
+    }
+
+    /// Return true if the hart is idle in WFI and no locally enabled interrupt is pending to wake
+    /// it up.
+    pub fn is_waiting_for_interrupt(&self) -> bool {
+        self.idle && self.state.read(MIE) & self.state.read(MIP) == 0
+    }
+
+    /// Request the hart to halt. The hart enters debug mode before the next instruction.
+    pub fn halt(&mut self) {
+        if !self.is_halted() {
+            DebugCause::HaltRequest.enter(self);
+        }
+    }
+
+    /// Return true if the hart is halted in debug mode.
+    pub fn is_halted(&self) -> bool {
+        self.mode == Mode::Debug
+    }
+
+    /// Return true if the hart is single-stepping: it runs outside debug mode with `dcsr.step`
+    /// set, and halts again after the next instruction.
+    pub fn is_stepping(&self) -> bool {
+        !self.is_halted() && self.state.read_bits(DCSR, DCSR_STEP) == 1
+    }
+
+    /// Execute an instruction on behalf of the debugger while the hart is halted, like an
+    /// instruction in the program buffer of a debug module. The program counter is left at DPC
+    /// unless the instruction is `dret`. An exception doesn't trap and the hart stays halted.
+    pub fn execute_debug(&mut self, bus: &mut Bus, inst: u32) -> Result<(), Exception> {
+        if !self.is_halted() {
+            return Err(Exception::IllegalInstruction(inst));
+        }
+
+        let pc = self.pc;
+        let result = self.execute_general(bus, inst);
+        if self.is_halted() {
+            self.pc = pc;
+        } else {
+            // dret resumes at DPC.
+            self.pc = self.pc.wrapping_add(4);
+        }
+        result
+    }
+
+    /// Return the ID of this hart.
+    pub fn hartid(&self) -> u32 {
+        self.state.read(MHARTID)
     }
 
     /// Execute an instruction. Raises an exception if something is wrong, otherwise, returns
     /// the instruction executed in this cycle.
-    pub fn execute(&mut self) -> Result<u32, Exception> {
-        // WFI is called and pending interrupts don't exist.
+    pub fn execute(&mut self, bus: &mut Bus) -> Result<u32, Exception> {
         if self.idle {
+            // 3.3.3 Wait for Interrupt
+            // "The WFI instruction can also be executed when interrupts are disabled. ... if any
+            // locally enabled interrupt becomes pending, WFI resumes execution."
+            if self.state.read(MIE) & self.state.read(MIP) == 0 {
+                // WFI is called and pending interrupts don't exist.
+                return Ok(0);
+            }
+            self.idle = false;
+        }
+        // A halted hart only executes the instructions given by `execute_debug`.
+        if self.is_halted() {
             return Ok(0);
         }
 
+        // Execute triggers fire before the instruction is fetched.
+        if let Err(exception) = self.check_triggers(AccessType::Instruction, self.pc, WORD) {
+            return if self.is_halted() {
+                Ok(0)
+            } else {
+                Err(exception)
+            };
+        }
+
         // Fetch.
-        let inst = self.fetch()?;
-        self.execute_general(inst)?;
-        // Add 4 bytes to the program counter.
-        self.pc += 4;
+        let inst = self.fetch(bus)?;
+        if let Err(exception) = self.execute_general(bus, inst) {
+            // A load or store trigger that entered debug mode aborted the instruction.
+            return if self.is_halted() {
+                Ok(inst)
+            } else {
+                Err(exception)
+            };
+        }
+        // An ebreak that entered debug mode doesn't retire, and DPC points to it.
+        if self.is_halted() {
+            return Ok(inst);
+        }
+        self.state.increment_instret();
+        // Add 4 bytes to the program counter. A jump to address 0 left it at 0xffff_fffc.
+        self.pc = self.pc.wrapping_add(4);
 
         self.pre_inst = inst;
         Ok(inst)
@@ -368,7 +626,7 @@ impl Cpu {
 
     /// Execute a general-purpose instruction. Raises an exception if something is wrong,
     /// otherwise, returns a fetched instruction. It also increments the program counter by 4 bytes.
-    fn execute_general(&mut self, inst: u32) -> Result<(), Exception> {
+    fn execute_general(&mut self, bus: &mut Bus, inst: u32) -> Result<(), Exception> {
         // 2. Decode.
         let opcode = inst & 0x0000007f;
         let rd = (inst & 0x00000f80) >> 7;
@@ -377,6 +635,8 @@ impl Cpu {
         let funct3 = (inst & 0x00007000) >> 12;
         let funct7 = (inst & 0xfe000000) >> 25;
 
+        self.check_registers(inst)?;
+
         // 3. Execute.
         match opcode {
             0x03 => {
@@ -388,45 +648,40 @@ impl Cpu {
                     0x0 => {
                         // lb
                         inst_count!(self, "lb");
-                        self.debug(inst, "lb");
 
-                        let val = self.read(addr, BYTE)?;
+                        let val = self.read(bus, addr, BYTE)?;
                         self.xregs.write(rd, val as i8 as i32 as u32);
                     }
                     0x1 => {
                         // lh
                         inst_count!(self, "lh");
-                        self.debug(inst, "lh");
 
-                        let val = self.read(addr, HALFWORD)?;
+                        let val = self.read(bus, addr, HALFWORD)?;
                         self.xregs.write(rd, val as i16 as i32 as u32);
                     }
                     0x2 => {
                         // lw
                         inst_count!(self, "lw");
-                        self.debug(inst, "lw");
 
-                        let val = self.read(addr, WORD)?;
+                        let val = self.read(bus, addr, WORD)?;
                         self.xregs.write(rd, val as i32 as u32);
                     }
                     0x4 => {
                         // lbu
                         inst_count!(self, "lbu");
-                        self.debug(inst, "lbu");
 
-                        let val = self.read(addr, BYTE)?;
+                        let val = self.read(bus, addr, BYTE)?;
                         self.xregs.write(rd, val);
                     }
                     0x5 => {
                         // lhu
                         inst_count!(self, "lhu");
-                        self.debug(inst, "lhu");
 
-                        let val = self.read(addr, HALFWORD)?;
+                        let val = self.read(bus, addr, HALFWORD)?;
                         self.xregs.write(rd, val);
                     }
                     _ => {
-                        return Err(Exception::IllegalInstruction(inst));
+                        return Err(self.illegal("load funct3", inst));
                     }
                 }
             }
@@ -439,15 +694,14 @@ impl Cpu {
                     0x0 => {
                         // fence
                         inst_count!(self, "fence");
-                        self.debug(inst, "fence");
                     }
                     0x1 => {
                         // fence.i
                         inst_count!(self, "fence.i");
-                        self.debug(inst, "fence.i");
+                        self.require(Extension::Zifencei, inst)?;
                     }
                     _ => {
-                        return Err(Exception::IllegalInstruction(inst));
+                        return Err(self.illegal("misc-mem funct3", inst));
                     }
                 }
             }
@@ -455,19 +709,22 @@ impl Cpu {
                 // RV32I and RV64I
                 // imm[11:0] = inst[31:20]
                 let imm = ((inst as i32) >> 20) as u32;
-                let funct6 = funct7 >> 1;
                 match funct3 {
                     0x0 => {
                         // addi
                         inst_count!(self, "addi");
-                        self.debug(inst, "addi");
 
                         self.xregs.write(rd, self.xregs.read(rs1).wrapping_add(imm));
                     }
                     0x1 => {
                         // slli
                         inst_count!(self, "slli");
-                        self.debug(inst, "slli");
+
+                        // "SLLI, SRLI, and SRAI generate an illegal instruction exception if
+                        // imm[5] ≠ 0."
+                        if funct7 != 0x00 {
+                            return Err(self.illegal("slli funct7", inst));
+                        }
 
                         // shamt size is 5 bits for RV32I and 6 bits for RV64I.
                         let shamt = (inst >> 20) & 0x1f;
@@ -476,7 +733,6 @@ impl Cpu {
                     0x2 => {
                         // slti
                         inst_count!(self, "slti");
-                        self.debug(inst, "slti");
 
                         self.xregs.write(
                             rd,
@@ -490,7 +746,6 @@ impl Cpu {
                     0x3 => {
                         // sltiu
                         inst_count!(self, "sltiu");
-                        self.debug(inst, "sltiu");
 
                         self.xregs
                             .write(rd, if self.xregs.read(rs1) < imm { 1 } else { 0 });
@@ -498,25 +753,24 @@ impl Cpu {
                     0x4 => {
                         // xori
                         inst_count!(self, "xori");
-                        self.debug(inst, "xori");
 
                         self.xregs.write(rd, self.xregs.read(rs1) ^ imm);
                     }
                     0x5 => {
-                        match funct6 {
+                        // Unlike RV64I, imm[5] of SRLI and SRAI must be zero, so the whole funct7
+                        // field selects the instruction.
+                        match funct7 {
                             0x00 => {
                                 // srli
                                 inst_count!(self, "srli");
-                                self.debug(inst, "srli");
 
                                 // shamt size is 5 bits for RV32I and 6 bits for RV64I.
                                 let shamt = (inst >> 20) & 0x1f;
                                 self.xregs.write(rd, self.xregs.read(rs1) >> shamt);
                             }
-                            0x10 => {
+                            0x20 => {
                                 // srai
                                 inst_count!(self, "srai");
-                                self.debug(inst, "srai");
 
                                 // shamt size is 5 bits for RV32I and 6 bits for RV64I.
                                 let shamt = (inst >> 20) & 0x1f;
@@ -524,26 +778,24 @@ impl Cpu {
                                     .write(rd, ((self.xregs.read(rs1) as i32) >> shamt) as u32);
                             }
                             _ => {
-                                return Err(Exception::IllegalInstruction(inst));
+                                return Err(self.illegal("srli/srai funct7", inst));
                             }
                         }
                     }
                     0x6 => {
                         // ori
                         inst_count!(self, "ori");
-                        self.debug(inst, "ori");
 
                         self.xregs.write(rd, self.xregs.read(rs1) | imm);
                     }
                     0x7 => {
                         // andi
                         inst_count!(self, "andi");
-                        self.debug(inst, "andi");
 
                         self.xregs.write(rd, self.xregs.read(rs1) & imm);
                     }
                     _ => {
-                        return Err(Exception::IllegalInstruction(inst));
+                        return Err(self.illegal("op-imm funct3", inst));
                     }
                 }
             }
@@ -551,7 +803,6 @@ impl Cpu {
                 // RV32I
                 // auipc
                 inst_count!(self, "auipc");
-                self.debug(inst, "auipc");
 
                 // AUIPC forms a 32-bit offset from the 20-bit U-immediate, filling
                 // in the lowest 12 bits with zeros.
@@ -569,36 +820,202 @@ impl Cpu {
                     0x0 => {
                         // sb
                         inst_count!(self, "sb");
-                        self.debug(inst, "sb");
 
-                        self.write(addr, self.xregs.read(rs2), BYTE)?
+                        self.write(bus, addr, self.xregs.read(rs2), BYTE)?
                     }
                     0x1 => {
                         // sh
                         inst_count!(self, "sh");
-                        self.debug(inst, "sh");
 
-                        self.write(addr, self.xregs.read(rs2), HALFWORD)?
+                        self.write(bus, addr, self.xregs.read(rs2), HALFWORD)?
                     }
                     0x2 => {
                         // sw
                         inst_count!(self, "sw");
-                        self.debug(inst, "sw");
 
-                        self.write(addr, self.xregs.read(rs2), WORD)?
+                        self.write(bus, addr, self.xregs.read(rs2), WORD)?
+                    }
+                    _ => {
+                        return Err(self.illegal("store funct3", inst));
+                    }
+                }
+            }
+            0x2f => {
+                // RV32A: "A" standard extension for atomic instructions
+                self.require(Extension::A, inst)?;
+                // The aq/rl bits (inst[26:25]) only constrain memory ordering, which is trivially
+                // satisfied because this emulator performs every memory access in program order.
+                let funct5 = funct7 >> 2;
+                match (funct3, funct5) {
+                    (0x2, 0x00) => {
+                        // amoadd.w
+                        inst_count!(self, "amoadd.w");
+
+                        let addr = self.xregs.read(rs1);
+                        if addr & 0b11 != 0 {
+                            return Err(Exception::StoreAMOAddressMisaligned(addr));
+                        }
+                        let t = self.read(bus, addr, WORD)?;
+                        self.write(bus, addr, t.wrapping_add(self.xregs.read(rs2)), WORD)?;
+                        self.xregs.write(rd, t);
+                    }
+                    (0x2, 0x01) => {
+                        // amoswap.w
+                        inst_count!(self, "amoswap.w");
+
+                        let addr = self.xregs.read(rs1);
+                        if addr & 0b11 != 0 {
+                            return Err(Exception::StoreAMOAddressMisaligned(addr));
+                        }
+                        let t = self.read(bus, addr, WORD)?;
+                        self.write(bus, addr, self.xregs.read(rs2), WORD)?;
+                        self.xregs.write(rd, t);
+                    }
+                    (0x2, 0x02) => {
+                        // lr.w
+                        inst_count!(self, "lr.w");
+
+                        let addr = self.xregs.read(rs1);
+                        // "For LR and SC, the A extension requires that the address held in rs1
+                        // be naturally aligned to the size of the operand."
+                        if addr & 0b11 != 0 {
+                            return Err(Exception::LoadAddressMisaligned(addr));
+                        }
+                        let value = self.read(bus, addr, WORD)?;
+                        self.xregs.write(rd, value);
+                        // "LR.W loads a word from the address in rs1 ... and registers a
+                        // reservation set—a set of bytes that subsumes the bytes in the addressed
+                        // word." A hart only holds a single reservation at a time.
+                        bus.reserve(self.hartid(), addr);
+                    }
+                    (0x2, 0x03) => {
+                        // sc.w
+                        inst_count!(self, "sc.w");
+
+                        let addr = self.xregs.read(rs1);
+                        if addr & 0b11 != 0 {
+                            return Err(Exception::StoreAMOAddressMisaligned(addr));
+                        }
+                        // "Regardless of success or failure, executing an SC.W instruction
+                        // invalidates any reservation held by this hart."
+                        let is_reserved = bus.take_reservation(self.hartid(), addr);
+                        if is_reserved {
+                            // "SC.W writes zero to rd on success"
+                            self.write(bus, addr, self.xregs.read(rs2), WORD)?;
+                            self.xregs.write(rd, 0);
+                        } else {
+                            // "or a nonzero code on failure."
+                            self.xregs.write(rd, 1);
+                        }
+                    }
+                    (0x2, 0x04) => {
+                        // amoxor.w
+                        inst_count!(self, "amoxor.w");
+
+                        let addr = self.xregs.read(rs1);
+                        if addr & 0b11 != 0 {
+                            return Err(Exception::StoreAMOAddressMisaligned(addr));
+                        }
+                        let t = self.read(bus, addr, WORD)?;
+                        self.write(bus, addr, t ^ self.xregs.read(rs2), WORD)?;
+                        self.xregs.write(rd, t);
+                    }
+                    (0x2, 0x08) => {
+                        // amoor.w
+                        inst_count!(self, "amoor.w");
+
+                        let addr = self.xregs.read(rs1);
+                        if addr & 0b11 != 0 {
+                            return Err(Exception::StoreAMOAddressMisaligned(addr));
+                        }
+                        let t = self.read(bus, addr, WORD)?;
+                        self.write(bus, addr, t | self.xregs.read(rs2), WORD)?;
+                        self.xregs.write(rd, t);
+                    }
+                    (0x2, 0x0c) => {
+                        // amoand.w
+                        inst_count!(self, "amoand.w");
+
+                        let addr = self.xregs.read(rs1);
+                        if addr & 0b11 != 0 {
+                            return Err(Exception::StoreAMOAddressMisaligned(addr));
+                        }
+                        let t = self.read(bus, addr, WORD)?;
+                        self.write(bus, addr, t & self.xregs.read(rs2), WORD)?;
+                        self.xregs.write(rd, t);
+                    }
+                    (0x2, 0x10) => {
+                        // amomin.w
+                        inst_count!(self, "amomin.w");
+
+                        let addr = self.xregs.read(rs1);
+                        if addr & 0b11 != 0 {
+                            return Err(Exception::StoreAMOAddressMisaligned(addr));
+                        }
+                        let t = self.read(bus, addr, WORD)?;
+                        self.write(
+                            bus,
+                            addr,
+                            cmp::min(t as i32, self.xregs.read(rs2) as i32) as u32,
+                            WORD,
+                        )?;
+                        self.xregs.write(rd, t);
+                    }
+                    (0x2, 0x14) => {
+                        // amomax.w
+                        inst_count!(self, "amomax.w");
+
+                        let addr = self.xregs.read(rs1);
+                        if addr & 0b11 != 0 {
+                            return Err(Exception::StoreAMOAddressMisaligned(addr));
+                        }
+                        let t = self.read(bus, addr, WORD)?;
+                        self.write(
+                            bus,
+                            addr,
+                            cmp::max(t as i32, self.xregs.read(rs2) as i32) as u32,
+                            WORD,
+                        )?;
+                        self.xregs.write(rd, t);
+                    }
+                    (0x2, 0x18) => {
+                        // amominu.w
+                        inst_count!(self, "amominu.w");
+
+                        let addr = self.xregs.read(rs1);
+                        if addr & 0b11 != 0 {
+                            return Err(Exception::StoreAMOAddressMisaligned(addr));
+                        }
+                        let t = self.read(bus, addr, WORD)?;
+                        self.write(bus, addr, cmp::min(t, self.xregs.read(rs2)), WORD)?;
+                        self.xregs.write(rd, t);
+                    }
+                    (0x2, 0x1c) => {
+                        // amomaxu.w
+                        inst_count!(self, "amomaxu.w");
+
+                        let addr = self.xregs.read(rs1);
+                        if addr & 0b11 != 0 {
+                            return Err(Exception::StoreAMOAddressMisaligned(addr));
+                        }
+                        let t = self.read(bus, addr, WORD)?;
+                        self.write(bus, addr, cmp::max(t, self.xregs.read(rs2)), WORD)?;
+                        self.xregs.write(rd, t);
                     }
                     _ => {
-                        return Err(Exception::IllegalInstruction(inst));
+                        return Err(self.illegal("amo funct5", inst));
                     }
                 }
             }
             0x33 => {
-                // RV32M
+                // RV32I and RV32M
+                if funct7 == 0x01 {
+                    self.require(Extension::M, inst)?;
+                }
                 match (funct3, funct7) {
                     (0x0, 0x00) => {
                         // add
                         inst_count!(self, "add");
-                        self.debug(inst, "add");
 
                         self.xregs
                             .write(rd, self.xregs.read(rs1).wrapping_add(self.xregs.read(rs2)));
@@ -606,7 +1023,6 @@ impl Cpu {
                     (0x0, 0x01) => {
                         // mul
                         inst_count!(self, "mul");
-                        self.debug(inst, "mul");
 
                         self.xregs.write(
                             rd,
@@ -617,7 +1033,6 @@ impl Cpu {
                     (0x0, 0x20) => {
                         // sub
                         inst_count!(self, "sub");
-                        self.debug(inst, "sub");
 
                         self.xregs
                             .write(rd, self.xregs.read(rs1).wrapping_sub(self.xregs.read(rs2)));
@@ -625,7 +1040,6 @@ impl Cpu {
                     (0x1, 0x00) => {
                         // sll
                         inst_count!(self, "sll");
-                        self.debug(inst, "sll");
 
                         // "SLL, SRL, and SRA perform logical left, logical right, and arithmetic right shifts on the value in
                         // register rs1 by the shift amount held in the lower 5 bits of register rs2."
@@ -635,20 +1049,18 @@ impl Cpu {
                     (0x1, 0x01) => {
                         // mulh
                         inst_count!(self, "mulh");
-                        self.debug(inst, "mulh");
 
                         // signed × signed
                         self.xregs.write(
                             rd,
-                            ((self.xregs.read(rs1) as i32 as i128)
-                                .wrapping_mul(self.xregs.read(rs2) as i32 as i128)
-                                >> 64) as u32,
+                            ((self.xregs.read(rs1) as i32 as i64)
+                                .wrapping_mul(self.xregs.read(rs2) as i32 as i64)
+                                >> 32) as u32,
                         );
                     }
                     (0x2, 0x00) => {
                         // slt
                         inst_count!(self, "slt");
-                        self.debug(inst, "slt");
 
                         self.xregs.write(
                             rd,
@@ -662,20 +1074,18 @@ impl Cpu {
                     (0x2, 0x01) => {
                         // mulhsu
                         inst_count!(self, "mulhsu");
-                        self.debug(inst, "mulhsu");
 
                         // signed × unsigned
                         self.xregs.write(
                             rd,
-                            ((self.xregs.read(rs1) as i32 as i128 as u128)
-                                .wrapping_mul(self.xregs.read(rs2) as u128)
-                                >> 64) as u32,
+                            ((self.xregs.read(rs1) as i32 as i64 as u64)
+                                .wrapping_mul(self.xregs.read(rs2) as u64)
+                                >> 32) as u32,
                         );
                     }
                     (0x3, 0x00) => {
                         // sltu
                         inst_count!(self, "sltu");
-                        self.debug(inst, "sltu");
 
                         self.xregs.write(
                             rd,
@@ -689,20 +1099,18 @@ impl Cpu {
                     (0x3, 0x01) => {
                         // mulhu
                         inst_count!(self, "mulhu");
-                        self.debug(inst, "mulhu");
 
                         // unsigned × unsigned
                         self.xregs.write(
                             rd,
-                            ((self.xregs.read(rs1) as u128)
-                                .wrapping_mul(self.xregs.read(rs2) as u128)
-                                >> 64) as u32,
+                            ((self.xregs.read(rs1) as u64)
+                                .wrapping_mul(self.xregs.read(rs2) as u64)
+                                >> 32) as u32,
                         );
                     }
                     (0x4, 0x00) => {
                         // xor
                         inst_count!(self, "xor");
-                        self.debug(inst, "xor");
 
                         self.xregs
                             .write(rd, self.xregs.read(rs1) ^ self.xregs.read(rs2));
@@ -710,7 +1118,6 @@ impl Cpu {
                     (0x4, 0x01) => {
                         // div
                         inst_count!(self, "div");
-                        self.debug(inst, "div");
 
                         let dividend = self.xregs.read(rs1) as i32;
                         let divisor = self.xregs.read(rs2) as i32;
@@ -736,7 +1143,6 @@ impl Cpu {
                     (0x5, 0x00) => {
                         // srl
                         inst_count!(self, "srl");
-                        self.debug(inst, "srl");
 
                         // "SLL, SRL, and SRA perform logical left, logical right, and arithmetic right shifts on the value in
                         // register rs1 by the shift amount held in the lower 5 bits of register rs2."
@@ -746,7 +1152,6 @@ impl Cpu {
                     (0x5, 0x01) => {
                         // divu
                         inst_count!(self, "divu");
-                        self.debug(inst, "divu");
 
                         let dividend = self.xregs.read(rs1);
                         let divisor = self.xregs.read(rs2);
@@ -767,7 +1172,6 @@ impl Cpu {
                     (0x5, 0x20) => {
                         // sra
                         inst_count!(self, "sra");
-                        self.debug(inst, "sra");
 
                         // "SLL, SRL, and SRA perform logical left, logical right, and arithmetic right shifts on the value in
                         // register rs1 by the shift amount held in the lower 5 bits of register rs2."
@@ -778,7 +1182,6 @@ impl Cpu {
                     (0x6, 0x00) => {
                         // or
                         inst_count!(self, "or");
-                        self.debug(inst, "or");
 
                         self.xregs
                             .write(rd, self.xregs.read(rs1) | self.xregs.read(rs2));
@@ -786,7 +1189,6 @@ impl Cpu {
                     (0x6, 0x01) => {
                         // rem
                         inst_count!(self, "rem");
-                        self.debug(inst, "rem");
 
                         let dividend = self.xregs.read(rs1) as i32;
                         let divisor = self.xregs.read(rs2) as i32;
@@ -810,7 +1212,6 @@ impl Cpu {
                     (0x7, 0x00) => {
                         // and
                         inst_count!(self, "and");
-                        self.debug(inst, "and");
 
                         self.xregs
                             .write(rd, self.xregs.read(rs1) & self.xregs.read(rs2));
@@ -818,7 +1219,6 @@ impl Cpu {
                     (0x7, 0x01) => {
                         // remu
                         inst_count!(self, "remu");
-                        self.debug(inst, "remu");
 
                         let dividend = self.xregs.read(rs1);
                         let divisor = self.xregs.read(rs2);
@@ -836,7 +1236,7 @@ impl Cpu {
                         );
                     }
                     _ => {
-                        return Err(Exception::IllegalInstruction(inst));
+                        return Err(self.illegal("op funct3/funct7", inst));
                     }
                 };
             }
@@ -844,7 +1244,6 @@ impl Cpu {
                 // RV32I
                 // lui
                 inst_count!(self, "lui");
-                self.debug(inst, "lui");
 
                 // "LUI places the U-immediate value in the top 20 bits of the destination
                 // register rd, filling in the lowest 12 bits with zeros."
@@ -862,82 +1261,74 @@ impl Cpu {
                     0x0 => {
                         // beq
                         inst_count!(self, "beq");
-                        self.debug(inst, "beq");
 
                         if self.xregs.read(rs1) == self.xregs.read(rs2) {
-                            self.pc = self.pc.wrapping_add(imm).wrapping_sub(4);
+                            self.branch(self.pc.wrapping_add(imm))?;
                         }
                     }
                     0x1 => {
                         // bne
                         inst_count!(self, "bne");
-                        self.debug(inst, "bne");
 
                         if self.xregs.read(rs1) != self.xregs.read(rs2) {
-                            self.pc = self.pc.wrapping_add(imm).wrapping_sub(4);
+                            self.branch(self.pc.wrapping_add(imm))?;
                         }
                     }
                     0x4 => {
                         // blt
                         inst_count!(self, "blt");
-                        self.debug(inst, "blt");
 
                         if (self.xregs.read(rs1) as i32) < (self.xregs.read(rs2) as i32) {
-                            self.pc = self.pc.wrapping_add(imm).wrapping_sub(4);
+                            self.branch(self.pc.wrapping_add(imm))?;
                         }
                     }
                     0x5 => {
                         // bge
                         inst_count!(self, "bge");
-                        self.debug(inst, "bge");
 
                         if (self.xregs.read(rs1) as i32) >= (self.xregs.read(rs2) as i32) {
-                            self.pc = self.pc.wrapping_add(imm).wrapping_sub(4);
+                            self.branch(self.pc.wrapping_add(imm))?;
                         }
                     }
                     0x6 => {
                         // bltu
                         inst_count!(self, "bltu");
-                        self.debug(inst, "bltu");
 
                         if self.xregs.read(rs1) < self.xregs.read(rs2) {
-                            self.pc = self.pc.wrapping_add(imm).wrapping_sub(4);
+                            self.branch(self.pc.wrapping_add(imm))?;
                         }
                     }
                     0x7 => {
                         // bgeu
                         inst_count!(self, "bgeu");
-                        self.debug(inst, "bgeu");
 
                         if self.xregs.read(rs1) >= self.xregs.read(rs2) {
-                            self.pc = self.pc.wrapping_add(imm).wrapping_sub(4);
+                            self.branch(self.pc.wrapping_add(imm))?;
                         }
                     }
                     _ => {
-                        return Err(Exception::IllegalInstruction(inst));
+                        return Err(self.illegal("branch funct3", inst));
                     }
                 }
             }
             0x67 => {
                 // jalr
                 inst_count!(self, "jalr");
-                self.debug(inst, "jalr");
 
                 let t = self.pc.wrapping_add(4);
 
                 let offset = (inst as i32) >> 20;
                 let target = ((self.xregs.read(rs1) as i32).wrapping_add(offset)) & !1;
 
-                self.pc = (target as u32).wrapping_sub(4);
+                self.jump(target as u32)?;
 
                 self.xregs.write(rd, t);
             }
             0x6F => {
                 // jal
                 inst_count!(self, "jal");
-                self.debug(inst, "jal");
 
-                self.xregs.write(rd, self.pc.wrapping_add(4));
+                let t = self.pc.wrapping_add(4);
 
                 // imm[20|10:1|11|19:12] = inst[31|30:21|20|19:12]
                 let offset = (((inst & 0x80000000) as i32 >> 11) as u32) // imm[20]
@@ -945,18 +1336,23 @@ impl Cpu {
                     | ((inst >> 9) & 0x800) // imm[11]
                     | ((inst >> 20) & 0x7fe); // imm[10:1]
 
-                self.pc = self.pc.wrapping_add(offset).wrapping_sub(4);
+                self.jump(self.pc.wrapping_add(offset))?;
+
+                self.xregs.write(rd, t);
             }
             0x73 => {
                 // RV32I, RVZicsr, and supervisor ISA
                 let csr_addr = ((inst >> 20) & 0xfff) as u16;
+                if funct3 != 0x0 {
+                    self.require(Extension::Zicsr, inst)?;
+                    self.check_csr_access(csr_addr, inst)?;
+                }
                 match funct3 {
                     0x0 => {
                         match (rs2, funct7) {
                             (0x0, 0x0) => {
                                 // ecall
                                 inst_count!(self, "ecall");
-                                self.debug(inst, "ecall");
 
                                 // Makes a request of the execution environment by raising an
                                 // environment call exception.
@@ -968,29 +1364,47 @@ impl Cpu {
                                         return Err(Exception::EnvironmentCallFromMMode);
                                     }
                                     _ => {
-                                        return Err(Exception::IllegalInstruction(inst));
+                                        return Err(self.illegal("ecall in debug mode", inst));
                                     }
                                 }
                             }
                             (0x1, 0x0) => {
                                 // ebreak
                                 inst_count!(self, "ebreak");
-                                self.debug(inst, "ebreak");
+
+                                // 4.8.1 Debug Control and Status (dcsr)
+                                // "ebreakm: 1: ebreak instructions in M-mode enter Debug Mode."
+                                // "ebreaku: 1: ebreak instructions in U-mode enter Debug Mode."
+                                let ebreak_to_debug = match self.mode {
+                                    Mode::Machine => self.state.read_bits(DCSR, DCSR_EBREAKM),
+                                    Mode::User => self.state.read_bits(DCSR, DCSR_EBREAKU),
+                                    Mode::Debug => 0,
+                                };
+                                if ebreak_to_debug == 1 {
+                                    DebugCause::Ebreak.enter(self);
+                                    return Ok(());
+                                }
 
                                 // Makes a request of the debugger by raising a Breakpoint
                                 // exception.
-                                return Err(Exception::Breakpoint);
+                                return Err(Exception::Breakpoint(self.pc));
                             }
                             (0x2, 0x0) => {
                                 // uret
-                                inst_count!(self, "uret");
-                                self.debug(inst, "uret");
-                                panic!("uret: not implemented yet. pc {}", self.pc);
+                                // User-level traps are the N extension, which isn't implemented.
+                                return Err(self.illegal("uret without N", inst));
                             }
                             (0x2, 0x18) => {
                                 // mret
                                 inst_count!(self, "mret");
-                                self.debug(inst, "mret");
+
+                                // 3.3.2 Trap-Return Instructions
+                                // "An MRET or SRET instruction is used to return from a trap in
+                                // M-mode or S-mode respectively." MRET is only provided in M-mode,
+                                // so a less privileged mode can't use it to raise its privilege.
+                                if self.mode != Mode::Machine {
+                                    return Err(self.illegal("mret outside M-mode", inst));
+                                }
 
                                 // "The RISC-V Reader" book says:
                                 // "Returns from a machine-mode exception handler. Sets the pc to
@@ -1000,20 +1414,18 @@ impl Cpu {
                                 // 0".
 
                                 // Set the program counter to the machine exception program
-                                // counter (MEPC).
+                                // counter (MEPC). The program counter is incremented after this
+                                // instruction, so execution resumes exactly at MEPC.
                                 self.pc = self.state.read(MEPC).wrapping_sub(4);
 
                                 // Set the current privileged mode depending on a previous
                                 // privilege mode for machine  mode (MPP, 11..13).
-                                self.mode = match self.state.read_mstatus(MSTATUS_MPP) {
-                                    0b00 => {
-                                        // If MPP != M-mode, MRET also sets MPRV=0.
-                                        self.state.write_mstatus(MSTATUS_MPRV, 0);
-                                        Mode::User
-                                    }
-                                    0b11 => Mode::Machine,
-                                    _ => Mode::Debug,
-                                };
+                                self.mode =
+                                    self.privilege_mode(self.state.read_mstatus(MSTATUS_MPP));
+                                // If MPP != M-mode, MRET also sets MPRV=0.
+                                if self.mode != Mode::Machine {
+                                    self.state.write_mstatus(MSTATUS_MPRV, 0);
+                                }
 
                                 // Read a previous interrupt-enable bit for machine mode (MPIE, 7),
                                 // and set a global interrupt-enable bit for machine mode (MIE, 3)
@@ -1028,102 +1440,138 @@ impl Cpu {
                                 self.state.write_mstatus(MSTATUS_MPIE, 1);
 
                                 // Set a previous privilege mode for machine mode (MPP, 11..13) to
-                                // 0.
-                                self.state.write_mstatus(MSTATUS_MPP, Mode::User as u32);
+                                // the least-privileged supported mode.
+                                let least_privileged = self.privilege_mode(Mode::User as u32);
+                                self.state
+                                    .write_mstatus(MSTATUS_MPP, least_privileged as u32);
+                            }
+                            (0x12, 0x3d) => {
+                                // dret
+                                inst_count!(self, "dret");
+
+                                // 4.3 Resume
+                                // "dret is an instruction which only has meaning while Debug Mode
+                                // is active and should not be executed outside of Debug Mode."
+                                if !self.is_halted() {
+                                    return Err(self.illegal("dret outside debug mode", inst));
+                                }
+
+                                // "The pc changes to the value stored in dpc" and "the current
+                                // privilege mode and virtualization mode are changed to that
+                                // specified by prv and v."
+                                self.pc = self.state.read(DPC).wrapping_sub(4);
+                                self.mode =
+                                    self.privilege_mode(self.state.read_bits(DCSR, DCSR_PRV));
+                                // "If the new privilege mode is less privileged than M-mode, MPRV
+                                // in mstatus is cleared."
+                                if self.mode != Mode::Machine {
+                                    self.state.write_mstatus(MSTATUS_MPRV, 0);
+                                }
                             }
                             (0x5, 0x8) => {
                                 // wfi
                                 inst_count!(self, "wfi");
-                                self.debug(inst, "wfi");
                                 // "provides a hint to the implementation that the current
                                 // hart can be stalled until an interrupt might need servicing."
                                 self.idle = true;
                             }
                             (_, 0x9) => {
                                 // sfence.vma
-                                inst_count!(self, "sfence.vma");
-                                self.debug(inst, "sfence.vma");
-                                // "SFENCE.VMA is used to synchronize updates to in-memory
-                                // memory-management data structures with current execution"
-                            }
-                            (_, 0x11) => {
-                                // hfence.bvma
-                                inst_count!(self, "hfence.bvma");
-                                self.debug(inst, "hfence.bvma");
+                                // There is no address translation without S-mode, which isn't
+                                // implemented.
+                                return Err(self.illegal("sfence.vma without S", inst));
                             }
-                            (_, 0x51) => {
-                                // hfence.gvma
-                                inst_count!(self, "hfence.gvma");
-                                self.debug(inst, "hfence.gvma");
+                            (_, 0x11) | (_, 0x51) => {
+                                // hfence.bvma, hfence.gvma
+                                // The hypervisor extension isn't implemented.
+                                return Err(self.illegal("hfence without H", inst));
                             }
                             _ => {
-                                return Err(Exception::IllegalInstruction(inst));
+                                return Err(self.illegal("system funct7", inst));
                             }
                         }
                     }
                     0x1 => {
                         // csrrw
                         inst_count!(self, "csrrw");
-                        self.debug(inst, "csrrw");
 
-                        let t = self.state.read(csr_addr);
-                        self.state.write(csr_addr, self.xregs.read(rs1));
+                        let t = self.read_csr(bus, csr_addr);
+                        self.write_csr(csr_addr, self.xregs.read(rs1));
                         self.xregs.write(rd, t);
                     }
                     0x2 => {
                         // csrrs
                         inst_count!(self, "csrrs");
-                        self.debug(inst, "csrrs");
 
-                        let t = self.state.read(csr_addr);
-                        self.state.write(csr_addr, t | self.xregs.read(rs1));
+                        let t = self.read_csr(bus, csr_addr);
+                        // With rs1=x0, the CSR isn't written at all.
+                        if rs1 != 0 {
+                            self.write_csr(csr_addr, t | self.xregs.read(rs1));
+                        }
                         self.xregs.write(rd, t);
                     }
                     0x3 => {
                         // csrrc
                         inst_count!(self, "csrrc");
-                        self.debug(inst, "csrrc");
 
-                        let t = self.state.read(csr_addr);
-                        self.state.write(csr_addr, t & (!self.xregs.read(rs1)));
+                        let t = self.read_csr(bus, csr_addr);
+                        // With rs1=x0, the CSR isn't written at all.
+                        if rs1 != 0 {
+                            self.write_csr(csr_addr, t & (!self.xregs.read(rs1)));
+                        }
                         self.xregs.write(rd, t);
                     }
                     0x5 => {
                         // csrrwi
                         inst_count!(self, "csrrwi");
-                        self.debug(inst, "csrrwi");
 
                         let zimm = rs1;
-                        self.xregs.write(rd, self.state.read(csr_addr));
-                        self.state.write(csr_addr, zimm);
+                        let t = self.read_csr(bus, csr_addr);
+                        self.xregs.write(rd, t);
+                        self.write_csr(csr_addr, zimm);
                     }
                     0x6 => {
                         // csrrsi
                         inst_count!(self, "csrrsi");
-                        self.debug(inst, "csrrsi");
 
                         let zimm = rs1;
-                        let t = self.state.read(csr_addr);
-                        self.state.write(csr_addr, t | zimm);
+                        let t = self.read_csr(bus, csr_addr);
+                        // With a zero immediate, the CSR isn't written at all.
+                        if zimm != 0 {
+                            self.write_csr(csr_addr, t | zimm);
+                        }
                         self.xregs.write(rd, t);
                     }
                     0x7 => {
                         // csrrci
                         inst_count!(self, "csrrci");
-                        self.debug(inst, "csrrci");
 
                         let zimm = rs1;
-                        let t = self.state.read(csr_addr);
-                        self.state.write(csr_addr, t & (!zimm));
+                        let t = self.read_csr(bus, csr_addr);
+                        // With a zero immediate, the CSR isn't written at all.
+                        if zimm != 0 {
+                            self.write_csr(csr_addr, t & (!zimm));
+                        }
                         self.xregs.write(rd, t);
                     }
                     _ => {
-                        return Err(Exception::IllegalInstruction(inst));
+                        return Err(self.illegal("system funct3", inst));
                     }
                 }
             }
+            CUSTOM_0 | CUSTOM_1 | CUSTOM_2 | CUSTOM_3 => {
+                // The extension is taken from the bus while it runs, since it accesses the bus.
+                let Some(mut extension) = bus.custom_extension.take() else {
+                    return Err(self.illegal("custom opcode without extension", inst));
+                };
+                inst_count!(self, "custom");
+
+                let result = custom::execute(self, bus, extension.as_mut(), inst);
+                bus.custom_extension = Some(extension);
+                result?;
+            }
             _ => {
-                return Err(Exception::IllegalInstruction(inst));
+                return Err(self.illegal("unknown opcode", inst));
             }
         }
         Ok(())
@@ -5,10 +5,13 @@ use core::{
     ops::{Bound, Range, RangeBounds, RangeInclusive},
 };
 
+use crate::config::{CpuConfig, Extension};
+use crate::trigger::Triggers;
+
 pub type CsrAddress = u16;
 pub type CsrFieldRange = RangeInclusive<usize>;
 
-pub const MXLEN: usize = 64;
+pub const MXLEN: usize = 32;
 /// The number of CSRs. The field is 12 bits so the maximum kind of CSRs is 4096 (2**12).
 pub const CSR_SIZE: usize = 4096;
 
@@ -38,8 +41,22 @@ const _FRB: CsrAddress = 0x002;
 pub const FCSR: CsrAddress = 0x003;
 
 // User Counter/Timers.
+/// Cycle counter for RDCYCLE instruction.
+pub const CYCLE: CsrAddress = 0xc00;
 /// Timer for RDTIME instruction.
-const TIME: CsrAddress = 0xc01;
+pub const TIME: CsrAddress = 0xc01;
+/// Instructions-retired counter for RDINSTRET instruction.
+pub const INSTRET: CsrAddress = 0xc02;
+/// Performance-monitoring counter. `HPMCOUNTER3 + n - 3` is hpmcounter`n`, up to 31.
+pub const HPMCOUNTER3: CsrAddress = 0xc03;
+/// Upper 32 bits of cycle, RV32 only.
+pub const CYCLEH: CsrAddress = 0xc80;
+/// Upper 32 bits of time, RV32 only.
+pub const TIMEH: CsrAddress = 0xc81;
+/// Upper 32 bits of instret, RV32 only.
+pub const INSTRETH: CsrAddress = 0xc82;
+/// Upper 32 bits of hpmcounter3, RV32 only.
+pub const HPMCOUNTER3H: CsrAddress = 0xc83;
 
 /////////////////////////////////////
 // Supervisor-level CSR addresses //
@@ -83,13 +100,13 @@ const MARCHID: CsrAddress = 0xf12;
 /// Implementation ID.
 const MIMPID: CsrAddress = 0xf13;
 /// Hardware thread ID.
-const MHARTID: CsrAddress = 0xf14;
+pub const MHARTID: CsrAddress = 0xf14;
 
 // Machine trap setup.
 /// Machine status register.
 pub const MSTATUS: CsrAddress = 0x300;
 /// ISA and extensions.
-const MISA: CsrAddress = 0x301;
+pub const MISA: CsrAddress = 0x301;
 /// Machine exception delefation register.
 pub const MEDELEG: CsrAddress = 0x302;
 /// Machine interrupt delefation register.
@@ -99,7 +116,7 @@ pub const MIE: CsrAddress = 0x304;
 /// Machine trap-handler base address.
 pub const MTVEC: CsrAddress = 0x305;
 /// Machine counter enable.
-const _MCOUNTEREN: CsrAddress = 0x306;
+pub const MCOUNTEREN: CsrAddress = 0x306;
 
 // Machine trap handling.
 /// Scratch register for machine trap handlers.
@@ -113,21 +130,184 @@ pub const MTVAL: CsrAddress = 0x343;
 /// Machine interrupt pending.
 pub const MIP: CsrAddress = 0x344;
 
+// Machine counter/timers.
+/// Machine cycle counter.
+pub const MCYCLE: CsrAddress = 0xb00;
+/// Machine instructions-retired counter.
+pub const MINSTRET: CsrAddress = 0xb02;
+/// Machine performance-monitoring counter. `MHPMCOUNTER3 + n - 3` is mhpmcounter`n`, up to 31.
+pub const MHPMCOUNTER3: CsrAddress = 0xb03;
+/// Upper 32 bits of mcycle, RV32 only.
+pub const MCYCLEH: CsrAddress = 0xb80;
+/// Upper 32 bits of minstret, RV32 only.
+pub const MINSTRETH: CsrAddress = 0xb82;
+/// Upper 32 bits of mhpmcounter3, RV32 only.
+pub const MHPMCOUNTER3H: CsrAddress = 0xb83;
+
+// Machine counter setup.
+/// Machine counter-inhibit register.
+pub const MCOUNTINHIBIT: CsrAddress = 0x320;
+/// Machine performance-monitoring event selector. `MHPMEVENT3 + n - 3` is mhpmevent`n`, up to 31.
+pub const MHPMEVENT3: CsrAddress = 0x323;
+
 // Machine memory protection.
 /// Physical memory protection configuration.
 const _PMPCFG0: CsrAddress = 0x3a0;
 /// Physical memory protection address register.
 const _PMPADDR0: CsrAddress = 0x3b0;
 
+// Trigger module registers.
+/// Trigger select register.
+pub const TSELECT: CsrAddress = 0x7a0;
+/// First trigger data register.
+pub const TDATA1: CsrAddress = 0x7a1;
+/// Second trigger data register.
+pub const TDATA2: CsrAddress = 0x7a2;
+/// Third trigger data register.
+pub const TDATA3: CsrAddress = 0x7a3;
+/// Trigger info register.
+pub const TINFO: CsrAddress = 0x7a4;
+
+// Debug mode registers.
+/// Debug control and status register.
+pub const DCSR: CsrAddress = 0x7b0;
+/// Debug program counter.
+pub const DPC: CsrAddress = 0x7b1;
+/// Debug scratch register 0.
+pub const DSCRATCH0: CsrAddress = 0x7b2;
+/// Debug scratch register 1.
+pub const DSCRATCH1: CsrAddress = 0x7b3;
+
+/// The names of the CSRs in assembly, such as in `csrrw t0, mscratch, t1`.
+pub const CSR_NAMES: &[(&str, CsrAddress)] = &[
+    ("ustatus", USTATUS),
+    ("fflags", _FFLAGS),
+    ("frm", _FRB),
+    ("fcsr", FCSR),
+    ("utvec", UTVEC),
+    ("uepc", UEPC),
+    ("ucause", UCAUSE),
+    ("utval", _UTVAL),
+    ("cycle", CYCLE),
+    ("time", TIME),
+    ("instret", INSTRET),
+    ("hpmcounter3", HPMCOUNTER3),
+    ("cycleh", CYCLEH),
+    ("timeh", TIMEH),
+    ("instreth", INSTRETH),
+    ("hpmcounter3h", HPMCOUNTER3H),
+    ("sstatus", SSTATUS),
+    ("sedeleg", SEDELEG),
+    ("sideleg", SIDELEG),
+    ("sie", SIE),
+    ("stvec", STVEC),
+    ("sscratch", _SSCRATCH),
+    ("sepc", SEPC),
+    ("scause", SCAUSE),
+    ("stval", STVAL),
+    ("sip", SIP),
+    ("satp", SATP),
+    ("mvendorid", MVENDORID),
+    ("marchid", MARCHID),
+    ("mimpid", MIMPID),
+    ("mhartid", MHARTID),
+    ("mstatus", MSTATUS),
+    ("misa", MISA),
+    ("medeleg", MEDELEG),
+    ("mideleg", MIDELEG),
+    ("mie", MIE),
+    ("mtvec", MTVEC),
+    ("mcounteren", MCOUNTEREN),
+    ("mscratch", _MSCRATCH),
+    ("mepc", MEPC),
+    ("mcause", MCAUSE),
+    ("mtval", MTVAL),
+    ("mip", MIP),
+    ("mcycle", MCYCLE),
+    ("minstret", MINSTRET),
+    ("mhpmcounter3", MHPMCOUNTER3),
+    ("mcycleh", MCYCLEH),
+    ("minstreth", MINSTRETH),
+    ("mhpmcounter3h", MHPMCOUNTER3H),
+    ("mcountinhibit", MCOUNTINHIBIT),
+    ("mhpmevent3", MHPMEVENT3),
+    ("pmpcfg0", _PMPCFG0),
+    ("pmpaddr0", _PMPADDR0),
+    ("tselect", TSELECT),
+    ("tdata1", TDATA1),
+    ("tdata2", TDATA2),
+    ("tdata3", TDATA3),
+    ("tinfo", TINFO),
+    ("dcsr", DCSR),
+    ("dpc", DPC),
+    ("dscratch0", DSCRATCH0),
+    ("dscratch1", DSCRATCH1),
+];
+
 // MSTATUS fields.
+/// Global interrupt-enable bit for supervisor mode.
+pub const MSTATUS_SIE: CsrFieldRange = 1..=1;
 /// Global interrupt-enable bit for machine mode.
 pub const MSTATUS_MIE: CsrFieldRange = 3..=3;
+/// Previous interrupt-enable bit for supervisor mode.
+pub const MSTATUS_SPIE: CsrFieldRange = 5..=5;
 /// Previous interrupt-enable bit for machine mode.
 pub const MSTATUS_MPIE: CsrFieldRange = 7..=7;
+/// Previous privilege mode for supervisor mode.
+pub const MSTATUS_SPP: CsrFieldRange = 8..=8;
 /// Previous privilege mode for machine mode.
 pub const MSTATUS_MPP: CsrFieldRange = 11..=12;
 /// Modify privilege bit.
 pub const MSTATUS_MPRV: CsrFieldRange = 17..=17;
+/// Permit supervisor user memory access bit.
+pub const MSTATUS_SUM: CsrFieldRange = 18..=18;
+/// Make executable readable bit.
+pub const MSTATUS_MXR: CsrFieldRange = 19..=19;
+
+/// The writable bits of MSTATUS. The floating-point and extension state (FS, XS, SD) is zero
+/// because neither is implemented, and the trap virtualization bits (TVM, TW, TSR) are zero
+/// because their checks are not implemented.
+const MSTATUS_MASK: u32 = field_mask(&MSTATUS_SIE)
+    | field_mask(&MSTATUS_MIE)
+    | field_mask(&MSTATUS_SPIE)
+    | field_mask(&MSTATUS_MPIE)
+    | field_mask(&MSTATUS_SPP)
+    | field_mask(&MSTATUS_MPP)
+    | field_mask(&MSTATUS_MPRV)
+    | field_mask(&MSTATUS_SUM)
+    | field_mask(&MSTATUS_MXR);
+/// The bits of MSTATUS visible in SSTATUS.
+const SSTATUS_MASK: u32 = field_mask(&MSTATUS_SIE)
+    | field_mask(&MSTATUS_SPIE)
+    | field_mask(&MSTATUS_SPP)
+    | field_mask(&MSTATUS_SUM)
+    | field_mask(&MSTATUS_MXR);
+
+/// Return the bits of the field `range`.
+const fn field_mask(range: &CsrFieldRange) -> u32 {
+    let width = *range.end() - *range.start() + 1;
+    (u32::MAX >> (32 - width)) << *range.start()
+}
+
+// DCSR fields.
+/// Privilege mode the hart was operating in when debug mode was entered.
+pub const DCSR_PRV: CsrFieldRange = 0..=1;
+/// Enter debug mode after executing a single instruction.
+pub const DCSR_STEP: CsrFieldRange = 2..=2;
+/// Honor MPRV in mstatus while in debug mode.
+pub const DCSR_MPRVEN: CsrFieldRange = 4..=4;
+/// Reason why debug mode was entered.
+pub const DCSR_CAUSE: CsrFieldRange = 6..=8;
+/// Stop the timer while in debug mode. Read-only zero, since the harts share the timer.
+pub const DCSR_STOPTIME: CsrFieldRange = 9..=9;
+/// Stop the counters while in debug mode.
+pub const DCSR_STOPCOUNT: CsrFieldRange = 10..=10;
+/// Enable interrupts during single stepping.
+pub const DCSR_STEPIE: CsrFieldRange = 11..=11;
+/// `ebreak` in U-mode enters debug mode.
+pub const DCSR_EBREAKU: CsrFieldRange = 12..=12;
+/// `ebreak` in M-mode enters debug mode.
+pub const DCSR_EBREAKM: CsrFieldRange = 15..=15;
 
 // MIP fields.
 /// Supervisor software interrupt.
@@ -142,10 +322,28 @@ pub const MTIP_BIT: u32 = 1 << 7;
 pub const SEIP_BIT: u32 = 1 << 9;
 /// Machine external interrupt.
 pub const MEIP_BIT: u32 = 1 << 11;
+/// The interrupts that can be enabled in MIE. S-mode isn't implemented, so only the machine-level
+/// interrupts exist.
+const MIE_MASK: u32 = MSIP_BIT | MTIP_BIT | MEIP_BIT;
+
+// Events selectable in the MHPMEVENT registers.
+/// Count nothing.
+pub const HPM_EVENT_NONE: u32 = 0;
+/// Count taken conditional branches.
+pub const HPM_EVENT_BRANCH_TAKEN: u32 = 1;
+/// Count loads, including LR and AMO instructions.
+pub const HPM_EVENT_LOAD: u32 = 2;
+/// Count stores, including SC and AMO instructions.
+pub const HPM_EVENT_STORE: u32 = 3;
+/// Count exceptions and interrupts taken.
+pub const HPM_EVENT_TRAP: u32 = 4;
 
 /// The state to contains all the CSRs.
+#[derive(Clone)]
 pub struct State {
     csrs: [u32; CSR_SIZE],
+    /// The trigger module, which holds the trigger CSRs.
+    pub triggers: Triggers,
 }
 
 impl fmt::Display for State {
@@ -189,23 +387,84 @@ impl State {
     /// Create a new `state` object.
     pub fn new() -> Self {
         let mut csrs = [0; CSR_SIZE];
-        let misa: u32 = (2 << 30) | // MXL[1:0]=2 (XLEN is 32)
-            (1 << 20) | // Extensions[20] (User mode implemented)
-            (1 << 18) | // Extensions[18] (Supervisor mode implemented)
-            (1 << 12) | // Extensions[12] (Integer Multiply/Divide extension)
-            (1 << 8) | // Extensions[8] (RV32I/64I/128I base ISA)
-            (1 << 5) | // Extensions[5] (Single-precision floating-point extension)
-            (1 << 3) | // Extensions[3] (Double-precision floating-point extension)
-            (1 << 2) | // Extensions[2] (Compressed extension)
-            1; // Extensions[0] (Atomic extension)
-        csrs[MISA as usize] = misa;
-
-        Self { csrs }
+        csrs[MISA as usize] = CpuConfig::default().misa();
+        csrs[DCSR as usize] = DCSR_RESET;
+
+        Self {
+            csrs,
+            triggers: Triggers::new(),
+        }
+    }
+
+    /// Set the ID of the hart in the read-only MHARTID register.
+    pub fn set_hartid(&mut self, hartid: u32) {
+        self.csrs[MHARTID as usize] = hartid;
+    }
+
+    /// Set the ISA in the read-only MISA register, and legalize MSTATUS for it.
+    pub fn set_misa(&mut self, misa: u32) {
+        self.csrs[MISA as usize] = misa;
+        if !self.has_user_mode() {
+            // M-mode is the only legal MPP.
+            self.csrs[MSTATUS as usize] |= field_mask(&MSTATUS_MPP);
+        }
+        self.write_mstatus_value(self.csrs[MSTATUS as usize]);
+    }
+
+    /// Set MIP, including the pending bits that software can't write.
+    pub fn set_mip(&mut self, mip: u32) {
+        self.csrs[MIP as usize] = mip & MIE_MASK;
+    }
+
+    /// Set the read-only cause field in the DCSR register.
+    pub fn set_debug_cause(&mut self, cause: u32) {
+        let range = to_range(&DCSR_CAUSE, MXLEN);
+        let dcsr = self.csrs[DCSR as usize] & !(0b111 << range.start);
+        self.csrs[DCSR as usize] = dcsr | (cause << range.start);
+    }
+
+    /// Increment the value in the MCYCLE register unless it is inhibited.
+    pub fn increment_cycle(&mut self) {
+        self.advance_cycle(1);
+    }
+
+    /// Add `cycles` to the value in the MCYCLE register unless it is inhibited.
+    pub fn advance_cycle(&mut self, cycles: u64) {
+        if self.csrs[MCOUNTINHIBIT as usize] & 1 == 0 {
+            self.add_counter(MCYCLE, cycles);
+        }
+    }
+
+    /// Increment the value in the MINSTRET register unless it is inhibited.
+    pub fn increment_instret(&mut self) {
+        if (self.csrs[MCOUNTINHIBIT as usize] >> 2) & 1 == 0 {
+            self.increment_counter(MINSTRET);
+        }
+    }
+
+    /// Increment the MHPMCOUNTER registers that count `event` and are not inhibited.
+    pub fn increment_event(&mut self, event: u32) {
+        for i in 0..29 {
+            if self.csrs[(MHPMEVENT3 + i) as usize] == event
+                && (self.csrs[MCOUNTINHIBIT as usize] >> (i + 3)) & 1 == 0
+            {
+                self.increment_counter(MHPMCOUNTER3 + i);
+            }
+        }
+    }
+
+    /// Increment a 64-bit counter whose upper 32 bits are at `addr + 0x80`.
+    fn increment_counter(&mut self, addr: CsrAddress) {
+        self.add_counter(addr, 1);
     }
 
-    /// Increment the value in the TIME register.
-    pub fn increment_time(&mut self) {
-        self.csrs[TIME as usize] = self.csrs[TIME as usize].wrapping_add(1);
+    /// Add `n` to a 64-bit counter whose upper 32 bits are at `addr + 0x80`.
+    fn add_counter(&mut self, addr: CsrAddress, n: u64) {
+        let high = (addr + 0x80) as usize;
+        let value =
+            ((self.csrs[high] as u64) << 32 | self.csrs[addr as usize] as u64).wrapping_add(n);
+        self.csrs[addr as usize] = value as u32;
+        self.csrs[high] = (value >> 32) as u32;
     }
 
     /// Read the val from the CSR.
@@ -218,9 +477,15 @@ impl State {
         // machine-mode CSR, and the machinemode chapter should be read first to help understand
         // the supervisor-level CSR descriptions."
         match addr {
-            SSTATUS => self.csrs[MSTATUS as usize],
+            SSTATUS => self.csrs[MSTATUS as usize] & SSTATUS_MASK,
             SIE => self.csrs[MIE as usize] & self.csrs[MIDELEG as usize],
             SIP => self.csrs[MIP as usize] & self.csrs[MIDELEG as usize],
+            // The user-level counters are read-only shadows of the machine-level counters. TIME
+            // and TIMEH aren't kept here: a hart reads them from the CLINT's MTIME register.
+            _ if is_counter(addr) && addr != TIME && addr != TIMEH => {
+                self.csrs[(addr - CYCLE + MCYCLE) as usize]
+            }
+            _ if is_trigger_register(addr) => self.triggers.read(addr),
             _ => self.csrs[addr as usize],
         }
     }
@@ -239,9 +504,33 @@ impl State {
             MARCHID => {}
             MIMPID => {}
             MHARTID => {}
+            // The extensions can't be changed at run time.
+            MISA => {}
+            MSTATUS => self.write_mstatus_value(val),
             SSTATUS => {
-                self.csrs[MSTATUS as usize] = self.csrs[MSTATUS as usize] | val;
+                let mstatus = self.csrs[MSTATUS as usize];
+                self.write_mstatus_value((mstatus & !SSTATUS_MASK) | (val & SSTATUS_MASK));
+            }
+            // Only direct (0) and vectored (1) modes are legal, so a reserved mode keeps the
+            // previous mode.
+            MTVEC => {
+                let mode = if val & 0b11 < 2 {
+                    val & 0b11
+                } else {
+                    self.csrs[MTVEC as usize] & 0b11
+                };
+                self.csrs[MTVEC as usize] = (val & !0b11) | mode;
             }
+            // 3.1.8 Machine Trap Delegation Registers (medeleg and mideleg)
+            // "In systems with only M-mode, or with M and U modes but without U-mode trap support,
+            // the medeleg and mideleg registers should not exist." They read as zero.
+            MEDELEG | MIDELEG => {}
+            // IALIGN is 32, so the low two bits are hardwired to zero.
+            MEPC => self.csrs[MEPC as usize] = val & !0b11,
+            MIE => self.csrs[MIE as usize] = val & MIE_MASK,
+            // The pending bits are owned by the devices that raise them, so software can't write
+            // any of them.
+            MIP => {}
             SIE => {
                 self.csrs[MIE as usize] = (self.csrs[MIE as usize] & !self.csrs[MIDELEG as usize])
                     | (val & self.csrs[MIDELEG as usize]);
@@ -250,15 +539,70 @@ impl State {
                 let mask = SSIP_BIT & self.csrs[MIDELEG as usize];
                 self.csrs[MIP as usize] = (self.csrs[MIP as usize] & !mask) | (val & mask);
             }
+            _ if is_counter(addr) => {}
+            // Bit 1 (TM) is hardwired to zero because time is not a counter of this hart.
+            MCOUNTINHIBIT => self.csrs[addr as usize] = val & !0b10,
+            // Unknown events count nothing.
+            _ if (MHPMEVENT3..MHPMEVENT3 + 29).contains(&addr) => {
+                self.csrs[addr as usize] = if val <= HPM_EVENT_TRAP {
+                    val
+                } else {
+                    HPM_EVENT_NONE
+                }
+            }
+            DCSR => {
+                // debugver, cause, and nmip are read-only, and prv only holds the implemented
+                // privilege modes. stoptime is read-only zero: time is the MTIME register the
+                // harts share, so a halted hart can't stop it.
+                let mask = 0b1001_1100_0001_0100;
+                let mut dcsr = (self.csrs[DCSR as usize] & !mask) | (val & mask);
+                if matches!(val & 0b11, 0b00 | 0b11) {
+                    dcsr = (dcsr & !0b11) | (val & 0b11);
+                }
+                self.csrs[DCSR as usize] = dcsr;
+            }
+            _ if is_trigger_register(addr) => self.triggers.write(addr, val),
+            // IALIGN is 32, so the low two bits are hardwired to zero.
+            DPC => self.csrs[DPC as usize] = val & !0b11,
             _ => self.csrs[addr as usize] = val,
         }
     }
 
+    /// Write MSTATUS, keeping the fields that are read-only or would become illegal.
+    fn write_mstatus_value(&mut self, val: u32) {
+        let mstatus = self.csrs[MSTATUS as usize];
+        let mut new = (mstatus & !MSTATUS_MASK) | (val & MSTATUS_MASK);
+
+        // 3.1.6.2 Privilege and Global Interrupt-Enable Stack in mstatus register
+        // "M-mode software can determine whether a privilege mode is implemented by writing that
+        // mode to MPP then reading it back." Only M-mode, and U-mode if it is implemented, are
+        // legal, so another mode keeps the previous value.
+        let mpp = to_range(&MSTATUS_MPP, MXLEN);
+        let legal = match (val >> mpp.start) & 0b11 {
+            0b11 => true,
+            0b00 => self.has_user_mode(),
+            _ => false,
+        };
+        if !legal {
+            let mask = field_mask(&MSTATUS_MPP);
+            new = (new & !mask) | (mstatus & mask);
+        }
+        // 3.1.6.3 Memory Privilege in mstatus Register
+        // "If U-mode is not supported, MPRV is read-only 0."
+        if !self.has_user_mode() {
+            new &= !field_mask(&MSTATUS_MPRV);
+        }
+        self.csrs[MSTATUS as usize] = new;
+    }
+
+    /// Return true if MISA reports that U-mode is implemented.
+    fn has_user_mode(&self) -> bool {
+        self.csrs[MISA as usize] & (1 << 20) != 0
+    }
+
     /// Read a bit from the CSR.
     pub fn read_bit(&self, addr: CsrAddress, bit: usize) -> u32 {
-        if bit >= MXLEN {
-            // TODO: raise exception?
-        }
+        debug_assert!(bit < MXLEN, "bit {} is out of a CSR", bit);
 
         if (self.read(addr) & (1 << bit)) != 0 {
             1
@@ -271,13 +615,15 @@ impl State {
     pub fn read_bits<T: RangeBounds<usize>>(&self, addr: CsrAddress, range: T) -> u32 {
         let range = to_range(&range, MXLEN);
 
-        if (range.start >= MXLEN) | (range.end > MXLEN) | (range.start >= range.end) {
-            // TODO: ranse exception?
-        }
+        debug_assert!(
+            range.end <= MXLEN && range.start < range.end,
+            "{:?} is not a field of a CSR",
+            range
+        );
 
         // Bitmask for high bits.
         let mut bitmask = 0;
-        if range.end != 64 {
+        if range.end != MXLEN {
             bitmask = !0 << range.end;
         }
 
@@ -287,12 +633,8 @@ impl State {
 
     /// Write a bit to the CSR.
     pub fn write_bit(&mut self, addr: CsrAddress, bit: usize, val: u32) {
-        if bit >= MXLEN {
-            // TODO: raise exception?
-        }
-        if val > 1 {
-            // TODO: raise exception
-        }
+        debug_assert!(bit < MXLEN, "bit {} is out of a CSR", bit);
+        debug_assert!(val <= 1, "{} doesn't fit in a bit", val);
 
         if val == 1 {
             self.write(addr, self.read(addr) | 1 << bit);
@@ -305,14 +647,19 @@ impl State {
     pub fn write_bits<T: RangeBounds<usize>>(&mut self, addr: CsrAddress, range: T, val: u32) {
         let range = to_range(&range, MXLEN);
 
-        if (range.start >= MXLEN) | (range.end > MXLEN) | (range.start >= range.end) {
-            // TODO: ranse exception?
-        }
-        if (val >> (range.end - range.start)) != 0 {
-            // TODO: raise exception
-        }
-
-        let bitmask = (!0 << range.end) | !(!0 << range.start);
+        debug_assert!(
+            range.end <= MXLEN && range.start < range.end,
+            "{:?} is not a field of a CSR",
+            range
+        );
+        debug_assert!(
+            (val as u64 >> (range.end - range.start)) == 0,
+            "{} doesn't fit in {:?}",
+            val,
+            range
+        );
+
+        let bitmask = ((!0u64 << range.end) as u32) | !(!0 << range.start);
         // Set bits.
         self.write(addr, (self.read(addr) & bitmask) | (val << range.start))
     }
@@ -337,22 +684,90 @@ impl State {
         self.write_bits(MSTATUS, range, val);
     }
 
-    /// Reset all the CSRs.
+    /// Reset all the CSRs except the hart ID and the ISA.
     pub fn reset(&mut self) {
+        let hartid = self.csrs[MHARTID as usize];
+        let misa = self.csrs[MISA as usize];
         self.csrs = [0; CSR_SIZE];
+        self.csrs[MHARTID as usize] = hartid;
+        self.set_misa(misa);
+        self.csrs[DCSR as usize] = DCSR_RESET;
+        self.triggers = Triggers::new();
+    }
+}
 
-        let misa: u32 = (2 << 30) | // MXL[1:0]=2 (XLEN is 32)
-            (1 << 18) | // Extensions[18] (Supervisor mode implemented)
-            (1 << 12) | // Extensions[12] (Integer Multiply/Divide extension)
-            (1 << 8) | // Extensions[8] (RV32I/64I/128I base ISA)
-            (1 << 5) | // Extensions[5] (Single-precision floating-point extension)
-            (1 << 3) | // Extensions[3] (Double-precision floating-point extension)
-            (1 << 2) | // Extensions[2] (Compressed extension)
-            1; // Extensions[0] (Atomic extension)
-        self.csrs[MISA as usize] = misa;
+/// The reset value of DCSR: external debug support as described in the specification
+/// (debugver = 4), and M-mode as the privilege mode to return to.
+const DCSR_RESET: u32 = (4 << 28) | 0b11;
+
+/// Return the lowest privilege level that can access the CSR at `addr`, from bits [9:8] of the
+/// address: 0 for user, 1 for supervisor, and 3 for machine.
+pub fn privilege_level(addr: CsrAddress) -> u32 {
+    ((addr >> 8) & 0b11) as u32
+}
+
+/// Return true if the CSR at `addr` is read-only, which bits [11:10] of the address encode as
+/// `0b11`.
+pub fn is_read_only(addr: CsrAddress) -> bool {
+    (addr >> 10) & 0b11 == 0b11
+}
+
+/// The CSRs every hart has, whatever its configuration: the counters, the machine-level CSRs,
+/// the trigger module and the debug mode registers.
+const HART_CSRS: &[RangeInclusive<CsrAddress>] = &[
+    // cycle, time, instret, hpmcounter3-hpmcounter31 and their upper halves.
+    CYCLE..=HPMCOUNTER3 + 28,
+    CYCLEH..=HPMCOUNTER3H + 28,
+    MVENDORID..=MHARTID,
+    MSTATUS..=MTVEC,
+    MCOUNTINHIBIT..=MCOUNTINHIBIT,
+    // mhpmevent3-mhpmevent31.
+    MHPMEVENT3..=MHPMEVENT3 + 28,
+    _MSCRATCH..=MIP,
+    // pmpcfg0-pmpcfg3 and pmpaddr0-pmpaddr15.
+    _PMPCFG0..=_PMPCFG0 + 3,
+    _PMPADDR0..=_PMPADDR0 + 15,
+    // mcycle, minstret, mhpmcounter3-mhpmcounter31 and their upper halves.
+    MCYCLE..=MCYCLE,
+    MINSTRET..=MHPMCOUNTER3 + 28,
+    MCYCLEH..=MCYCLEH,
+    MINSTRETH..=MHPMCOUNTER3H + 28,
+    TSELECT..=TINFO,
+    DCSR..=DSCRATCH1,
+];
+
+/// Return true if the CSR at `addr` exists on a hart with `config`. Accessing any other CSR
+/// raises an illegal instruction exception. `CSR_NAMES` is only the assembler's name table: the
+/// floating-point CSRs need F, the supervisor-level CSRs need S, mcounteren needs U, and the
+/// user-level trap CSRs of the N extension never exist.
+pub fn exists(addr: CsrAddress, config: &CpuConfig) -> bool {
+    match addr {
+        _FFLAGS..=FCSR => config.has(Extension::F),
+        // 3.1.11 Machine Counter-Enable Register (mcounteren)
+        // mcounteren controls the counters of the next-lowest privilege mode, so only a hart
+        // with U-mode has one.
+        MCOUNTEREN => config.has(Extension::U),
+        _ if privilege_level(addr) == 1 => config.has(Extension::S),
+        _ => HART_CSRS.iter().any(|range| range.contains(&addr)),
     }
 }
 
+/// Return true if `addr` is one of the debug mode registers, which are only accessible in debug
+/// mode.
+pub fn is_debug_register(addr: CsrAddress) -> bool {
+    (DCSR..=DSCRATCH1).contains(&addr)
+}
+
+/// Return true if `addr` is one of the trigger module registers.
+pub fn is_trigger_register(addr: CsrAddress) -> bool {
+    (TSELECT..=TINFO).contains(&addr)
+}
+
+/// Return true if `addr` is one of the user-level counters or their upper halves.
+fn is_counter(addr: CsrAddress) -> bool {
+    (CYCLE..=CYCLE + 31).contains(&addr) || (CYCLEH..=CYCLEH + 31).contains(&addr)
+}
+
 /// Convert the val implement `RangeBounds` to the `Range` struct.
 fn to_range<T: RangeBounds<usize>>(generic_range: &T, bit_length: usize) -> Range<usize> {
     let start = match generic_range.start_bound() {
@@ -0,0 +1,163 @@
+//! The custom module lets the embedder implement the instructions in the major opcodes the base ISA
+//! reserves for custom extensions: custom-0 (0x0b), custom-1 (0x2b), custom-2 (0x5b) and custom-3
+//! (0x7b). A guest can then use an operation the host implements natively, such as a memory copy
+//! or a blit, as a single instruction instead of a system call.
+//!
+//! The extension is registered with [`Bus::custom_extension`](crate::bus::Bus::custom_extension).
+//! Without it, or when the extension doesn't implement an encoding, the instruction raises an
+//! illegal-instruction exception as before.
+
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+use crate::exception::Exception;
+
+/// The custom-0 major opcode.
+pub const CUSTOM_0: u32 = 0x0b;
+/// The custom-1 major opcode.
+pub const CUSTOM_1: u32 = 0x2b;
+/// The custom-2 major opcode, reserved for RV128 but available on RV32.
+pub const CUSTOM_2: u32 = 0x5b;
+/// The custom-3 major opcode, reserved for RV128 but available on RV32.
+pub const CUSTOM_3: u32 = 0x7b;
+
+/// Return true if `inst` is in one of the custom major opcodes.
+pub fn is_custom(inst: u32) -> bool {
+    matches!(inst & 0x7f, CUSTOM_0 | CUSTOM_1 | CUSTOM_2 | CUSTOM_3)
+}
+
+/// A custom instruction decoded with the R-type layout. Extensions that use another layout decode
+/// the immediate from `inst` themselves, or with the helpers.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct CustomInstruction {
+    /// The raw instruction.
+    pub inst: u32,
+    /// Which custom opcode the instruction is in, from 0 to 3.
+    pub index: u32,
+    pub rd: u32,
+    pub funct3: u32,
+    pub rs1: u32,
+    pub rs2: u32,
+    pub funct7: u32,
+}
+
+impl CustomInstruction {
+    /// Decode the fields of `inst`.
+    pub fn decode(inst: u32) -> Self {
+        Self {
+            inst,
+            index: (inst & 0x60) >> 5,
+            rd: (inst & 0x00000f80) >> 7,
+            funct3: (inst & 0x00007000) >> 12,
+            rs1: (inst & 0x000f8000) >> 15,
+            rs2: (inst & 0x01f00000) >> 20,
+            funct7: (inst & 0xfe000000) >> 25,
+        }
+    }
+
+    /// Return the sign-extended I-type immediate, inst[31:20].
+    pub fn imm_i(&self) -> u32 {
+        ((self.inst as i32) >> 20) as u32
+    }
+
+    /// Return the sign-extended S-type immediate, inst[31:25|11:7].
+    pub fn imm_s(&self) -> u32 {
+        (((self.inst & 0xfe000000) as i32 >> 20) as u32) | self.rd
+    }
+}
+
+/// The state a custom instruction can access: the integer registers of the hart that executes it,
+/// and memory.
+pub struct CustomContext<'a> {
+    cpu: &'a mut Cpu,
+    bus: &'a mut Bus,
+    inst: u32,
+}
+
+impl<'a> CustomContext<'a> {
+    /// Return the ID of the hart that executes the instruction.
+    pub fn hartid(&self) -> u32 {
+        self.cpu.hartid()
+    }
+
+    /// Return the address of the instruction.
+    pub fn pc(&self) -> u32 {
+        self.cpu.pc
+    }
+
+    /// Read the integer register `index`. A register that doesn't exist, such as x16 on RV32E,
+    /// makes the instruction illegal.
+    pub fn read_register(&self, index: u32) -> Result<u32, Exception> {
+        self.check_register(index)?;
+        Ok(self.cpu.xregs.read(index))
+    }
+
+    /// Write `value` to the integer register `index`. Writes to x0 are ignored.
+    pub fn write_register(&mut self, index: u32, value: u32) -> Result<(), Exception> {
+        self.check_register(index)?;
+        let value = self.journaled(value);
+        self.cpu.xregs.write(index, value);
+        Ok(())
+    }
+
+    /// Load `size`-bit data from `addr`. The access is checked by the triggers like a load
+    /// instruction, and the exception it raises aborts the custom instruction.
+    pub fn load(&mut self, addr: u32, size: u8) -> Result<u32, Exception> {
+        self.cpu.read(self.bus, addr, size)
+    }
+
+    /// Store `size`-bit data to `addr`. The access is checked by the triggers like a store
+    /// instruction, and the exception it raises aborts the custom instruction. Stores done
+    /// before the exception aren't undone.
+    pub fn store(&mut self, addr: u32, value: u32, size: u8) -> Result<(), Exception> {
+        let value = self.journaled(value);
+        self.cpu.write(self.bus, addr, value, size)
+    }
+
+    /// Journal a value the extension writes when the run is recorded or replayed, since the
+    /// extension may not be deterministic.
+    fn journaled(&mut self, value: u32) -> u32 {
+        match self.bus.journal.as_mut() {
+            Some(journal) => journal.custom(value),
+            None => value,
+        }
+    }
+
+    fn check_register(&self, index: u32) -> Result<(), Exception> {
+        if index as usize >= self.cpu.xregs.count() {
+            return Err(Exception::IllegalInstruction(self.inst));
+        }
+        Ok(())
+    }
+}
+
+/// The custom instructions supplied by the embedder of the emulator.
+pub trait CustomExtension {
+    /// Execute the custom instruction `inst`. An encoding the extension doesn't implement returns
+    /// `Exception::IllegalInstruction(inst.inst)`, and any exception is taken as a trap at the
+    /// instruction. On success, the instruction retires and the hart continues with the next one.
+    fn execute(
+        &mut self,
+        inst: &CustomInstruction,
+        context: &mut CustomContext,
+    ) -> Result<(), Exception>;
+}
+
+/// Execute the custom instruction `inst` on `cpu` with the extension registered on `bus`.
+pub(crate) fn execute(
+    cpu: &mut Cpu,
+    bus: &mut Bus,
+    extension: &mut dyn CustomExtension,
+    inst: u32,
+) -> Result<(), Exception> {
+    let decoded = CustomInstruction::decode(inst);
+    let mut context = CustomContext {
+        cpu,
+        bus: &mut *bus,
+        inst,
+    };
+    let result = extension.execute(&decoded, &mut context);
+    if let Some(journal) = bus.journal.as_mut() {
+        journal.custom_result(result.as_ref().err().map(Exception::exception_code));
+    }
+    result
+}
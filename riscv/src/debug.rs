@@ -0,0 +1,40 @@
+//! The debug module contains the reasons to enter debug mode and the function to halt a hart, as
+//! described in the external debug support (Sdext) of the RISC-V Debug Specification.
+
+use crate::{
+    cpu::{Cpu, Mode},
+    csr::*,
+};
+
+/// All the reasons a hart enters debug mode. The value is written to `dcsr.cause`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum DebugCause {
+    /// An `ebreak` was executed while `dcsr.ebreakm` or `dcsr.ebreaku` was set.
+    Ebreak = 1,
+    /// A trigger module fired with action 1.
+    Trigger = 2,
+    /// The debugger requested a halt.
+    HaltRequest = 3,
+    /// The hart single-stepped because `dcsr.step` was set.
+    Step = 4,
+}
+
+impl DebugCause {
+    /// Halt the hart: save the program counter of the next instruction to DPC and the current
+    /// privilege mode to DCSR, and enter debug mode.
+    pub fn enter(&self, cpu: &mut Cpu) {
+        // 4.1 Debug Mode
+        // "Upon entry to debug mode, dpc is updated with the virtual address of the next
+        // instruction to be executed", "dcsr.cause is updated", "dcsr.prv is set to reflect the
+        // current privilege mode".
+        cpu.idle = false;
+        cpu.state.write(DPC, cpu.pc);
+        cpu.state.set_debug_cause(*self as u32);
+        let prv = match cpu.mode {
+            Mode::User => Mode::User as u32,
+            _ => Mode::Machine as u32,
+        };
+        cpu.state.write_bits(DCSR, DCSR_PRV, prv);
+        cpu.mode = Mode::Debug;
+    }
+}
@@ -1,96 +1,180 @@
 //! The memory module contains the memory structure and implementation to read/write the memory.
+//!
+//! The memory is sparse: it is divided into pages which are allocated when they are first written,
+//! and a page that was never written reads as zero. A large guest address space only costs the
+//! pages the guest touches.
+//!
+//! Pages are reference-counted, so a clone of the memory shares all the pages with the original.
+//! A shared page is copied when either of them writes to it, which makes cloning a memory image
+//! cheap in both time and space.
 
 use crate::bus::DRAM_BASE;
 use crate::cpu::{BYTE, HALFWORD, WORD};
 use crate::exception::Exception;
-use alloc::vec::Vec;
+use alloc::{rc::Rc, vec::Vec};
 
 /// Default memory size (32KiB).
 pub const DRAM_SIZE: u32 = 32 * 1024;
 
+/// The size of a page of the memory in bytes.
+pub const PAGE_SIZE: u32 = 4096;
+
+/// A page of the memory.
+type Page = [u8; PAGE_SIZE as usize];
+
 /// The memory used by the emulator.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Dram {
-    pub dram: Vec<u8>,
-    code_size: u32,
+    /// The pages of the memory, or `None` for a page that was never written. A page can be
+    /// shared with clones of the memory.
+    pages: Vec<Option<Rc<Page>>>,
+    /// The address which the memory starts.
+    base: u32,
+    /// The size of the memory in bytes.
+    size: u32,
 }
 
 impl Dram {
-    /// Create a new memory object with default memory size.
+    /// Create a new memory object with default memory size at `DRAM_BASE`.
     pub fn new() -> Self {
+        Self::with_size(DRAM_BASE, DRAM_SIZE)
+    }
+
+    /// Create a new memory object of `size` bytes starting at `base`. No page is allocated yet.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the memory doesn't fit in the 32-bit address space.
+    pub fn with_size(base: u32, size: u32) -> Self {
+        assert!(
+            base as u64 + size as u64 <= 1 << 32,
+            "the memory at {:#x} of {:#x} bytes doesn't fit in the address space",
+            base,
+            size
+        );
+        let pages = (size as u64).div_ceil(PAGE_SIZE as u64) as usize;
         Self {
-            dram: vec![0; DRAM_SIZE as usize],
-            code_size: 0,
+            pages: (0..pages).map(|_| None).collect(),
+            base,
+            size,
         }
     }
 
-    /// Set the binary in the memory.
+    /// Return the address which the memory starts.
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
+    /// Return the size of the memory in bytes.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Return the number of pages that are allocated.
+    pub fn allocated_pages(&self) -> usize {
+        self.pages.iter().filter(|page| page.is_some()).count()
+    }
+
+    /// Return the number of allocated pages that are shared with a clone of the memory.
+    pub fn shared_pages(&self) -> usize {
+        self.pages
+            .iter()
+            .flatten()
+            .filter(|page| Rc::strong_count(page) > 1)
+            .count()
+    }
+
+    /// Return true if `addr` is in the memory.
+    pub fn contains(&self, addr: u32) -> bool {
+        addr.wrapping_sub(self.base) < self.size
+    }
+
+    /// Set the binary at the start of the memory. The rest of the memory is left as it is. Pages
+    /// that the binary only fills with zeros aren't allocated if they weren't already.
     pub fn initialize(&mut self, binary: Vec<u8>) {
-        self.code_size = binary.len() as u32;
-        self.dram.splice(..binary.len(), binary.iter().cloned());
+        assert!(
+            binary.len() as u64 <= self.size as u64,
+            "the binary is larger than the memory"
+        );
+        for (index, chunk) in binary.chunks(PAGE_SIZE as usize).enumerate() {
+            if self.pages[index].is_none() && chunk.iter().all(|&byte| byte == 0) {
+                continue;
+            }
+            self.page_mut(index)[..chunk.len()].copy_from_slice(chunk);
+        }
     }
 
     /// Load `size`-bit data from the memory.
     pub fn read(&self, addr: u32, size: u8) -> Result<u32, Exception> {
-        match size {
-            BYTE => Ok(self.read8(addr)),
-            HALFWORD => Ok(self.read16(addr)),
-            WORD => Ok(self.read32(addr)),
-            _ => return Err(Exception::LoadAccessFault),
+        if !self.contains_range(addr, size) || !matches!(size, BYTE | HALFWORD | WORD) {
+            return Err(Exception::LoadAccessFault(addr));
+        }
+        let len = (size / 8) as u32;
+        let offset = addr.wrapping_sub(self.base);
+        let start = (offset % PAGE_SIZE) as usize;
+        if start + len as usize > PAGE_SIZE as usize {
+            // The access crosses a page boundary, so each byte is read from its own page.
+            return Ok((0..len)
+                .rev()
+                .fold(0, |value, i| value << 8 | self.read8(addr.wrapping_add(i))));
         }
+        Ok(match &self.pages[(offset / PAGE_SIZE) as usize] {
+            Some(page) => page[start..start + len as usize]
+                .iter()
+                .rev()
+                .fold(0, |value, &byte| value << 8 | byte as u32),
+            None => 0,
+        })
     }
 
     /// Store `size`-bit data to the memory.
     pub fn write(&mut self, addr: u32, value: u32, size: u8) -> Result<(), Exception> {
-        match size {
-            BYTE => self.write8(addr, value),
-            HALFWORD => self.write16(addr, value),
-            WORD => self.write32(addr, value),
-            _ => return Err(Exception::StoreAMOAccessFault),
+        if !self.contains_range(addr, size) || !matches!(size, BYTE | HALFWORD | WORD) {
+            return Err(Exception::StoreAMOAccessFault(addr));
+        }
+        let len = (size / 8) as u32;
+        let offset = addr.wrapping_sub(self.base);
+        let start = (offset % PAGE_SIZE) as usize;
+        if start + len as usize > PAGE_SIZE as usize {
+            // The access crosses a page boundary, so each byte is written to its own page.
+            for i in 0..len {
+                self.write8(addr.wrapping_add(i), value >> (8 * i));
+            }
+            return Ok(());
+        }
+        let page = self.page_mut((offset / PAGE_SIZE) as usize);
+        for (i, byte) in page[start..start + len as usize].iter_mut().enumerate() {
+            *byte = (value >> (8 * i)) as u8;
         }
         Ok(())
     }
 
-    /// Write a byte to the memory.
-    fn write8(&mut self, addr: u32, val: u32) {
-        let index = (addr - DRAM_BASE) as usize;
-        self.dram[index] = val as u8
+    /// Return true if all the bytes of a `size`-bit access at `addr` are in the memory.
+    fn contains_range(&self, addr: u32, size: u8) -> bool {
+        let offset = addr.wrapping_sub(self.base) as u64;
+        offset + (size / 8) as u64 <= self.size as u64
     }
 
-    /// Write 2 bytes to the memory with little endian.
-    fn write16(&mut self, addr: u32, val: u32) {
-        let index = (addr - DRAM_BASE) as usize;
-        self.dram[index] = (val & 0xff) as u8;
-        self.dram[index + 1] = ((val >> 8) & 0xff) as u8;
+    /// Return the page `index` to write to it, allocating it if it was never written and copying
+    /// it if it is shared.
+    fn page_mut(&mut self, index: usize) -> &mut Page {
+        let page = self.pages[index].get_or_insert_with(|| Rc::new([0; PAGE_SIZE as usize]));
+        Rc::make_mut(page)
     }
 
-    /// Write 4 bytes to the memory with little endian.
-    fn write32(&mut self, addr: u32, val: u32) {
-        let index = (addr - DRAM_BASE) as usize;
-        self.dram[index] = (val & 0xff) as u8;
-        self.dram[index + 1] = ((val >> 8) & 0xff) as u8;
-        self.dram[index + 2] = ((val >> 16) & 0xff) as u8;
-        self.dram[index + 3] = ((val >> 24) & 0xff) as u8;
+    /// Write a byte to the memory.
+    fn write8(&mut self, addr: u32, val: u32) {
+        let offset = addr.wrapping_sub(self.base);
+        let page = self.page_mut((offset / PAGE_SIZE) as usize);
+        page[(offset % PAGE_SIZE) as usize] = val as u8;
     }
 
     /// Read a byte from the memory.
     fn read8(&self, addr: u32) -> u32 {
-        let index = (addr - DRAM_BASE) as usize;
-        self.dram[index] as u32
-    }
-
-    /// Read 2 bytes from the memory with little endian.
-    fn read16(&self, addr: u32) -> u32 {
-        let index = (addr - DRAM_BASE) as usize;
-        return (self.dram[index] as u32) | ((self.dram[index + 1] as u32) << 8);
-    }
-
-    /// Read 4 bytes from the memory with little endian.
-    fn read32(&self, addr: u32) -> u32 {
-        let index = (addr - DRAM_BASE) as usize;
-        return (self.dram[index] as u32)
-            | ((self.dram[index + 1] as u32) << 8)
-            | ((self.dram[index + 2] as u32) << 16)
-            | ((self.dram[index + 3] as u32) << 24);
+        let offset = addr.wrapping_sub(self.base);
+        match &self.pages[(offset / PAGE_SIZE) as usize] {
+            Some(page) => page[(offset % PAGE_SIZE) as usize] as u32,
+            None => 0,
+        }
     }
 }
@@ -0,0 +1,451 @@
+//! The emulator module represents an entire computer.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt;
+
+use crate::{
+    bus::{Bus, DRAM_BASE},
+    config::CpuConfig,
+    coverage::Coverage,
+    cpu::Cpu,
+    debug::DebugCause,
+    dram::DRAM_SIZE,
+    exception::{Exception, Trap},
+    profiler::Profiler,
+    replay::{Journal, JournaledHost, Log},
+    semihosting::{self, Host},
+};
+
+/// Why [`Emulator::run`] returned.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum RunStatus {
+    /// The guest exited with the exit code.
+    Exited(u32),
+    /// All the harts are idle in WFI, and no interrupt can wake them up until the next device
+    /// event in `next_event` cycles. `None` means that only an interrupt injected by the embedder,
+    /// such as an input event, can wake them up.
+    Idle { next_event: Option<u64> },
+    /// The given number of steps was executed.
+    Paused,
+}
+
+/// The emulator to hold harts and the system bus they share.
+pub struct Emulator {
+    /// The harts which are the core implementation of this emulator, indexed by the hart ID.
+    pub harts: Vec<Cpu>,
+    /// The system bus shared by all the harts.
+    pub bus: Bus,
+    /// Debug flag. Output messages described how the emulator executes instructions. Only has an
+    /// effect with the `std` feature.
+    pub is_debug: bool,
+    /// The host that handles semihosting calls. Semihosting is disabled if this is `None`, and
+    /// `ebreak` always raises a breakpoint exception.
+    pub semihosting: Option<Box<dyn Host>>,
+    /// The exit code reported by the guest through semihosting.
+    pub exit_code: Option<u32>,
+    /// The profiler that records every executed instruction, if profiling is enabled.
+    pub profiler: Option<Profiler>,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Emulator {
+    /// Constructor for an emulator with a single hart.
+    pub fn new() -> Emulator {
+        Self::with_harts(1)
+    }
+
+    /// Constructor for an emulator with `harts` harts. The hart IDs are `0..harts`.
+    pub fn with_harts(harts: usize) -> Emulator {
+        Self::with_memory(harts, DRAM_BASE, DRAM_SIZE)
+    }
+
+    /// Constructor for an emulator with `harts` harts and `size` bytes of DRAM starting at
+    /// `base`. The stack pointer of each hart is set to the end of DRAM.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `harts` is 0, or if DRAM overlaps the register window of a device.
+    pub fn with_memory(harts: usize, base: u32, size: u32) -> Emulator {
+        assert!(harts > 0, "an emulator needs at least one hart");
+        let mut harts: Vec<Cpu> = (0..harts as u32).map(Cpu::with_hartid).collect();
+        for cpu in harts.iter_mut() {
+            cpu.xregs.write(2, base.wrapping_add(size));
+        }
+        Self {
+            bus: Bus::with_memory(harts.len(), base, size),
+            harts,
+            is_debug: false,
+            semihosting: None,
+            exit_code: None,
+            profiler: None,
+        }
+    }
+
+    /// Create a copy of the emulator in its current state, such as a guest that was loaded and
+    /// initialized once, to run another instance of it. The copy shares the DRAM pages with this
+    /// emulator and copies a page when either of them first writes to it.
+    ///
+    /// The embedder's objects aren't copied: the copy has no semihosting host, custom extension,
+    /// memory hooks, or profiler until they are set on it.
+    pub fn fork(&self) -> Emulator {
+        Self {
+            harts: self.harts.clone(),
+            bus: self.bus.fork(),
+            is_debug: self.is_debug,
+            semihosting: None,
+            exit_code: self.exit_code,
+            profiler: None,
+        }
+    }
+
+    /// Set the ISA of all the harts.
+    pub fn set_config(&mut self, config: CpuConfig) {
+        for cpu in self.harts.iter_mut() {
+            cpu.set_config(config);
+        }
+    }
+
+    /// Reset the state of all the harts.
+    pub fn reset(&mut self) {
+        for cpu in self.harts.iter_mut() {
+            cpu.reset();
+        }
+        self.exit_code = None;
+    }
+
+    /// Set binary data to the beginning of the DRAM from the emulator console.
+    pub fn initialize_dram(&mut self, data: Vec<u8>) {
+        self.bus.initialize_dram(data);
+    }
+
+    /// Set the binary data in the file at `path` to the beginning of the DRAM.
+    #[cfg(feature = "std")]
+    pub fn initialize_dram_from_file<P: AsRef<std::path::Path>>(
+        &mut self,
+        path: P,
+    ) -> std::io::Result<()> {
+        self.initialize_dram(std::fs::read(path)?);
+        Ok(())
+    }
+
+    /// Set the program counter of all the harts.
+    pub fn initialize_pc(&mut self, pc: u32) {
+        for cpu in self.harts.iter_mut() {
+            cpu.pc = pc;
+        }
+    }
+
+    /// Execute a single step: run a cycle on peripheral devices, then let each hart in the order of
+    /// the hart IDs take a pending interrupt if there is one and execute an instruction. Returns
+    /// the exception that terminated the execution environment, if any.
+    pub fn step(&mut self) -> Result<(), Exception> {
+        // Run a cycle on peripheral devices.
+        self.bus.devices_increment();
+
+        // Interleave the harts deterministically, one instruction each.
+        for hart in 0..self.harts.len() {
+            self.step_hart(hart)?;
+            if self.exit_code.is_some() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Take a pending interrupt and execute an instruction on the hart `hart`. A single-stepping
+    /// hart halts afterwards.
+    fn step_hart(&mut self, hart: usize) -> Result<(), Exception> {
+        let stepping = self.harts[hart].is_stepping();
+        let result = self.execute_hart(hart);
+
+        // 4.5 Single Step
+        // "If the instruction being stepped over causes an exception, the hart enters Debug Mode
+        // before executing the trap handler". An ebreak that entered debug mode takes priority.
+        let cpu = &mut self.harts[hart];
+        if result.is_ok() && stepping && !cpu.is_halted() {
+            DebugCause::Step.enter(cpu);
+        }
+        result
+    }
+
+    /// Take a pending interrupt and execute an instruction on the hart `hart`.
+    fn execute_hart(&mut self, hart: usize) -> Result<(), Exception> {
+        self.harts[hart].devices_increment(&self.bus);
+        if !self.take_interrupt(hart) {
+            return Ok(());
+        }
+
+        // Execute an instruction.
+        let cpu = &mut self.harts[hart];
+        let pc = cpu.pc;
+        let running = !cpu.idle && !cpu.is_halted();
+        // An idle hart with a pending interrupt wakes up and executes an instruction.
+        let executes = !cpu.is_waiting_for_interrupt() && !cpu.is_halted();
+        let result = self.execute_instruction(hart, pc, running);
+        if executes {
+            if let Some(journal) = self.bus.journal.as_mut() {
+                journal.retire(hart as u32);
+            }
+        }
+        result
+    }
+
+    /// Take a pending interrupt on the hart `hart`. A replay takes the interrupts and the wake-ups
+    /// from WFI in the log instead, and returns false if the hart keeps waiting in WFI.
+    fn take_interrupt(&mut self, hart: usize) -> bool {
+        let cpu = &mut self.harts[hart];
+        let hartid = hart as u32;
+        match self.bus.journal.as_mut() {
+            Some(journal) if journal.is_replaying() => {
+                if let Some(interrupt) = journal.replay_interrupt(hartid) {
+                    interrupt.take_trap(cpu);
+                }
+                if cpu.idle {
+                    if !journal.replay_wake(hartid) {
+                        return false;
+                    }
+                    cpu.idle = false;
+                }
+            }
+            mut journal => {
+                if let Some(interrupt) = cpu.check_pending_interrupt() {
+                    if let Some(journal) = journal.as_mut() {
+                        journal.record_interrupt(hartid, interrupt);
+                    }
+                    interrupt.take_trap(cpu);
+                }
+                // An idle hart wakes up in `Cpu::execute` if an interrupt is pending.
+                if cpu.idle && !cpu.is_waiting_for_interrupt() {
+                    if let Some(journal) = journal.as_mut() {
+                        journal.record_wake(hartid);
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Execute an instruction on the hart `hart`, whose program counter is `pc`.
+    fn execute_instruction(
+        &mut self,
+        hart: usize,
+        pc: u32,
+        running: bool,
+    ) -> Result<(), Exception> {
+        let cpu = &mut self.harts[hart];
+        match cpu.execute(&mut self.bus) {
+            Ok(inst) => {
+                // Only the first hart is profiled.
+                if let Some(profiler) = self.profiler.as_mut() {
+                    if hart == 0 && running {
+                        profiler.record(pc, inst, cpu.pc);
+                    }
+                }
+                trace(
+                    self.is_debug,
+                    format_args!("hart: {}, pc: {:#x}, inst: {:#x}", hart, pc, inst),
+                );
+                Ok(())
+            }
+            Err(Exception::Breakpoint(_)) if self.is_semihosting_call(hart) => {
+                if let Some(host) = self.semihosting.as_deref_mut() {
+                    // The answers of the host are journaled when the run is recorded or replayed.
+                    let cpu = &mut self.harts[hart];
+                    let mut journal = self.bus.journal.take();
+                    self.exit_code = match journal.as_mut() {
+                        Some(journal) => {
+                            let mut host = JournaledHost { host, journal };
+                            semihosting::call(cpu, &mut self.bus, &mut host)
+                        }
+                        None => semihosting::call(cpu, &mut self.bus, host),
+                    };
+                    self.bus.journal = journal;
+                }
+                Ok(())
+            }
+            Err(exception) => self.handle_exception(hart, pc, exception),
+        }
+    }
+
+    /// Take the trap for the exception the instruction at `pc` on the hart `hart` raised, unless
+    /// it is fatal.
+    fn handle_exception(
+        &mut self,
+        hart: usize,
+        pc: u32,
+        exception: Exception,
+    ) -> Result<(), Exception> {
+        trace(
+            self.is_debug,
+            format_args!("hart: {}, pc: {:#x}, exception: {:?}", hart, pc, exception),
+        );
+        // A fatal trap terminates the execution environment, and taking it leaves the CPU state
+        // as it was when the exception was raised.
+        match exception.take_trap(&mut self.harts[hart]) {
+            Trap::Fatal => Err(exception),
+            _ => Ok(()),
+        }
+    }
+
+    /// Return true if semihosting is enabled and the `ebreak` at the program counter is a
+    /// semihosting call.
+    fn is_semihosting_call(&mut self, hart: usize) -> bool {
+        self.semihosting.is_some()
+            && semihosting::is_semihosting_call(&self.harts[hart], &mut self.bus)
+    }
+
+    /// Start recording the non-deterministic inputs of the guest into a log, which
+    /// [`Emulator::start_replay`] replays. See the [`replay`](crate::replay) module for what the
+    /// log holds.
+    pub fn start_recording(&mut self) {
+        self.bus.journal = Some(Journal::record());
+    }
+
+    /// Start replaying `log`. The emulator must be in the state the recording started from, such
+    /// as a fork of the recorded emulator made at that point.
+    pub fn start_replay(&mut self, log: Log) {
+        self.bus.journal = Some(Journal::replay(log));
+    }
+
+    /// Stop recording or replaying, and return the recorded log or the log that was replayed.
+    pub fn stop_journal(&mut self) -> Option<Log> {
+        self.bus.journal.take().map(Journal::into_log)
+    }
+
+    /// Return true if a replay is in progress: the log hasn't run out, and the replay hasn't
+    /// diverged from it.
+    pub fn is_replaying(&self) -> bool {
+        let journal = self.bus.journal.as_ref();
+        journal.is_some_and(|journal| journal.is_replaying())
+    }
+
+    /// Return true if the replay diverged from the log: the guest read a value from a device, a
+    /// volatile CSR, the host, or a custom instruction that the log didn't have next, or a custom
+    /// instruction raised an exception the log didn't have.
+    pub fn has_diverged(&self) -> bool {
+        let journal = self.bus.journal.as_ref();
+        journal.is_some_and(|journal| journal.has_diverged())
+    }
+
+    /// Start recording the coverage of the decoder and the trap causes on all the harts. See the
+    /// [`coverage`](crate::coverage) module for what it records.
+    pub fn enable_coverage(&mut self) {
+        for cpu in self.harts.iter_mut() {
+            cpu.coverage.get_or_insert_with(Coverage::new);
+        }
+    }
+
+    /// Return the coverage of all the harts together, or `None` if coverage is disabled.
+    pub fn coverage(&self) -> Option<Coverage> {
+        let mut harts = self.harts.iter().filter_map(|cpu| cpu.coverage.as_ref());
+        let mut coverage = harts.next()?.clone();
+        for other in harts {
+            coverage.merge(other);
+        }
+        Some(coverage)
+    }
+
+    /// Start executing the first hart with limited range of program. This method is for test.
+    /// No interrupts happen.
+    pub fn test_start(&mut self, start: u32, end: u32) {
+        let cpu = &mut self.harts[0];
+        loop {
+            if cpu.pc < start || end <= cpu.pc {
+                return;
+            }
+
+            let pc = cpu.pc;
+            match cpu.execute(&mut self.bus) {
+                Ok(inst) => {
+                    trace(
+                        self.is_debug,
+                        format_args!("pc: {:#x}, inst: {:#x}", pc, inst),
+                    );
+                }
+                Err(exception) => {
+                    trace(
+                        self.is_debug,
+                        format_args!("pc: {:#x}, exception: {:?}", pc, exception),
+                    );
+                    if let Trap::Fatal = exception.take_trap(cpu) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Execute at most `steps` steps, and stop early when the guest exits or all the harts are
+    /// idle. Returns the exception that terminated the execution environment, if any.
+    ///
+    /// When the harts are idle, the embedder either calls [`Emulator::fast_forward`] with the
+    /// cycles until the next event, or blocks until it injects an interrupt, and then runs again.
+    pub fn run(&mut self, steps: u64) -> Result<RunStatus, Exception> {
+        for _ in 0..steps {
+            if let Some(code) = self.exit_code {
+                return Ok(RunStatus::Exited(code));
+            }
+            if self.is_idle() {
+                return Ok(RunStatus::Idle {
+                    next_event: self.bus.cycles_until_event(),
+                });
+            }
+            self.step()?;
+        }
+        Ok(match self.exit_code {
+            Some(code) => RunStatus::Exited(code),
+            None => RunStatus::Paused,
+        })
+    }
+
+    /// Return true if all the harts are idle in WFI and no interrupt is pending to wake them up.
+    /// The pending bits are updated first, so an interrupt injected by the embedder counts. A
+    /// replay is never idle until the log runs out.
+    pub fn is_idle(&mut self) -> bool {
+        // A replay wakes the harts up from the log.
+        if self.is_replaying() {
+            return false;
+        }
+        for cpu in self.harts.iter_mut() {
+            cpu.update_pending_interrupts(&self.bus);
+        }
+        self.harts.iter().all(|cpu| cpu.is_waiting_for_interrupt())
+    }
+
+    /// Advance the virtual time by `cycles` cycles at once without executing instructions, as if
+    /// the idle harts had waited that long.
+    pub fn fast_forward(&mut self, cycles: u64) {
+        self.bus.devices_advance(cycles);
+        for cpu in self.harts.iter_mut() {
+            cpu.advance_counters(cycles);
+        }
+    }
+
+    /// Start executing the emulator until a fatal trap happens or the guest exits. Idle harts skip
+    /// ahead to the next device event, and the execution stops if no event can wake them up.
+    pub fn start(&mut self) {
+        loop {
+            match self.run(u64::MAX) {
+                Ok(RunStatus::Idle {
+                    next_event: Some(cycles),
+                }) => self.fast_forward(cycles),
+                _ => return,
+            }
+        }
+    }
+}
+
+/// Print a line to stdout if the debug flag is set. Tracing needs the `std` feature.
+fn trace(is_debug: bool, args: fmt::Arguments) {
+    #[cfg(feature = "std")]
+    if is_debug {
+        std::println!("{}", args);
+    }
+    #[cfg(not(feature = "std"))]
+    let _ = (is_debug, args);
+}
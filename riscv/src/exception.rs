@@ -8,19 +8,22 @@ use crate::{
 /// All the exception kinds.
 #[derive(Debug, PartialEq)]
 pub enum Exception {
+    // Address-misaligned, access-fault, and page-fault exceptions store a trap value (the
+    // faulting address), and the illegal instruction exception stores the faulting instruction.
+    // The breakpoint exception stores the address of the `ebreak`, or the address of the access
+    // that fired a trigger.
     /// With the addition of the C extension, no instructions can raise
     /// instruction-address-misaligned exceptions.
-    InstructionAddressMisaligned,
-    InstructionAccessFault,
+    InstructionAddressMisaligned(u32),
+    InstructionAccessFault(u32),
     IllegalInstruction(u32),
-    Breakpoint,
-    LoadAddressMisaligned,
-    LoadAccessFault,
-    StoreAMOAddressMisaligned,
-    StoreAMOAccessFault,
+    Breakpoint(u32),
+    LoadAddressMisaligned(u32),
+    LoadAccessFault(u32),
+    StoreAMOAddressMisaligned(u32),
+    StoreAMOAccessFault(u32),
     EnvironmentCallFromUMode,
     EnvironmentCallFromMMode,
-    // Stores a trap value (the faulting address) for page fault exceptions.
     InstructionPageFault(u32),
     LoadPageFault(u32),
     StoreAMOPageFault(u32),
@@ -44,16 +47,16 @@ pub enum Trap {
 }
 
 impl Exception {
-    fn exception_code(&self) -> u32 {
+    pub(crate) fn exception_code(&self) -> u32 {
         match self {
-            Exception::InstructionAddressMisaligned => 0,
-            Exception::InstructionAccessFault => 1,
+            Exception::InstructionAddressMisaligned(_) => 0,
+            Exception::InstructionAccessFault(_) => 1,
             Exception::IllegalInstruction(_) => 2,
-            Exception::Breakpoint => 3,
-            Exception::LoadAddressMisaligned => 4,
-            Exception::LoadAccessFault => 5,
-            Exception::StoreAMOAddressMisaligned => 6,
-            Exception::StoreAMOAccessFault => 7,
+            Exception::Breakpoint(_) => 3,
+            Exception::LoadAddressMisaligned(_) => 4,
+            Exception::LoadAccessFault(_) => 5,
+            Exception::StoreAMOAddressMisaligned(_) => 6,
+            Exception::StoreAMOAccessFault(_) => 7,
             Exception::EnvironmentCallFromUMode => 8,
             Exception::EnvironmentCallFromMMode => 11,
             Exception::InstructionPageFault(_) => 12,
@@ -62,24 +65,7 @@ impl Exception {
         }
     }
 
-    fn epc(&self, pc: u32) -> u32 {
-        // 3.2.1 Environment Call and Breakpoint
-        // "ECALL and EBREAK cause the receiving privilege mode’s epc register to be set to the
-        // address of the ECALL or EBREAK instruction itself, not the address of the following
-        // instruction."
-        match self {
-            Exception::Breakpoint
-            | Exception::EnvironmentCallFromUMode
-            | Exception::EnvironmentCallFromMMode
-            // TODO: why page fault needs this?
-            | Exception::InstructionPageFault(_)
-            | Exception::LoadPageFault(_)
-            | Exception::StoreAMOPageFault(_) => pc,
-            _ => pc.wrapping_add(4),
-        }
-    }
-
-    fn trap_value(&self, pc: u32) -> u32 {
+    fn trap_value(&self) -> u32 {
         // 3.1.17 Machine Trap Value Register (mtval)
         // 4.1.9 Supervisor Trap Value Register (stval)
         // "When a hardware breakpoint is triggered, or an address-misaligned, access-fault, or
@@ -89,17 +75,17 @@ impl Exception {
         // below. For other traps, mtval (stval) is set to zero, but a future standard may redefine
         // mtval's (stval's) setting for other traps."
         match self {
-            Exception::InstructionAddressMisaligned
-            | Exception::InstructionAccessFault
-            | Exception::Breakpoint
-            | Exception::LoadAddressMisaligned
-            | Exception::LoadAccessFault
-            | Exception::StoreAMOAddressMisaligned
-            | Exception::StoreAMOAccessFault => pc,
-            Exception::InstructionPageFault(val)
+            Exception::InstructionAddressMisaligned(val)
+            | Exception::InstructionAccessFault(val)
+            | Exception::LoadAddressMisaligned(val)
+            | Exception::LoadAccessFault(val)
+            | Exception::StoreAMOAddressMisaligned(val)
+            | Exception::StoreAMOAccessFault(val)
+            | Exception::InstructionPageFault(val)
             | Exception::LoadPageFault(val)
-            | Exception::StoreAMOPageFault(val) => *val,
-            Exception::IllegalInstruction(val) => *val,
+            | Exception::StoreAMOPageFault(val)
+            | Exception::IllegalInstruction(val)
+            | Exception::Breakpoint(val) => *val,
             _ => 0,
         }
     }
@@ -110,15 +96,34 @@ impl Exception {
         // "Traps that increase privilege level are termed vertical traps, while traps that remain
         // at the same privilege level are termed horizontal traps."
 
-        let exception_pc = self.epc(cpu.pc);
+        // The program counter still points to the instruction that raised the exception.
+        let exception_pc = cpu.pc;
         let previous_mode = cpu.mode;
         let cause = self.exception_code();
+        if let Some(coverage) = cpu.coverage.as_mut() {
+            coverage.hit_exception(self);
+        }
+
+        // The trap handler can't be fetched either, so the same fault would repeat forever.
+        if self.faults_on_trap_handler(cpu) {
+            return Trap::Fatal;
+        }
+
+        // 4.1 Debug Mode (Sdext)
+        // "Exceptions don't update any registers. That includes cause, epc, tval, dpc, and
+        // mstatus. They do end execution of the Program Buffer." The hart stays in debug mode.
+        if previous_mode == Mode::Debug {
+            return self.trap();
+        }
+        cpu.state.increment_event(HPM_EVENT_TRAP);
 
         // Handle the trap in M-mode.
         cpu.mode = Mode::Machine;
 
         // Set the program counter to the machine trap-handler base address (mtvec).
-        cpu.pc = (cpu.state.read(MTVEC) & !1) as u32;
+        // "When MODE=Vectored, all synchronous exceptions into machine mode cause the pc to be set
+        // to the address in the BASE field".
+        cpu.pc = cpu.state.read(MTVEC) & !0b11;
 
         // 3.1.15 Machine Exception Program Counter (mepc)
         // "The low bit of mepc (mepc[0]) is always zero."
@@ -139,7 +144,7 @@ impl Exception {
         // exception-specific information to assist software in handling the trap.
         // Otherwise, mtval is never written by the implementation, though it may be
         // explicitly written by software."
-        cpu.state.write(MTVAL, self.trap_value(exception_pc));
+        cpu.state.write(MTVAL, self.trap_value());
 
         // Set a previous interrupt-enable bit for machine mode (MPIE, 7) to the value
         // of a global interrupt-enable bit for machine mode (MIE, 3).
@@ -149,22 +154,41 @@ impl Exception {
         cpu.state.write_mstatus(MSTATUS_MIE, 0);
         // When a trap is taken from privilege mode y into privilege mode x, xPIE is set
         // to the value of x IE; x IE is set to 0; and xPP is set to y.
-        match previous_mode {
-            Mode::User => cpu.state.write_mstatus(MSTATUS_MPP, Mode::User as u32),
-            Mode::Machine => cpu.state.write_mstatus(MSTATUS_MPP, Mode::Machine as u32),
-            _ => panic!("previous privilege mode is invalid"),
-        }
+        let mpp = match previous_mode {
+            Mode::User => Mode::User as u32,
+            _ => Mode::Machine as u32,
+        };
+        cpu.state.write_mstatus(MSTATUS_MPP, mpp);
+
+        self.trap()
+    }
 
+    /// Return true if this is an instruction access fault on fetching the trap handler at mtvec,
+    /// which a trap into M-mode would only raise again.
+    fn faults_on_trap_handler(&self, cpu: &Cpu) -> bool {
         match self {
-            Exception::InstructionAddressMisaligned | Exception::InstructionAccessFault => {
-                Trap::Fatal
+            Exception::InstructionAccessFault(addr) => {
+                cpu.mode != Mode::Debug && *addr == cpu.state.read(MTVEC) & !0b11
             }
+            _ => false,
+        }
+    }
+
+    /// Return how the execution environment treats this exception. An instruction access fault
+    /// on fetching the trap handler itself is fatal instead, which [`Exception::take_trap`]
+    /// returns.
+    pub fn trap(&self) -> Trap {
+        match self {
             Exception::IllegalInstruction(_) => Trap::Invisible,
-            Exception::Breakpoint => Trap::Requested,
-            Exception::LoadAddressMisaligned
-            | Exception::LoadAccessFault
-            | Exception::StoreAMOAddressMisaligned
-            | Exception::StoreAMOAccessFault => Trap::Fatal,
+            Exception::Breakpoint(_) => Trap::Requested,
+            // Taken so that a trap handler can emulate the faulting access. Without a handler,
+            // fetching from mtvec fails and terminates the execution.
+            Exception::InstructionAddressMisaligned(_)
+            | Exception::InstructionAccessFault(_)
+            | Exception::LoadAddressMisaligned(_)
+            | Exception::LoadAccessFault(_)
+            | Exception::StoreAMOAddressMisaligned(_)
+            | Exception::StoreAMOAccessFault(_) => Trap::Contained,
             Exception::EnvironmentCallFromUMode | Exception::EnvironmentCallFromMMode => {
                 Trap::Requested
             }
@@ -0,0 +1,300 @@
+//! The framebuffer module contains a memory-mapped display: an RGB565 pixel buffer the guest draws
+//! into, registers to flush a dirty rectangle to the host, and a vsync interrupt.
+//!
+//! The guest writes pixels to the pixel buffer at `FRAMEBUFFER_VRAM`, one little-endian halfword
+//! per pixel in row-major order, then writes the changed area to the dirty rectangle registers and
+//! sets `FRAMEBUFFER_CONTROL_FLUSH`. The embedder takes the flushed rectangle with
+//! [`Framebuffer::take_flush`] and presents the pixels on a real display.
+
+use alloc::vec::Vec;
+
+use crate::cpu::{BYTE, HALFWORD, WORD};
+use crate::exception::Exception;
+
+/// The address which the framebuffer registers start.
+pub const FRAMEBUFFER_BASE: u32 = 0x300_0000;
+/// The size of the framebuffer registers.
+pub const FRAMEBUFFER_SIZE: u32 = 0x1000;
+/// The address which the pixel buffer starts.
+pub const FRAMEBUFFER_VRAM: u32 = 0x400_0000;
+/// The size of the address window reserved for the pixel buffer, which bounds its size.
+pub const FRAMEBUFFER_VRAM_SIZE: u32 = 0x100_0000;
+
+/// The width in pixels. Read-only.
+pub const FRAMEBUFFER_WIDTH: u32 = FRAMEBUFFER_BASE;
+/// The height in pixels. Read-only.
+pub const FRAMEBUFFER_HEIGHT: u32 = FRAMEBUFFER_BASE + 0x04;
+/// The left edge of the dirty rectangle.
+pub const FRAMEBUFFER_DIRTY_X: u32 = FRAMEBUFFER_BASE + 0x08;
+/// The top edge of the dirty rectangle.
+pub const FRAMEBUFFER_DIRTY_Y: u32 = FRAMEBUFFER_BASE + 0x0c;
+/// The width of the dirty rectangle.
+pub const FRAMEBUFFER_DIRTY_WIDTH: u32 = FRAMEBUFFER_BASE + 0x10;
+/// The height of the dirty rectangle.
+pub const FRAMEBUFFER_DIRTY_HEIGHT: u32 = FRAMEBUFFER_BASE + 0x14;
+/// The control register.
+pub const FRAMEBUFFER_CONTROL: u32 = FRAMEBUFFER_BASE + 0x18;
+/// The status register. Writing 1 to a bit clears it.
+pub const FRAMEBUFFER_STATUS: u32 = FRAMEBUFFER_BASE + 0x1c;
+/// The number of vsyncs since the framebuffer was created. Read-only.
+pub const FRAMEBUFFER_FRAME: u32 = FRAMEBUFFER_BASE + 0x20;
+
+/// Flush the dirty rectangle to the host. Always reads as zero.
+pub const FRAMEBUFFER_CONTROL_FLUSH: u32 = 1 << 0;
+/// Raise the vsync interrupt.
+pub const FRAMEBUFFER_CONTROL_VSYNC_IRQ: u32 = 1 << 1;
+
+/// A flush hasn't been presented by the host yet. Read-only.
+pub const FRAMEBUFFER_STATUS_FLUSH: u32 = 1 << 0;
+/// A vsync happened.
+pub const FRAMEBUFFER_STATUS_VSYNC: u32 = 1 << 1;
+
+/// The default number of cycles between two vsyncs.
+pub const DEFAULT_VSYNC_PERIOD: u64 = 100_000;
+
+/// A rectangle in pixels.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// Return the smallest rectangle that contains both rectangles.
+    fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Rect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+}
+
+/// The framebuffer device.
+#[derive(Clone)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    /// The RGB565 pixels in row-major order.
+    pixels: Vec<u16>,
+    dirty: Rect,
+    control: u32,
+    status: u32,
+    frame: u32,
+    /// The flushed area the host hasn't presented yet.
+    flush: Option<Rect>,
+    vsync_period: u64,
+    cycles: u64,
+}
+
+impl Framebuffer {
+    /// Create a new black framebuffer of `width` x `height` pixels. Panics if the pixel buffer
+    /// doesn't fit in its window of `FRAMEBUFFER_VRAM_SIZE` bytes.
+    pub fn new(width: u32, height: u32) -> Self {
+        let vram_size = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(2));
+        assert!(
+            vram_size.is_some_and(|size| size <= FRAMEBUFFER_VRAM_SIZE),
+            "a {}x{} pixel buffer doesn't fit in {:#x} bytes",
+            width,
+            height,
+            FRAMEBUFFER_VRAM_SIZE
+        );
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize],
+            dirty: Rect {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+            },
+            control: 0,
+            status: 0,
+            frame: 0,
+            flush: None,
+            vsync_period: DEFAULT_VSYNC_PERIOD,
+            cycles: 0,
+        }
+    }
+
+    /// Set the number of cycles between two vsyncs.
+    pub fn set_vsync_period(&mut self, cycles: u64) {
+        self.vsync_period = cycles.max(1);
+    }
+
+    /// Return the width in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Return the height in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Return the RGB565 pixel at (`x`, `y`).
+    pub fn pixel(&self, x: u32, y: u32) -> u16 {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Take the area the guest flushed since the last call, if any.
+    pub fn take_flush(&mut self) -> Option<Rect> {
+        self.status &= !FRAMEBUFFER_STATUS_FLUSH;
+        self.flush.take()
+    }
+
+    /// Run a cycle: raise a vsync once every vsync period.
+    pub fn increment(&mut self) {
+        self.advance(1);
+    }
+
+    /// Run `cycles` cycles at once.
+    pub fn advance(&mut self, cycles: u64) {
+        let cycles = self.cycles + cycles;
+        let vsyncs = cycles / self.vsync_period;
+        self.cycles = cycles % self.vsync_period;
+        if vsyncs > 0 {
+            self.frame = self.frame.wrapping_add(vsyncs as u32);
+            self.status |= FRAMEBUFFER_STATUS_VSYNC;
+        }
+    }
+
+    /// Return the number of cycles until the next vsync interrupt, or `None` if the interrupt is
+    /// disabled.
+    pub fn cycles_until_interrupt(&self) -> Option<u64> {
+        if self.control & FRAMEBUFFER_CONTROL_VSYNC_IRQ == 0 {
+            return None;
+        }
+        Some(self.vsync_period - self.cycles)
+    }
+
+    /// Return true if the vsync interrupt is enabled and pending.
+    pub fn is_interrupting(&self) -> bool {
+        self.control & FRAMEBUFFER_CONTROL_VSYNC_IRQ != 0
+            && self.status & FRAMEBUFFER_STATUS_VSYNC != 0
+    }
+
+    /// Return the size of the pixel buffer in bytes. `new` checked that it fits in its window.
+    pub fn vram_size(&self) -> u32 {
+        self.pixels.len() as u32 * 2
+    }
+
+    /// Load `size`-bit data from the pixel buffer.
+    pub fn read_vram(&self, addr: u32, size: u8) -> Result<u32, Exception> {
+        let offset = addr - FRAMEBUFFER_VRAM;
+        let len = match size {
+            BYTE | HALFWORD | WORD => size as u32 / 8,
+            _ => return Err(Exception::LoadAccessFault(addr)),
+        };
+        if offset + len > self.vram_size() {
+            return Err(Exception::LoadAccessFault(addr));
+        }
+        let mut value = 0;
+        for i in (0..len).rev() {
+            value = (value << 8) | self.vram_byte(offset + i) as u32;
+        }
+        Ok(value)
+    }
+
+    /// Store `size`-bit data to the pixel buffer.
+    pub fn write_vram(&mut self, addr: u32, value: u32, size: u8) -> Result<(), Exception> {
+        let offset = addr - FRAMEBUFFER_VRAM;
+        let len = match size {
+            BYTE | HALFWORD | WORD => size as u32 / 8,
+            _ => return Err(Exception::StoreAMOAccessFault(addr)),
+        };
+        if offset + len > self.vram_size() {
+            return Err(Exception::StoreAMOAccessFault(addr));
+        }
+        for i in 0..len {
+            self.set_vram_byte(offset + i, (value >> (8 * i)) as u8);
+        }
+        Ok(())
+    }
+
+    /// Return the byte at `offset` in the pixel buffer.
+    fn vram_byte(&self, offset: u32) -> u8 {
+        let pixel = self.pixels[(offset / 2) as usize];
+        (pixel >> (8 * (offset % 2))) as u8
+    }
+
+    /// Set the byte at `offset` in the pixel buffer.
+    fn set_vram_byte(&mut self, offset: u32, value: u8) {
+        let pixel = &mut self.pixels[(offset / 2) as usize];
+        let shift = 8 * (offset % 2);
+        *pixel = (*pixel & !(0xff << shift)) | ((value as u16) << shift);
+    }
+
+    /// Load a word from a framebuffer register. Only word accesses are supported.
+    pub fn read(&self, addr: u32, size: u8) -> Result<u32, Exception> {
+        if size != WORD {
+            return Err(Exception::LoadAccessFault(addr));
+        }
+        let value = match addr {
+            FRAMEBUFFER_WIDTH => self.width,
+            FRAMEBUFFER_HEIGHT => self.height,
+            FRAMEBUFFER_DIRTY_X => self.dirty.x,
+            FRAMEBUFFER_DIRTY_Y => self.dirty.y,
+            FRAMEBUFFER_DIRTY_WIDTH => self.dirty.width,
+            FRAMEBUFFER_DIRTY_HEIGHT => self.dirty.height,
+            FRAMEBUFFER_CONTROL => self.control,
+            FRAMEBUFFER_STATUS => self.status,
+            FRAMEBUFFER_FRAME => self.frame,
+            _ => return Err(Exception::LoadAccessFault(addr)),
+        };
+        Ok(value)
+    }
+
+    /// Store a word to a framebuffer register. Only word accesses are supported.
+    pub fn write(&mut self, addr: u32, value: u32, size: u8) -> Result<(), Exception> {
+        if size != WORD {
+            return Err(Exception::StoreAMOAccessFault(addr));
+        }
+        match addr {
+            FRAMEBUFFER_WIDTH | FRAMEBUFFER_HEIGHT | FRAMEBUFFER_FRAME => {}
+            FRAMEBUFFER_DIRTY_X => self.dirty.x = value,
+            FRAMEBUFFER_DIRTY_Y => self.dirty.y = value,
+            FRAMEBUFFER_DIRTY_WIDTH => self.dirty.width = value,
+            FRAMEBUFFER_DIRTY_HEIGHT => self.dirty.height = value,
+            FRAMEBUFFER_CONTROL => {
+                self.control = value & FRAMEBUFFER_CONTROL_VSYNC_IRQ;
+                if value & FRAMEBUFFER_CONTROL_FLUSH != 0 {
+                    self.flush_dirty();
+                }
+            }
+            FRAMEBUFFER_STATUS => self.status &= !(value & FRAMEBUFFER_STATUS_VSYNC),
+            _ => return Err(Exception::StoreAMOAccessFault(addr)),
+        }
+        Ok(())
+    }
+
+    /// Add the dirty rectangle, clipped to the framebuffer, to the flushed area.
+    fn flush_dirty(&mut self) {
+        let x = self.dirty.x.min(self.width);
+        let y = self.dirty.y.min(self.height);
+        let rect = Rect {
+            x,
+            y,
+            width: self.dirty.width.min(self.width - x),
+            height: self.dirty.height.min(self.height - y),
+        };
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        self.flush = Some(match self.flush {
+            Some(flush) => flush.union(&rect),
+            None => rect,
+        });
+        self.status |= FRAMEBUFFER_STATUS_FLUSH;
+    }
+}
@@ -0,0 +1,44 @@
+//! The hook module lets the embedder intercept the memory accesses of the harts to an address
+//! range, without modifying the devices behind it. A hook can observe an access, rewrite the value
+//! loaded or stored, or make the access fail with an access fault.
+//!
+//! Hooks are registered with [`Bus::add_hook`](crate::bus::Bus::add_hook). They see instruction
+//! fetches, loads, and stores (including those of LR/SC and AMOs) made by harts. Accesses the
+//! embedder makes directly with `Bus::read` and `Bus::write` bypass them.
+
+use crate::cpu::AccessType;
+
+/// A memory access made by a hart.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Access {
+    /// The ID of the hart that makes the access.
+    pub hartid: u32,
+    /// Whether the access is an instruction fetch, a load, or a store.
+    pub access_type: AccessType,
+    /// The address of the access.
+    pub addr: u32,
+    /// The size of the access in bits.
+    pub size: u8,
+}
+
+/// The identifier of a registered hook, which removes it.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct HookId(pub(crate) usize);
+
+/// A callback on the memory accesses to an address range. Both methods return the value of the
+/// access, or `None` to raise the access fault of the access type instead.
+pub trait MemoryHook {
+    /// Called after a fetch or a load read `value` from the device. The hart gets the returned
+    /// value. A load that faults in the device doesn't call the hook.
+    fn read(&mut self, access: &Access, value: u32) -> Option<u32> {
+        let _ = access;
+        Some(value)
+    }
+
+    /// Called before a store writes `value` to the device. The returned value is written, and
+    /// nothing is written if the store faults.
+    fn write(&mut self, access: &Access, value: u32) -> Option<u32> {
+        let _ = access;
+        Some(value)
+    }
+}
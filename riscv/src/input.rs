@@ -0,0 +1,173 @@
+//! The input module contains a memory-mapped input device: a FIFO of keyboard, pointer, and
+//! rotary events that the embedder pushes and the guest pops, with an interrupt while events are
+//! queued.
+//!
+//! The guest reads the event at the head of the FIFO from `INPUT_EVENT_KIND`, `INPUT_EVENT_DATA0`
+//! and `INPUT_EVENT_DATA1`, then writes any value to `INPUT_POP` to remove it.
+
+use alloc::collections::VecDeque;
+
+use crate::cpu::WORD;
+use crate::exception::Exception;
+
+/// The address which the input device starts.
+pub const INPUT_BASE: u32 = 0x300_1000;
+/// The size of the input device.
+pub const INPUT_SIZE: u32 = 0x1000;
+
+/// The status register. Writing 1 to `INPUT_STATUS_OVERFLOW` clears it.
+pub const INPUT_STATUS: u32 = INPUT_BASE;
+/// The control register.
+pub const INPUT_CONTROL: u32 = INPUT_BASE + 0x04;
+/// The number of queued events. Read-only.
+pub const INPUT_COUNT: u32 = INPUT_BASE + 0x08;
+/// The kind of the event at the head of the FIFO, or `INPUT_KIND_NONE`. Read-only.
+pub const INPUT_EVENT_KIND: u32 = INPUT_BASE + 0x0c;
+/// The first data word of the event at the head of the FIFO. Read-only.
+pub const INPUT_EVENT_DATA0: u32 = INPUT_BASE + 0x10;
+/// The second data word of the event at the head of the FIFO. Read-only.
+pub const INPUT_EVENT_DATA1: u32 = INPUT_BASE + 0x14;
+/// Writing any value removes the event at the head of the FIFO.
+pub const INPUT_POP: u32 = INPUT_BASE + 0x18;
+
+/// At least one event is queued. Read-only.
+pub const INPUT_STATUS_READY: u32 = 1 << 0;
+/// An event was dropped because the FIFO was full.
+pub const INPUT_STATUS_OVERFLOW: u32 = 1 << 1;
+
+/// Raise the interrupt while events are queued.
+pub const INPUT_CONTROL_IRQ: u32 = 1 << 0;
+
+/// The FIFO is empty.
+pub const INPUT_KIND_NONE: u32 = 0;
+/// A key was pressed. The data words are the keycode and the modifiers.
+pub const INPUT_KIND_KEY_DOWN: u32 = 1;
+/// A key was released. The data words are the keycode and the modifiers.
+pub const INPUT_KIND_KEY_UP: u32 = 2;
+/// The pointer moved. The data words are the x and y coordinates.
+pub const INPUT_KIND_POINTER_MOVE: u32 = 3;
+/// The pointer was pressed, or a touch started. The data words are the x and y coordinates.
+pub const INPUT_KIND_POINTER_DOWN: u32 = 4;
+/// The pointer was released, or a touch ended. The data words are the x and y coordinates.
+pub const INPUT_KIND_POINTER_UP: u32 = 5;
+/// A rotary encoder or trackball moved. The data words are the signed x and y deltas.
+pub const INPUT_KIND_SCROLL: u32 = 6;
+
+/// The shift modifier of a key event.
+pub const INPUT_MODIFIER_SHIFT: u32 = 1 << 0;
+/// The control modifier of a key event.
+pub const INPUT_MODIFIER_CTRL: u32 = 1 << 1;
+/// The alt modifier of a key event.
+pub const INPUT_MODIFIER_ALT: u32 = 1 << 2;
+/// The meta modifier of a key event.
+pub const INPUT_MODIFIER_META: u32 = 1 << 3;
+
+/// The number of events the FIFO holds.
+pub const INPUT_FIFO_DEPTH: usize = 64;
+
+/// An input event pushed by the embedder.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum InputEvent {
+    KeyDown { keycode: u32, modifiers: u32 },
+    KeyUp { keycode: u32, modifiers: u32 },
+    PointerMove { x: u32, y: u32 },
+    PointerDown { x: u32, y: u32 },
+    PointerUp { x: u32, y: u32 },
+    Scroll { dx: i32, dy: i32 },
+}
+
+impl InputEvent {
+    /// Return the kind and the data words the guest reads.
+    fn encode(&self) -> (u32, u32, u32) {
+        match *self {
+            InputEvent::KeyDown { keycode, modifiers } => (INPUT_KIND_KEY_DOWN, keycode, modifiers),
+            InputEvent::KeyUp { keycode, modifiers } => (INPUT_KIND_KEY_UP, keycode, modifiers),
+            InputEvent::PointerMove { x, y } => (INPUT_KIND_POINTER_MOVE, x, y),
+            InputEvent::PointerDown { x, y } => (INPUT_KIND_POINTER_DOWN, x, y),
+            InputEvent::PointerUp { x, y } => (INPUT_KIND_POINTER_UP, x, y),
+            InputEvent::Scroll { dx, dy } => (INPUT_KIND_SCROLL, dx as u32, dy as u32),
+        }
+    }
+}
+
+/// The input device.
+#[derive(Debug, Default, Clone)]
+pub struct Input {
+    fifo: VecDeque<InputEvent>,
+    control: u32,
+    overflow: bool,
+}
+
+impl Input {
+    /// Create a new input device with an empty FIFO.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue an event for the guest. Returns false and drops the event if the FIFO is full.
+    pub fn push(&mut self, event: InputEvent) -> bool {
+        if self.fifo.len() >= INPUT_FIFO_DEPTH {
+            self.overflow = true;
+            return false;
+        }
+        self.fifo.push_back(event);
+        true
+    }
+
+    /// Return true if the interrupt is enabled and events are queued.
+    pub fn is_interrupting(&self) -> bool {
+        self.control & INPUT_CONTROL_IRQ != 0 && !self.fifo.is_empty()
+    }
+
+    /// Load a word from an input register. Only word accesses are supported.
+    pub fn read(&self, addr: u32, size: u8) -> Result<u32, Exception> {
+        if size != WORD {
+            return Err(Exception::LoadAccessFault(addr));
+        }
+        let (kind, data0, data1) = match self.fifo.front() {
+            Some(event) => event.encode(),
+            None => (INPUT_KIND_NONE, 0, 0),
+        };
+        let value = match addr {
+            INPUT_STATUS => {
+                let mut status = 0;
+                if !self.fifo.is_empty() {
+                    status |= INPUT_STATUS_READY;
+                }
+                if self.overflow {
+                    status |= INPUT_STATUS_OVERFLOW;
+                }
+                status
+            }
+            INPUT_CONTROL => self.control,
+            INPUT_COUNT => self.fifo.len() as u32,
+            INPUT_EVENT_KIND => kind,
+            INPUT_EVENT_DATA0 => data0,
+            INPUT_EVENT_DATA1 => data1,
+            INPUT_POP => 0,
+            _ => return Err(Exception::LoadAccessFault(addr)),
+        };
+        Ok(value)
+    }
+
+    /// Store a word to an input register. Only word accesses are supported.
+    pub fn write(&mut self, addr: u32, value: u32, size: u8) -> Result<(), Exception> {
+        if size != WORD {
+            return Err(Exception::StoreAMOAccessFault(addr));
+        }
+        match addr {
+            INPUT_STATUS => {
+                if value & INPUT_STATUS_OVERFLOW != 0 {
+                    self.overflow = false;
+                }
+            }
+            INPUT_CONTROL => self.control = value & INPUT_CONTROL_IRQ,
+            INPUT_COUNT | INPUT_EVENT_KIND | INPUT_EVENT_DATA0 | INPUT_EVENT_DATA1 => {}
+            INPUT_POP => {
+                self.fifo.pop_front();
+            }
+            _ => return Err(Exception::StoreAMOAccessFault(addr)),
+        }
+        Ok(())
+    }
+}
@@ -6,50 +6,74 @@ use crate::{
 };
 
 /// All the interrupt kinds.
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Interrupt {
-    UserSoftwareInterrupt,
+    SupervisorSoftwareInterrupt,
     MachineSoftwareInterrupt,
-    UserTimerInterrupt,
+    SupervisorTimerInterrupt,
     MachineTimerInterrupt,
-    UserExternalInterrupt,
+    SupervisorExternalInterrupt,
     MachineExternalInterrupt,
 }
 
 impl Interrupt {
-    fn exception_code(&self) -> u32 {
+    /// All the interrupts a hart can take in decreasing priority order. S-mode isn't implemented,
+    /// so the supervisor-level interrupts are never taken.
+    pub const PRIORITY: [Interrupt; 3] = [
+        Interrupt::MachineExternalInterrupt,
+        Interrupt::MachineSoftwareInterrupt,
+        Interrupt::MachineTimerInterrupt,
+    ];
+
+    /// Return the exception code in mcause, which is also the bit of the interrupt in mip and mie.
+    pub fn exception_code(&self) -> u32 {
         match self {
-            Interrupt::UserSoftwareInterrupt => 0,
+            Interrupt::SupervisorSoftwareInterrupt => 1,
             Interrupt::MachineSoftwareInterrupt => 3,
-            Interrupt::UserTimerInterrupt => 4,
+            Interrupt::SupervisorTimerInterrupt => 5,
             Interrupt::MachineTimerInterrupt => 7,
-            Interrupt::UserExternalInterrupt => 8,
+            Interrupt::SupervisorExternalInterrupt => 9,
             Interrupt::MachineExternalInterrupt => 11,
         }
     }
 
+    /// Return the bit of the interrupt in mip and mie.
+    pub fn pending_bit(&self) -> u32 {
+        1 << self.exception_code()
+    }
+
     /// Update CSRs and the program counter depending on an interrupt.
     pub fn take_trap(&self, cpu: &mut Cpu) {
         // 1.2 Privilege Levels
         // "Traps that increase privilege level are termed vertical traps, while traps that remain
         // at the same privilege level are termed horizontal traps."
 
+        // 4.1 Debug Mode (Sdext)
+        // "All interrupts (including NMI) are masked." A halted hart never takes one.
+        if cpu.mode == Mode::Debug {
+            return;
+        }
         cpu.idle = false;
 
         let exception_pc = cpu.pc;
         let previous_mode = cpu.mode;
         let cause = self.exception_code();
+        cpu.state.increment_event(HPM_EVENT_TRAP);
+        if let Some(coverage) = cpu.coverage.as_mut() {
+            coverage.hit_interrupt(*self);
+        }
 
         // Handle the trap in M-mode.
         cpu.mode = Mode::Machine;
 
         // Set the program counter to the machine trap-handler base address (mtvec)
         // depending on the mode.
+        // "When MODE=Vectored, ... Asynchronous interrupts set pc to BASE+4×cause."
         let vector = match cpu.state.read_bit(MTVEC, 0) {
             1 => 4 * cause, // vectored mode
             _ => 0,         // direct mode
         };
-        cpu.pc = ((cpu.state.read(MTVEC) & !1) + vector) as u32;
+        cpu.pc = (cpu.state.read(MTVEC) & !0b11).wrapping_add(vector);
 
         // 3.1.15 Machine Exception Program Counter (mepc)
         // "The low bit of mepc (mepc[0]) is always zero."
@@ -85,10 +109,10 @@ impl Interrupt {
         cpu.state.write_mstatus(MSTATUS_MIE, 0);
         // When a trap is taken from privilege mode y into privilege mode x, xPIE is set
         // to the value of x IE; x IE is set to 0; and xPP is set to y.
-        match previous_mode {
-            Mode::User => cpu.state.write_mstatus(MSTATUS_MPP, Mode::User as u32),
-            Mode::Machine => cpu.state.write_mstatus(MSTATUS_MPP, Mode::Machine as u32),
-            _ => panic!("previous privilege mode is invalid"),
-        }
+        let mpp = match previous_mode {
+            Mode::User => Mode::User as u32,
+            _ => Mode::Machine as u32,
+        };
+        cpu.state.write_mstatus(MSTATUS_MPP, mpp);
     }
 }
@@ -1,9 +1,34 @@
+//! A RISC-V (RV32) emulator.
+//!
+//! The crate is `no_std` and needs `alloc`. The `std` feature adds host-only conveniences such as
+//! loading files and tracing to stdout.
+
+#![no_std]
+
+#[cfg(feature = "std")]
+extern crate std;
+
 #[macro_use]
 extern crate alloc;
 
+pub mod asm;
 pub mod bus;
+pub mod clint;
+pub mod config;
+pub mod coverage;
 pub mod cpu;
 pub mod csr;
+pub mod custom;
+pub mod debug;
 pub mod dram;
+pub mod emulator;
 pub mod exception;
+pub mod framebuffer;
+pub mod hook;
+pub mod input;
 pub mod interrupt;
+pub mod profiler;
+pub mod replay;
+pub mod semihosting;
+pub mod trigger;
+pub mod uart;
@@ -0,0 +1,203 @@
+//! The profiler module counts executed instructions per program counter and per call stack, and
+//! exports them as folded stacks for flame graphs.
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{self, Write};
+
+use elf::{
+    abi::{SHN_UNDEF, STT_FUNC, STT_NOTYPE},
+    endian::LittleEndian,
+    ElfBytes, ParseError,
+};
+
+/// A named address range, usually a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    /// The size in bytes. A symbol of size 0 extends to the next symbol.
+    pub size: u32,
+}
+
+/// A symbol table to resolve addresses to function names.
+#[derive(Debug, Default)]
+pub struct Symbols {
+    /// Sorted by address.
+    symbols: Vec<Symbol>,
+}
+
+impl Symbols {
+    /// Create an empty symbol table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the function symbols and untyped labels from an ELF file.
+    pub fn from_elf(data: &[u8]) -> Result<Self, ParseError> {
+        let file = ElfBytes::<LittleEndian>::minimal_parse(data)?;
+        let mut symbols = Self::new();
+        let Some((symtab, strtab)) = file.symbol_table()? else {
+            return Ok(symbols);
+        };
+        for symbol in symtab.iter() {
+            let symtype = symbol.st_symtype();
+            if (symtype != STT_FUNC && symtype != STT_NOTYPE) || symbol.st_shndx == SHN_UNDEF {
+                continue;
+            }
+            let name = strtab.get(symbol.st_name as usize)?;
+            if name.is_empty() {
+                continue;
+            }
+            symbols.insert(name, symbol.st_value as u32, symbol.st_size as u32);
+        }
+        Ok(symbols)
+    }
+
+    /// Add a symbol.
+    pub fn insert(&mut self, name: &str, addr: u32, size: u32) {
+        let index = self.symbols.partition_point(|s| s.addr <= addr);
+        self.symbols.insert(
+            index,
+            Symbol {
+                name: name.to_string(),
+                addr,
+                size,
+            },
+        );
+    }
+
+    /// Return the symbol that contains `addr`.
+    pub fn lookup(&self, addr: u32) -> Option<&Symbol> {
+        let index = self.symbols.partition_point(|s| s.addr <= addr);
+        let symbol = self.symbols[..index].last()?;
+        if symbol.size != 0 && addr - symbol.addr >= symbol.size {
+            return None;
+        }
+        Some(symbol)
+    }
+
+    /// Return the name of the symbol that contains `addr`, or the address in hex.
+    fn name(&self, addr: u32) -> String {
+        match self.lookup(addr) {
+            Some(symbol) => symbol.name.clone(),
+            None => format!("{:#010x}", addr),
+        }
+    }
+}
+
+/// Return true if `reg` is a link register (ra or t0) in the calling convention.
+fn is_link(reg: u32) -> bool {
+    reg == 1 || reg == 5
+}
+
+/// An exact profiler that counts every executed instruction.
+///
+/// Call stacks are tracked with the return-address stack hints of JAL and JALR: a jump that
+/// writes a link register (ra or t0) is a call, and a JALR that reads a link register without
+/// writing one is a return. Trap handlers are attributed to the interrupted call stack.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    /// The number of times each program counter was executed.
+    pcs: BTreeMap<u32, u64>,
+    /// The entry addresses of the functions in the current call stack, outermost first.
+    stack: Vec<u32>,
+    /// The index of each call stack seen so far in `counts`.
+    stack_ids: BTreeMap<Vec<u32>, usize>,
+    /// The number of instructions executed with each call stack at the top.
+    counts: Vec<u64>,
+    /// The index of the current call stack in `counts`.
+    current: usize,
+}
+
+impl Profiler {
+    /// Create a new profiler.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an instruction `inst` executed at `pc`. `next_pc` is the program counter after
+    /// the instruction.
+    pub fn record(&mut self, pc: u32, inst: u32, next_pc: u32) {
+        if self.stack.is_empty() {
+            // The first instruction is the entry of the outermost function.
+            self.stack.push(pc);
+            self.current = self.stack_id();
+        }
+
+        *self.pcs.entry(pc).or_insert(0) += 1;
+        self.counts[self.current] += 1;
+
+        let opcode = inst & 0x7f;
+        let rd = (inst >> 7) & 0x1f;
+        let rs1 = (inst >> 15) & 0x1f;
+        let (pop, push) = match opcode {
+            // jal
+            0x6f => (false, is_link(rd)),
+            // jalr
+            0x67 => match (is_link(rd), is_link(rs1)) {
+                (false, false) => (false, false),
+                (false, true) => (true, false),
+                (true, false) => (false, true),
+                (true, true) => (rd != rs1, true),
+            },
+            _ => return,
+        };
+        // Never pop the outermost function.
+        if pop && self.stack.len() > 1 {
+            self.stack.pop();
+        }
+        if push {
+            self.stack.push(next_pc);
+        }
+        if pop || push {
+            self.current = self.stack_id();
+        }
+    }
+
+    /// Return the index of the current call stack in `counts`.
+    fn stack_id(&mut self) -> usize {
+        if let Some(&id) = self.stack_ids.get(&self.stack) {
+            return id;
+        }
+        let id = self.counts.len();
+        self.counts.push(0);
+        self.stack_ids.insert(self.stack.clone(), id);
+        id
+    }
+
+    /// Return the number of times each program counter was executed.
+    pub fn pc_counts(&self) -> &BTreeMap<u32, u64> {
+        &self.pcs
+    }
+
+    /// Return the number of instructions executed in each function, resolved by `symbols`.
+    pub fn function_counts(&self, symbols: &Symbols) -> BTreeMap<String, u64> {
+        let mut functions = BTreeMap::new();
+        for (&pc, &count) in self.pcs.iter() {
+            *functions.entry(symbols.name(pc)).or_insert(0) += count;
+        }
+        functions
+    }
+
+    /// Write the call stacks in the folded format of Brendan Gregg's FlameGraph
+    /// (`main;foo;bar 42`), one line per call stack, with the functions resolved by `symbols`.
+    pub fn write_folded<W: Write>(&self, symbols: &Symbols, out: &mut W) -> fmt::Result {
+        let mut lines = BTreeMap::new();
+        for (stack, &id) in self.stack_ids.iter() {
+            if self.counts[id] == 0 {
+                continue;
+            }
+            let names: Vec<String> = stack.iter().map(|&addr| symbols.name(addr)).collect();
+            // Call stacks that resolve to the same names fold into one line.
+            *lines.entry(names.join(";")).or_insert(0) += self.counts[id];
+        }
+        for (line, count) in lines {
+            writeln!(out, "{} {}", line, count)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,441 @@
+//! The replay module records the non-deterministic inputs of a guest into a log, and replays a run
+//! from the log instruction for instruction, for example to debug a run from a device under the
+//! emulator with tracing enabled.
+//!
+//! Everything else the harts do is deterministic, so the log only holds:
+//! - the values of loads from devices (everything but DRAM),
+//! - the values of CSRs that advance with time or reflect interrupt lines: the counters, `mip`
+//!   and `sip`,
+//! - the interrupts the harts take, and the wake-ups from WFI, with the number of instructions
+//!   the hart executed before,
+//! - the values semihosting calls get from the host,
+//! - the values custom instructions write to registers and memory, and the exceptions they raise.
+//!
+//! A replay starts from the state the recording started from, such as a fork of the emulator made
+//! when the recording started. Interrupts and wake-ups only come from the log, and the values read
+//! from devices are replaced with the recorded ones. When the log runs out, the replay continues
+//! with the live devices. If the guest reads a value the log doesn't have next, the replay has
+//! diverged, and it also continues with the live devices. A custom instruction that raises a
+//! different exception than the recorded one also diverges the replay.
+
+use alloc::vec::Vec;
+
+use crate::csr::{CsrAddress, MIP, SIP};
+use crate::interrupt::Interrupt;
+use crate::semihosting::{Host, Stream};
+
+/// A non-deterministic input of the guest.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Event {
+    /// A load from a device returned the value.
+    Mmio(u32),
+    /// A read of a CSR that advances with time or reflects interrupt lines returned the value.
+    Csr(u32),
+    /// The hart took the interrupt with the exception code `cause` after executing `instret`
+    /// instructions since the recording started.
+    Interrupt { hart: u32, instret: u64, cause: u32 },
+    /// The hart woke up from WFI after executing `instret` instructions since the recording
+    /// started.
+    Wake { hart: u32, instret: u64 },
+    /// A request to the semihosting host returned the value, such as a character or a clock.
+    Host(Option<u64>),
+    /// A custom instruction wrote the value to a register or to memory.
+    Custom(u32),
+    /// A custom instruction completed, or raised the exception with the exception code.
+    CustomResult(Option<u32>),
+}
+
+/// The tags of the events in the encoded log.
+const TAG_MMIO: u8 = 0;
+const TAG_CSR: u8 = 1;
+const TAG_INTERRUPT: u8 = 2;
+const TAG_WAKE: u8 = 3;
+const TAG_HOST_NONE: u8 = 4;
+const TAG_HOST_SOME: u8 = 5;
+const TAG_CUSTOM: u8 = 6;
+const TAG_CUSTOM_OK: u8 = 7;
+const TAG_CUSTOM_EXCEPTION: u8 = 8;
+
+/// A log of the non-deterministic inputs of a run, in the order the guest consumed them.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Log {
+    events: Vec<Event>,
+}
+
+impl Log {
+    /// Create an empty log.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the events in the log.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Encode the log compactly: each event is a tag byte followed by its fields as LEB128
+    /// numbers.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for event in self.events.iter() {
+            match *event {
+                Event::Mmio(value) => {
+                    bytes.push(TAG_MMIO);
+                    write_number(&mut bytes, value as u64);
+                }
+                Event::Csr(value) => {
+                    bytes.push(TAG_CSR);
+                    write_number(&mut bytes, value as u64);
+                }
+                Event::Interrupt {
+                    hart,
+                    instret,
+                    cause,
+                } => {
+                    bytes.push(TAG_INTERRUPT);
+                    write_number(&mut bytes, hart as u64);
+                    write_number(&mut bytes, instret);
+                    write_number(&mut bytes, cause as u64);
+                }
+                Event::Wake { hart, instret } => {
+                    bytes.push(TAG_WAKE);
+                    write_number(&mut bytes, hart as u64);
+                    write_number(&mut bytes, instret);
+                }
+                Event::Host(None) => bytes.push(TAG_HOST_NONE),
+                Event::Host(Some(value)) => {
+                    bytes.push(TAG_HOST_SOME);
+                    write_number(&mut bytes, value);
+                }
+                Event::Custom(value) => {
+                    bytes.push(TAG_CUSTOM);
+                    write_number(&mut bytes, value as u64);
+                }
+                Event::CustomResult(None) => bytes.push(TAG_CUSTOM_OK),
+                Event::CustomResult(Some(code)) => {
+                    bytes.push(TAG_CUSTOM_EXCEPTION);
+                    write_number(&mut bytes, code as u64);
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Decode a log encoded by [`Log::encode`]. Returns `None` if `bytes` isn't a valid log.
+    pub fn decode(bytes: &[u8]) -> Option<Log> {
+        let mut events = Vec::new();
+        let mut bytes = bytes.iter().copied();
+        while let Some(tag) = bytes.next() {
+            let event = match tag {
+                TAG_MMIO => Event::Mmio(read_number(&mut bytes)?.try_into().ok()?),
+                TAG_CSR => Event::Csr(read_number(&mut bytes)?.try_into().ok()?),
+                TAG_INTERRUPT => Event::Interrupt {
+                    hart: read_number(&mut bytes)?.try_into().ok()?,
+                    instret: read_number(&mut bytes)?,
+                    cause: read_number(&mut bytes)?.try_into().ok()?,
+                },
+                TAG_WAKE => Event::Wake {
+                    hart: read_number(&mut bytes)?.try_into().ok()?,
+                    instret: read_number(&mut bytes)?,
+                },
+                TAG_HOST_NONE => Event::Host(None),
+                TAG_HOST_SOME => Event::Host(Some(read_number(&mut bytes)?)),
+                TAG_CUSTOM => Event::Custom(read_number(&mut bytes)?.try_into().ok()?),
+                TAG_CUSTOM_OK => Event::CustomResult(None),
+                TAG_CUSTOM_EXCEPTION => {
+                    Event::CustomResult(Some(read_number(&mut bytes)?.try_into().ok()?))
+                }
+                _ => return None,
+            };
+            events.push(event);
+        }
+        Some(Log { events })
+    }
+}
+
+/// Append `value` as an unsigned LEB128 number.
+fn write_number(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Read an unsigned LEB128 number.
+fn read_number(bytes: &mut impl Iterator<Item = u8>) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = bytes.next()?;
+        value |= ((byte & 0x7f) as u64).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Return true if the value of the CSR depends on when the harts ran rather than on the
+/// instructions they executed: the counters, which advance with the cycles and the time a hart
+/// waits in WFI, and the pending interrupts.
+pub fn is_volatile_csr(addr: CsrAddress) -> bool {
+    matches!(addr, 0xb00..=0xb1f | 0xb80..=0xb9f | 0xc00..=0xc1f | 0xc80..=0xc9f | MIP | SIP)
+}
+
+/// Whether the journal records or replays.
+#[derive(Debug)]
+enum Mode {
+    Record(Log),
+    Replay {
+        log: Log,
+        /// The index of the next event to replay.
+        position: usize,
+        diverged: bool,
+    },
+}
+
+/// The journal of a run being recorded or replayed, held by the bus.
+#[derive(Debug)]
+pub(crate) struct Journal {
+    mode: Mode,
+    /// The number of instructions each hart executed since the journal started.
+    instret: Vec<u64>,
+}
+
+impl Journal {
+    /// Create a journal that records a new log.
+    pub(crate) fn record() -> Self {
+        Self {
+            mode: Mode::Record(Log::new()),
+            instret: Vec::new(),
+        }
+    }
+
+    /// Create a journal that replays `log`.
+    pub(crate) fn replay(log: Log) -> Self {
+        Self {
+            mode: Mode::Replay {
+                log,
+                position: 0,
+                diverged: false,
+            },
+            instret: Vec::new(),
+        }
+    }
+
+    /// Return the recorded log, or the log being replayed.
+    pub(crate) fn into_log(self) -> Log {
+        match self.mode {
+            Mode::Record(log) | Mode::Replay { log, .. } => log,
+        }
+    }
+
+    /// Return true if the events come from the log: the journal replays, the log hasn't run out,
+    /// and the replay hasn't diverged.
+    pub(crate) fn is_replaying(&self) -> bool {
+        match &self.mode {
+            Mode::Record(_) => false,
+            Mode::Replay {
+                log,
+                position,
+                diverged,
+            } => !diverged && *position < log.events.len(),
+        }
+    }
+
+    /// Return true if the replay diverged from the log.
+    pub(crate) fn has_diverged(&self) -> bool {
+        matches!(self.mode, Mode::Replay { diverged: true, .. })
+    }
+
+    /// Return the number of instructions the hart executed since the journal started.
+    fn instret(&self, hart: u32) -> u64 {
+        self.instret.get(hart as usize).copied().unwrap_or(0)
+    }
+
+    /// Count an instruction the hart executed.
+    pub(crate) fn retire(&mut self, hart: u32) {
+        let hart = hart as usize;
+        if self.instret.len() <= hart {
+            self.instret.resize(hart + 1, 0);
+        }
+        self.instret[hart] += 1;
+    }
+
+    /// Add `event` to the log if the journal records.
+    fn record_event(&mut self, event: Event) {
+        if let Mode::Record(log) = &mut self.mode {
+            log.events.push(event);
+        }
+    }
+
+    /// Return the value of the next event if `value` accepts it, and move past it. If the next
+    /// event isn't accepted, the replay diverged when `required` is true.
+    fn next_event<T>(&mut self, required: bool, value: impl Fn(&Event) -> Option<T>) -> Option<T> {
+        if !self.is_replaying() {
+            return None;
+        }
+        let Mode::Replay {
+            log,
+            position,
+            diverged,
+        } = &mut self.mode
+        else {
+            return None;
+        };
+        match value(&log.events[*position]) {
+            Some(value) => {
+                *position += 1;
+                Some(value)
+            }
+            None => {
+                *diverged |= required;
+                None
+            }
+        }
+    }
+
+    /// Journal the value `live` a hart loaded from a device.
+    pub(crate) fn mmio(&mut self, live: u32) -> u32 {
+        self.input(live, Event::Mmio, |event| match *event {
+            Event::Mmio(value) => Some(value),
+            _ => None,
+        })
+    }
+
+    /// Journal the value `live` a hart read from a volatile CSR.
+    pub(crate) fn csr(&mut self, live: u32) -> u32 {
+        self.input(live, Event::Csr, |event| match *event {
+            Event::Csr(value) => Some(value),
+            _ => None,
+        })
+    }
+
+    /// Journal the value `live` a custom instruction writes to a register or to memory.
+    pub(crate) fn custom(&mut self, live: u32) -> u32 {
+        self.input(live, Event::Custom, |event| match *event {
+            Event::Custom(value) => Some(value),
+            _ => None,
+        })
+    }
+
+    /// Journal the exception code a custom instruction raised, or `None` if it completed. The
+    /// outcome can't be replaced, so a replay that raises another one diverges.
+    pub(crate) fn custom_result(&mut self, live: Option<u32>) {
+        if self.is_replaying() {
+            self.next_event(true, |event| match *event {
+                Event::CustomResult(code) if code == live => Some(()),
+                _ => None,
+            });
+            return;
+        }
+        self.record_event(Event::CustomResult(live));
+    }
+
+    /// Replace `live` with the next event when replaying, or record it.
+    fn input(
+        &mut self,
+        live: u32,
+        event: fn(u32) -> Event,
+        value: fn(&Event) -> Option<u32>,
+    ) -> u32 {
+        if let Some(value) = self.next_event(true, value) {
+            return value;
+        }
+        self.record_event(event(live));
+        live
+    }
+
+    /// Journal the value a request to the semihosting host returns.
+    fn host(&mut self, live: impl FnOnce() -> Option<u64>) -> Option<u64> {
+        if let Some(value) = self.next_event(true, |event| match *event {
+            Event::Host(value) => Some(value),
+            _ => None,
+        }) {
+            return value;
+        }
+        let value = live();
+        self.record_event(Event::Host(value));
+        value
+    }
+
+    /// Record that the hart takes `interrupt`.
+    pub(crate) fn record_interrupt(&mut self, hart: u32, interrupt: Interrupt) {
+        let instret = self.instret(hart);
+        self.record_event(Event::Interrupt {
+            hart,
+            instret,
+            cause: interrupt.exception_code(),
+        });
+    }
+
+    /// Return the interrupt the hart takes now in the log.
+    pub(crate) fn replay_interrupt(&mut self, hart: u32) -> Option<Interrupt> {
+        let instret = self.instret(hart);
+        let cause = self.next_event(false, |event| match *event {
+            Event::Interrupt {
+                hart: h,
+                instret: i,
+                cause,
+            } if h == hart && i == instret => Some(cause),
+            _ => None,
+        })?;
+        Interrupt::PRIORITY
+            .into_iter()
+            .find(|interrupt| interrupt.exception_code() == cause)
+    }
+
+    /// Record that the hart wakes up from WFI.
+    pub(crate) fn record_wake(&mut self, hart: u32) {
+        let instret = self.instret(hart);
+        self.record_event(Event::Wake { hart, instret });
+    }
+
+    /// Return true if the hart wakes up from WFI now in the log.
+    pub(crate) fn replay_wake(&mut self, hart: u32) -> bool {
+        let instret = self.instret(hart);
+        self.next_event(false, |event| match *event {
+            Event::Wake {
+                hart: h,
+                instret: i,
+            } if h == hart && i == instret => Some(()),
+            _ => None,
+        })
+        .is_some()
+    }
+}
+
+/// A semihosting host whose answers are journaled. The console output still goes to the host.
+pub(crate) struct JournaledHost<'a> {
+    pub(crate) host: &'a mut dyn Host,
+    pub(crate) journal: &'a mut Journal,
+}
+
+impl Host for JournaledHost<'_> {
+    fn write(&mut self, stream: Stream, data: &[u8]) {
+        self.host.write(stream, data);
+    }
+
+    fn read_char(&mut self) -> Option<u8> {
+        let host = &mut self.host;
+        self.journal
+            .host(|| host.read_char().map(|c| c as u64))
+            .map(|c| c as u8)
+    }
+
+    fn clock(&mut self) -> Option<u32> {
+        let host = &mut self.host;
+        self.journal
+            .host(|| host.clock().map(|clock| clock as u64))
+            .map(|clock| clock as u32)
+    }
+
+    fn elapsed(&mut self) -> Option<u64> {
+        let host = &mut self.host;
+        self.journal.host(|| host.elapsed())
+    }
+}
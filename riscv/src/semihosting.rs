@@ -0,0 +1,248 @@
+//! The semihosting module implements the RISC-V semihosting interface, which lets a guest program
+//! request services such as console I/O and exiting from the host that runs the emulator.
+//!
+//! A semihosting call is the sequence `slli x0, x0, 0x1f; ebreak; srai x0, x0, 7`. The operation
+//! number is passed in `a0`, the parameter (a value or a pointer to a parameter block) in `a1`, and
+//! the result is returned in `a0`. See the RISC-V Semihosting specification and the Arm
+//! semihosting specification it is based on.
+
+use alloc::vec::Vec;
+
+use crate::bus::Bus;
+use crate::cpu::{Cpu, BYTE, WORD};
+
+/// `slli x0, x0, 0x1f`, the entry NOP placed before the `ebreak`.
+const ENTRY_NOP: u32 = 0x01f01013;
+/// `srai x0, x0, 7`, the exit NOP placed after the `ebreak`.
+const EXIT_NOP: u32 = 0x40705013;
+
+/// Open a file or the console (`:tt`).
+pub const SYS_OPEN: u32 = 0x01;
+/// Close a file.
+pub const SYS_CLOSE: u32 = 0x02;
+/// Write a character to the debug channel.
+pub const SYS_WRITEC: u32 = 0x03;
+/// Write a null-terminated string to the debug channel.
+pub const SYS_WRITE0: u32 = 0x04;
+/// Write a buffer to a file.
+pub const SYS_WRITE: u32 = 0x05;
+/// Read a character from the debug channel.
+pub const SYS_READC: u32 = 0x07;
+/// Return the number of centiseconds since the execution started.
+pub const SYS_CLOCK: u32 = 0x10;
+/// Report an exception to the host. An application exit is reported this way.
+pub const SYS_EXIT: u32 = 0x18;
+/// Return the number of elapsed target ticks since the execution started.
+pub const SYS_ELAPSED: u32 = 0x30;
+/// Return the number of ticks per second of `SYS_ELAPSED`.
+pub const SYS_TICKFREQ: u32 = 0x31;
+
+/// The reason code for a normal application exit (`ADP_Stopped_ApplicationExit`).
+pub const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+
+/// The handles returned by opening `:tt`. Only the console can be opened.
+const STDIN: u32 = 1;
+const STDOUT: u32 = 2;
+const STDERR: u32 = 3;
+
+/// The console streams a guest can write to.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// The host side of semihosting, supplied by the embedder of the emulator.
+pub trait Host {
+    /// Write `data` to the console stream. `SYS_WRITEC` and `SYS_WRITE0` write to stdout.
+    fn write(&mut self, stream: Stream, data: &[u8]);
+
+    /// Read a character from the console, or `None` if no input is available.
+    fn read_char(&mut self) -> Option<u8>;
+
+    /// Return the number of centiseconds since the execution started, or `None` if the host has
+    /// no clock.
+    fn clock(&mut self) -> Option<u32> {
+        None
+    }
+
+    /// Return the number of elapsed ticks since the execution started, or `None` if the host
+    /// does not count ticks.
+    fn elapsed(&mut self) -> Option<u64> {
+        None
+    }
+
+    /// Return the number of ticks per second that [`Host::elapsed`] counts, or `None` if the
+    /// host does not count ticks.
+    fn tick_frequency(&mut self) -> Option<u32> {
+        None
+    }
+}
+
+/// Return true if the `ebreak` at the program counter is a semihosting call.
+pub fn is_semihosting_call(cpu: &Cpu, bus: &mut Bus) -> bool {
+    let pc = cpu.pc;
+    let entry = bus.read(pc.wrapping_sub(4), WORD);
+    let exit = bus.read(pc.wrapping_add(4), WORD);
+    matches!((entry, exit), (Ok(ENTRY_NOP), Ok(EXIT_NOP)))
+}
+
+/// Handle the semihosting call at the program counter and move the program counter past the
+/// `ebreak`. Returns the exit code if the guest exited.
+pub fn call(cpu: &mut Cpu, bus: &mut Bus, host: &mut dyn Host) -> Option<u32> {
+    let op = cpu.xregs.read(10);
+    let param = cpu.xregs.read(11);
+
+    let mut exit_code = None;
+    let result = match op {
+        SYS_OPEN => open(bus, param),
+        SYS_CLOSE => Some(0),
+        SYS_WRITEC => bus.read(param, BYTE).ok().map(|c| {
+            host.write(Stream::Stdout, &[c as u8]);
+            0
+        }),
+        SYS_WRITE0 => read_string(bus, param).map(|s| {
+            host.write(Stream::Stdout, &s);
+            0
+        }),
+        SYS_WRITE => write(bus, host, param),
+        SYS_READC => host.read_char().map(|c| c as u32),
+        SYS_CLOCK => host.clock(),
+        SYS_EXIT => {
+            // On RV32, the parameter is the reason code itself rather than a parameter block.
+            exit_code = Some(if param == ADP_STOPPED_APPLICATION_EXIT {
+                0
+            } else {
+                1
+            });
+            Some(0)
+        }
+        SYS_ELAPSED => host.elapsed().and_then(|ticks| {
+            bus.write(param, ticks as u32, WORD).ok()?;
+            bus.write(param.wrapping_add(4), (ticks >> 32) as u32, WORD)
+                .ok()?;
+            Some(0)
+        }),
+        SYS_TICKFREQ => host.tick_frequency(),
+        _ => None,
+    };
+    // Failed and unsupported operations return -1.
+    cpu.xregs.write(10, result.unwrap_or(u32::MAX));
+
+    // Continue with the exit NOP.
+    cpu.pc = cpu.pc.wrapping_add(4);
+    exit_code
+}
+
+/// Open the console. The parameter block is `[name, mode, name length]`, and the mode selects
+/// stdin (0-3), stdout (4-7), or stderr (8-11).
+fn open(bus: &mut Bus, block: u32) -> Option<u32> {
+    let name = bus.read(block, WORD).ok()?;
+    let mode = bus.read(block.wrapping_add(4), WORD).ok()?;
+    if read_string(bus, name)? != b":tt" {
+        return None;
+    }
+    match mode {
+        0..=3 => Some(STDIN),
+        4..=7 => Some(STDOUT),
+        8..=11 => Some(STDERR),
+        _ => None,
+    }
+}
+
+/// Write a buffer to the console. The parameter block is `[handle, buffer, length]`, and the
+/// result is the number of bytes that were not written.
+fn write(bus: &mut Bus, host: &mut dyn Host, block: u32) -> Option<u32> {
+    let handle = bus.read(block, WORD).ok()?;
+    let buf = bus.read(block.wrapping_add(4), WORD).ok()?;
+    let len = bus.read(block.wrapping_add(8), WORD).ok()?;
+    let stream = match handle {
+        STDOUT => Stream::Stdout,
+        STDERR => Stream::Stderr,
+        _ => return None,
+    };
+
+    // The length comes from the guest, so the buffer grows only as its bytes are read, and a
+    // buffer that wraps around the address space continues at address 0.
+    let mut data = Vec::new();
+    for addr in (0..len).map(|i| buf.wrapping_add(i)) {
+        data.push(bus.read(addr, BYTE).ok()? as u8);
+    }
+    host.write(stream, &data);
+    Some(0)
+}
+
+/// Read a null-terminated string from the memory.
+fn read_string(bus: &mut Bus, mut addr: u32) -> Option<Vec<u8>> {
+    let mut s = Vec::new();
+    loop {
+        let c = bus.read(addr, BYTE).ok()? as u8;
+        if c == 0 {
+            return Some(s);
+        }
+        s.push(c);
+        addr = addr.wrapping_add(1);
+    }
+}
+
+/// A host that uses the standard streams of the process running the emulator.
+#[cfg(feature = "std")]
+pub struct StdHost {
+    start: std::time::Instant,
+}
+
+#[cfg(feature = "std")]
+impl StdHost {
+    /// Create a new host. `SYS_CLOCK` and `SYS_ELAPSED` count from this point.
+    pub fn new() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for StdHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Host for StdHost {
+    fn write(&mut self, stream: Stream, data: &[u8]) {
+        use std::io::Write;
+
+        // The guest can't do anything about a failed write to the console.
+        let _ = match stream {
+            Stream::Stdout => {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(data).and_then(|_| stdout.flush())
+            }
+            Stream::Stderr => std::io::stderr().write_all(data),
+        };
+    }
+
+    fn read_char(&mut self) -> Option<u8> {
+        use std::io::Read;
+
+        let mut c = [0];
+        match std::io::stdin().read(&mut c) {
+            Ok(1) => Some(c[0]),
+            _ => None,
+        }
+    }
+
+    fn clock(&mut self) -> Option<u32> {
+        Some((self.start.elapsed().as_millis() / 10) as u32)
+    }
+
+    /// The ticks are microseconds.
+    fn elapsed(&mut self) -> Option<u64> {
+        Some(self.start.elapsed().as_micros() as u64)
+    }
+
+    fn tick_frequency(&mut self) -> Option<u32> {
+        Some(1_000_000)
+    }
+}
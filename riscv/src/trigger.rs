@@ -0,0 +1,234 @@
+//! The trigger module implements the trigger module (Sdtrig) of the RISC-V Debug Specification.
+//! Triggers are hardware breakpoints and watchpoints: `mcontrol6` triggers that match the address
+//! of an instruction fetch, a load, or a store, and either raise a breakpoint exception or enter
+//! debug mode.
+
+use core::ops::RangeInclusive;
+
+use crate::{
+    cpu::{AccessType, Mode},
+    csr::*,
+};
+
+/// The number of triggers.
+pub const TRIGGER_COUNT: usize = 4;
+
+/// A trigger that matches nothing.
+pub const TRIGGER_TYPE_DISABLED: u32 = 15;
+/// An address or data match trigger.
+pub const TRIGGER_TYPE_MCONTROL6: u32 = 6;
+
+// TDATA1 fields.
+/// The type of the trigger.
+pub const TDATA1_TYPE: CsrFieldRange = 28..=31;
+/// Only debug mode can write the trigger.
+pub const TDATA1_DMODE: CsrFieldRange = 27..=27;
+
+// TDATA1 fields of `mcontrol6` triggers.
+/// Set when the trigger fires.
+pub const MCONTROL6_HIT0: CsrFieldRange = 22..=22;
+/// What happens when the trigger fires.
+pub const MCONTROL6_ACTION: CsrFieldRange = 12..=15;
+/// The trigger only fires together with the next trigger.
+pub const MCONTROL6_CHAIN: CsrFieldRange = 11..=11;
+/// How the address is compared with TDATA2.
+pub const MCONTROL6_MATCH: CsrFieldRange = 7..=10;
+/// The trigger matches in M-mode.
+pub const MCONTROL6_M: CsrFieldRange = 6..=6;
+/// The trigger matches in U-mode.
+pub const MCONTROL6_U: CsrFieldRange = 3..=3;
+/// The trigger matches instruction fetches.
+pub const MCONTROL6_EXECUTE: CsrFieldRange = 2..=2;
+/// The trigger matches stores.
+pub const MCONTROL6_STORE: CsrFieldRange = 1..=1;
+/// The trigger matches loads.
+pub const MCONTROL6_LOAD: CsrFieldRange = 0..=0;
+
+/// Raise a breakpoint exception.
+pub const ACTION_BREAKPOINT: u32 = 0;
+/// Enter debug mode. Only triggers with `dmode` set can have this action.
+pub const ACTION_DEBUG_MODE: u32 = 1;
+
+/// The address equals TDATA2.
+pub const MATCH_EQUAL: u32 = 0;
+/// The address is in the naturally aligned power-of-two range encoded in TDATA2.
+pub const MATCH_NAPOT: u32 = 1;
+/// The address is greater than or equal to TDATA2.
+pub const MATCH_GE: u32 = 2;
+/// The address is less than TDATA2.
+pub const MATCH_LT: u32 = 3;
+
+/// What a fired trigger does.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum TriggerAction {
+    Breakpoint,
+    EnterDebugMode,
+}
+
+/// The trigger module: the triggers and the trigger CSRs to select and program them.
+#[derive(Clone)]
+pub struct Triggers {
+    /// The index of the trigger that TDATA1-3 access.
+    tselect: u32,
+    tdata1: [u32; TRIGGER_COUNT],
+    tdata2: [u32; TRIGGER_COUNT],
+}
+
+impl Default for Triggers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Triggers {
+    /// Create a trigger module with all the triggers disabled.
+    pub fn new() -> Self {
+        Self {
+            tselect: 0,
+            tdata1: [TRIGGER_TYPE_DISABLED << TDATA1_TYPE.start(); TRIGGER_COUNT],
+            tdata2: [0; TRIGGER_COUNT],
+        }
+    }
+
+    /// Read a trigger CSR.
+    pub fn read(&self, addr: CsrAddress) -> u32 {
+        let index = self.tselect as usize;
+        match addr {
+            TSELECT => self.tselect,
+            TDATA1 => self.tdata1[index],
+            TDATA2 => self.tdata2[index],
+            // Version 1 (Sdtrig 1.0) supporting the mcontrol6 and disabled types.
+            TINFO => (1 << 24) | (1 << TRIGGER_TYPE_DISABLED) | (1 << TRIGGER_TYPE_MCONTROL6),
+            _ => 0,
+        }
+    }
+
+    /// Write a trigger CSR. TDATA3 and TINFO are read-only zero and read-only respectively.
+    pub fn write(&mut self, addr: CsrAddress, val: u32) {
+        let index = self.tselect as usize;
+        match addr {
+            // "Writes of values greater than or equal to the number of supported triggers may
+            // result in a different value", so such writes keep the current value.
+            TSELECT if (val as usize) < TRIGGER_COUNT => self.tselect = val,
+            TDATA1 => self.tdata1[index] = legalize_tdata1(val),
+            TDATA2 => self.tdata2[index] = val,
+            _ => {}
+        }
+    }
+
+    /// Return true if the selected trigger can only be written from debug mode.
+    pub fn is_dmode_selected(&self) -> bool {
+        field(self.tdata1[self.tselect as usize], &TDATA1_DMODE) == 1
+    }
+
+    /// Check the triggers against an access of `len` bytes at `addr` in the privilege mode
+    /// `mode`, and return the action of a fired trigger. The triggers in a chain fire together, with
+    /// the action of the last trigger in the chain.
+    pub fn check(
+        &mut self,
+        access: AccessType,
+        addr: u32,
+        len: u32,
+        mode: Mode,
+    ) -> Option<TriggerAction> {
+        let mut first = 0;
+        while first < TRIGGER_COUNT {
+            let mut last = first;
+            while field(self.tdata1[last], &MCONTROL6_CHAIN) == 1 && last + 1 < TRIGGER_COUNT {
+                last += 1;
+            }
+
+            if (first..=last).all(|i| self.matches(i, &access, addr, len, mode)) {
+                for i in first..=last {
+                    self.tdata1[i] |= 1 << MCONTROL6_HIT0.start();
+                }
+                return Some(match field(self.tdata1[last], &MCONTROL6_ACTION) {
+                    ACTION_DEBUG_MODE => TriggerAction::EnterDebugMode,
+                    _ => TriggerAction::Breakpoint,
+                });
+            }
+            first = last + 1;
+        }
+        None
+    }
+
+    /// Return true if the trigger `index` matches an access of `len` bytes at `addr`.
+    fn matches(&self, index: usize, access: &AccessType, addr: u32, len: u32, mode: Mode) -> bool {
+        let tdata1 = self.tdata1[index];
+        if field(tdata1, &TDATA1_TYPE) != TRIGGER_TYPE_MCONTROL6 {
+            return false;
+        }
+        let enabled = match mode {
+            Mode::Machine => &MCONTROL6_M,
+            Mode::User => &MCONTROL6_U,
+            // Triggers never match in debug mode.
+            Mode::Debug => return false,
+        };
+        let kind = match access {
+            AccessType::Instruction => &MCONTROL6_EXECUTE,
+            AccessType::Load => &MCONTROL6_LOAD,
+            AccessType::Store => &MCONTROL6_STORE,
+        };
+        if field(tdata1, enabled) == 0 || field(tdata1, kind) == 0 {
+            return false;
+        }
+
+        // The trigger matches if any of the accessed bytes matches.
+        let tdata2 = self.tdata2[index];
+        (0..len).any(|offset| {
+            let addr = addr.wrapping_add(offset);
+            match field(tdata1, &MCONTROL6_MATCH) {
+                MATCH_EQUAL => addr == tdata2,
+                MATCH_NAPOT => {
+                    // The number of trailing ones in TDATA2 encodes the size of the range.
+                    let mask = u32::MAX
+                        .checked_shl(tdata2.trailing_ones() + 1)
+                        .unwrap_or(0);
+                    addr & mask == tdata2 & mask
+                }
+                MATCH_GE => addr >= tdata2,
+                MATCH_LT => addr < tdata2,
+                _ => false,
+            }
+        })
+    }
+}
+
+/// Return the value of a field in a register value.
+fn field(val: u32, range: &RangeInclusive<usize>) -> u32 {
+    let width = range.end() - range.start() + 1;
+    (val >> range.start()) & (u32::MAX >> (32 - width))
+}
+
+/// Return the value TDATA1 holds after writing `val`. Unsupported types disable the trigger, and
+/// the fields of `mcontrol6` this implementation doesn't support are hardwired to zero.
+fn legalize_tdata1(val: u32) -> u32 {
+    let dmode = val & (1 << TDATA1_DMODE.start());
+    if field(val, &TDATA1_TYPE) != TRIGGER_TYPE_MCONTROL6 {
+        return (TRIGGER_TYPE_DISABLED << TDATA1_TYPE.start()) | dmode;
+    }
+
+    let mut tdata1 = (TRIGGER_TYPE_MCONTROL6 << TDATA1_TYPE.start()) | dmode;
+    for range in [
+        &MCONTROL6_HIT0,
+        &MCONTROL6_CHAIN,
+        &MCONTROL6_M,
+        &MCONTROL6_U,
+        &MCONTROL6_EXECUTE,
+        &MCONTROL6_STORE,
+        &MCONTROL6_LOAD,
+    ] {
+        tdata1 |= val & (1 << range.start());
+    }
+    // "action=1 is only legal when dmode=1".
+    let action = match field(val, &MCONTROL6_ACTION) {
+        ACTION_DEBUG_MODE if dmode != 0 => ACTION_DEBUG_MODE,
+        _ => ACTION_BREAKPOINT,
+    };
+    tdata1 |= action << MCONTROL6_ACTION.start();
+    let match_type = match field(val, &MCONTROL6_MATCH) {
+        m @ (MATCH_EQUAL | MATCH_NAPOT | MATCH_GE | MATCH_LT) => m,
+        _ => MATCH_EQUAL,
+    };
+    tdata1 | (match_type << MCONTROL6_MATCH.start())
+}
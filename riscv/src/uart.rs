@@ -0,0 +1,70 @@
+//! The uart module contains a minimal transmit-only UART with the 16550 register layout, as on the
+//! QEMU virt machine, so that guest programs can print without semihosting.
+//!
+//! The guest writes a byte to `UART_THR` to transmit it. The transmitter is always ready, and the
+//! embedder takes the transmitted bytes with [`Uart::take_output`].
+
+use alloc::vec::Vec;
+
+use crate::cpu::BYTE;
+use crate::exception::Exception;
+
+/// The address which the UART starts.
+pub const UART_BASE: u32 = 0x1000_0000;
+/// The size of the UART.
+pub const UART_SIZE: u32 = 0x100;
+
+/// The receive buffer register. Always reads as zero because the UART doesn't receive.
+pub const UART_RHR: u32 = UART_BASE;
+/// The transmit holding register.
+pub const UART_THR: u32 = UART_BASE;
+/// The line status register. Read-only.
+pub const UART_LSR: u32 = UART_BASE + 5;
+
+/// The transmit holding register is empty.
+pub const UART_LSR_THRE: u32 = 1 << 5;
+/// The transmitter is idle.
+pub const UART_LSR_TEMT: u32 = 1 << 6;
+
+/// The UART device.
+#[derive(Debug, Default, Clone)]
+pub struct Uart {
+    /// The bytes the guest transmitted that the embedder hasn't taken yet.
+    output: Vec<u8>,
+}
+
+impl Uart {
+    /// Create a new UART.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take the bytes the guest transmitted since the last call.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.output)
+    }
+
+    /// Load a byte from a UART register. Only byte accesses are supported, and registers other
+    /// than the line status register read as zero.
+    pub fn read(&self, addr: u32, size: u8) -> Result<u32, Exception> {
+        if size != BYTE {
+            return Err(Exception::LoadAccessFault(addr));
+        }
+        match addr {
+            UART_LSR => Ok(UART_LSR_THRE | UART_LSR_TEMT),
+            _ => Ok(0),
+        }
+    }
+
+    /// Store a byte to a UART register. Only byte accesses are supported, and writes to registers
+    /// other than the transmit holding register are ignored.
+    pub fn write(&mut self, addr: u32, value: u32, size: u8) -> Result<(), Exception> {
+        if size != BYTE {
+            return Err(Exception::StoreAMOAccessFault(addr));
+        }
+        if addr == UART_THR {
+            self.output.push(value as u8);
+        }
+        Ok(())
+    }
+}
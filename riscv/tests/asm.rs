@@ -0,0 +1,332 @@
+use riscv::asm::{self, Reg};
+use riscv::bus::DRAM_BASE;
+use riscv::csr::*;
+use riscv::emulator::Emulator;
+
+/// An instruction of each kind, with its encoding by llvm-mc and its disassembly.
+const INSTRUCTIONS: &[(u32, &str)] = &[
+    (0x123452b7, "lui t0, 0x12345"),
+    (0xfffff517, "auipc a0, 0xfffff"),
+    (0x801ff0ef, "jal ra, -2048"),
+    (0x00008067, "jalr zero, 0(ra)"),
+    (0xfff28303, "lb t1, -1(t0)"),
+    (0x00229303, "lh t1, 2(t0)"),
+    (0x7ff12403, "lw s0, 2047(sp)"),
+    (0x80064583, "lbu a1, -2048(a2)"),
+    (0x00665583, "lhu a1, 6(a2)"),
+    (0x80628063, "beq t0, t1, -4096"),
+    (0x7e051fe3, "bne a0, zero, 4094"),
+    (0x0124c463, "blt s1, s2, 8"),
+    (0xff49dce3, "bge s3, s4, -8"),
+    (0x01ff6863, "bltu t5, t6, 16"),
+    (0x0041f663, "bgeu gp, tp, 12"),
+    (0xfe628fa3, "sb t1, -1(t0)"),
+    (0x00629123, "sh t1, 2(t0)"),
+    (0x7ff12fa3, "sw t6, 2047(sp)"),
+    (0x00500f93, "addi t6, zero, 5"),
+    (0xffb5a513, "slti a0, a1, -5"),
+    (0x0055b513, "sltiu a0, a1, 5"),
+    (0xfff5c513, "xori a0, a1, -1"),
+    (0x0ff5e513, "ori a0, a1, 255"),
+    (0x00f5f513, "andi a0, a1, 15"),
+    (0x01f59513, "slli a0, a1, 31"),
+    (0x0015d513, "srli a0, a1, 1"),
+    (0x4045d513, "srai a0, a1, 4"),
+    (0x00c58533, "add a0, a1, a2"),
+    (0x40c58533, "sub a0, a1, a2"),
+    (0x00c59533, "sll a0, a1, a2"),
+    (0x00c5a533, "slt a0, a1, a2"),
+    (0x00c5b533, "sltu a0, a1, a2"),
+    (0x00c5c533, "xor a0, a1, a2"),
+    (0x00c5d533, "srl a0, a1, a2"),
+    (0x40c5d533, "sra a0, a1, a2"),
+    (0x00c5e533, "or a0, a1, a2"),
+    (0x00c5f533, "and a0, a1, a2"),
+    (0x02c58533, "mul a0, a1, a2"),
+    (0x02c59533, "mulh a0, a1, a2"),
+    (0x02c5a533, "mulhsu a0, a1, a2"),
+    (0x02c5b533, "mulhu a0, a1, a2"),
+    (0x02c5c533, "div a0, a1, a2"),
+    (0x02c5d533, "divu a0, a1, a2"),
+    (0x02c5e533, "rem a0, a1, a2"),
+    (0x02c5f533, "remu a0, a1, a2"),
+    (0x0ff0000f, "fence iorw, iorw"),
+    (0x0210000f, "fence r, w"),
+    (0x0000100f, "fence.i"),
+    (0x00000073, "ecall"),
+    (0x00100073, "ebreak"),
+    (0x00200073, "uret"),
+    (0x30200073, "mret"),
+    (0x7b200073, "dret"),
+    (0x10500073, "wfi"),
+    (0x12b50073, "sfence.vma a0, a1"),
+    (0x340312f3, "csrrw t0, mscratch, t1"),
+    (0x300022f3, "csrrs t0, mstatus, zero"),
+    (0x7c0332f3, "csrrc t0, 0x7c0, t1"),
+    (0x305fd073, "csrrwi zero, mtvec, 31"),
+    (0x304462f3, "csrrsi t0, mie, 8"),
+    (0xc000f2f3, "csrrci t0, cycle, 1"),
+    (0x100522af, "lr.w t0, (a0)"),
+    (0x186522af, "sc.w t0, t1, (a0)"),
+    (0x086522af, "amoswap.w t0, t1, (a0)"),
+    (0x006522af, "amoadd.w t0, t1, (a0)"),
+    (0x206522af, "amoxor.w t0, t1, (a0)"),
+    (0x606522af, "amoand.w t0, t1, (a0)"),
+    (0x406522af, "amoor.w t0, t1, (a0)"),
+    (0x806522af, "amomin.w t0, t1, (a0)"),
+    (0xa06522af, "amomax.w t0, t1, (a0)"),
+    (0xc06522af, "amominu.w t0, t1, (a0)"),
+    (0xe06522af, "amomaxu.w t0, t1, (a0)"),
+    (0x0c6522af, "amoswap.w.aq t0, t1, (a0)"),
+    (0x160522af, "lr.w.aqrl t0, (a0)"),
+    (0x1a6522af, "sc.w.rl t0, t1, (a0)"),
+    (0x026522af, "amoadd.w.rl t0, t1, (a0)"),
+];
+
+/// Return the little-endian bytes of `words`.
+fn bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+#[test]
+fn encoders() {
+    assert_eq!(0x00500f93, asm::addi(Reg::T6, Reg::Zero, 5));
+    assert_eq!(0x123452b7, asm::lui(Reg::T0, 0x12345));
+    assert_eq!(0x801ff0ef, asm::jal(Reg::Ra, -2048));
+    assert_eq!(0x80064583, asm::lbu(Reg::A1, Reg::A2, -2048));
+    assert_eq!(0xfe628fa3, asm::sb(Reg::T1, Reg::T0, -1));
+    assert_eq!(0x80628063, asm::beq(Reg::T0, Reg::T1, -4096));
+    assert_eq!(0x4045d513, asm::srai(Reg::A0, Reg::A1, 4));
+    assert_eq!(0x02c5c533, asm::div(Reg::A0, Reg::A1, Reg::A2));
+    assert_eq!(0x0210000f, asm::fence(0b0010, 0b0001));
+    assert_eq!(0x0000100f, asm::fence_i());
+    assert_eq!(0x12b50073, asm::sfence_vma(Reg::A0, Reg::A1));
+    assert_eq!(0x340312f3, asm::csrrw(Reg::T0, 0x340, Reg::T1));
+    assert_eq!(0x305fd073, asm::csrrwi(Reg::Zero, MTVEC, 31));
+    assert_eq!(0x100522af, asm::lr_w(Reg::T0, Reg::A0));
+    assert_eq!(0x186522af, asm::sc_w(Reg::T0, Reg::T1, Reg::A0));
+    assert_eq!(0xe06522af, asm::amomaxu_w(Reg::T0, Reg::T1, Reg::A0));
+    assert_eq!(0x30200073, asm::mret());
+}
+
+#[test]
+#[should_panic(expected = "addi: 2048 is out of range -2048..=2047")]
+fn encoder_out_of_range() {
+    asm::addi(Reg::A0, Reg::A0, 2048);
+}
+
+#[test]
+#[should_panic(expected = "jal: 3 is not a multiple of 2")]
+fn encoder_misaligned_offset() {
+    asm::jal(Reg::Zero, 3);
+}
+
+#[test]
+fn registers() {
+    assert_eq!(Some(Reg::T6), Reg::parse("x31"));
+    assert_eq!(Some(Reg::T6), Reg::parse("t6"));
+    assert_eq!(Some(Reg::S0), Reg::parse("fp"));
+    assert_eq!(None, Reg::parse("x32"));
+    assert_eq!(None, Reg::parse("x+1"));
+    assert_eq!(None, Reg::parse("t7"));
+    for index in 0..32 {
+        let reg = Reg::from_index(index).unwrap();
+        assert_eq!(index, reg.index());
+        assert_eq!(Some(reg), Reg::parse(reg.name()));
+        assert_eq!(Some(reg), Reg::parse(&format!("x{}", index)));
+    }
+}
+
+#[test]
+fn round_trip() {
+    for (word, text) in INSTRUCTIONS {
+        assert_eq!(
+            Some(text.to_string()),
+            asm::disassemble(*word),
+            "{:#010x}",
+            word
+        );
+        assert_eq!(Ok(bytes(&[*word])), asm::assemble(text), "{}", text);
+    }
+
+    let source: Vec<&str> = INSTRUCTIONS.iter().map(|(_, text)| *text).collect();
+    let words: Vec<u32> = INSTRUCTIONS.iter().map(|(word, _)| *word).collect();
+    assert_eq!(Ok(bytes(&words)), asm::assemble(&source.join("\n")));
+
+    // Unknown opcodes, and bits the operands can't express.
+    assert_eq!(None, asm::disassemble(0x0000007f));
+    assert_eq!(None, asm::disassemble(0x00003003));
+    assert_eq!(None, asm::disassemble(0x101522af)); // lr.w with rs2
+    assert_eq!(None, asm::disassemble(0x8ff0000f)); // fence.tso
+}
+
+#[test]
+fn pseudo_instructions() {
+    let source = "
+        nop
+        li a0, -5
+        li a1, 0x12345678
+        li a2, 0x7ffff800
+        mv a3, a0
+        not a4, a0
+        neg a5, a0
+        ret
+        jr t0
+        csrr t1, mscratch
+        csrw mtvec, t2
+        call 0x12345800
+        tail -4096
+    ";
+    let expected = [
+        asm::addi(Reg::Zero, Reg::Zero, 0),
+        asm::addi(Reg::A0, Reg::Zero, -5),
+        asm::lui(Reg::A1, 0x12345),
+        asm::addi(Reg::A1, Reg::A1, 0x678),
+        // The lower 12 bits are sign-extended, so the upper 20 bits are rounded up.
+        asm::lui(Reg::A2, 0x80000),
+        asm::addi(Reg::A2, Reg::A2, -2048),
+        asm::addi(Reg::A3, Reg::A0, 0),
+        asm::xori(Reg::A4, Reg::A0, -1),
+        asm::sub(Reg::A5, Reg::Zero, Reg::A0),
+        asm::jalr(Reg::Zero, Reg::Ra, 0),
+        asm::jalr(Reg::Zero, Reg::T0, 0),
+        asm::csrrs(Reg::T1, 0x340, Reg::Zero),
+        asm::csrrw(Reg::Zero, MTVEC, Reg::T2),
+        asm::auipc(Reg::Ra, 0x12346),
+        asm::jalr(Reg::Ra, Reg::Ra, -2048),
+        asm::auipc(Reg::T1, 0xfffff),
+        asm::jalr(Reg::Zero, Reg::T1, 0),
+    ];
+    assert_eq!(Ok(bytes(&expected)), asm::assemble(source));
+}
+
+#[test]
+fn pc_relative_pseudo_instructions() {
+    let source = "
+                la a0, value
+                lw a1, 0(a0)
+                call double
+                tail end
+        double: slli a1, a1, 1
+                ret
+        end:    j end
+        value:  .word 42
+    ";
+    let program = asm::assemble(source).unwrap();
+    // The offsets are relative to the auipc.
+    assert_eq!(
+        bytes(&[
+            asm::auipc(Reg::A0, 0),
+            asm::addi(Reg::A0, Reg::A0, 40),
+            asm::lw(Reg::A1, Reg::A0, 0),
+            asm::auipc(Reg::Ra, 0),
+            asm::jalr(Reg::Ra, Reg::Ra, 16),
+            asm::auipc(Reg::T1, 0),
+            asm::jalr(Reg::Zero, Reg::T1, 16),
+            asm::slli(Reg::A1, Reg::A1, 1),
+            asm::jalr(Reg::Zero, Reg::Ra, 0),
+            asm::jal(Reg::Zero, 0),
+            42,
+        ]),
+        program
+    );
+
+    let mut emu = Emulator::new();
+    emu.initialize_dram(program);
+    emu.initialize_pc(DRAM_BASE);
+    for _ in 0..12 {
+        emu.step().unwrap();
+    }
+    assert_eq!(84, emu.harts[0].xregs.read(Reg::A1.index()));
+    assert_eq!(DRAM_BASE + 36, emu.harts[0].pc);
+}
+
+#[test]
+fn labels() {
+    let source = "
+        # Sum 1 to 10, and call a subroutine that doubles the sum.
+                li a0, 0
+                li t0, 10
+        loop:   add a0, a0, t0
+                addi t0, t0, -1
+                bnez t0, loop
+                jal double
+        end:    j end
+        double: slli a0, a0, 1
+                ret
+                .word 0x0000007f, 0x00003003 0
+    ";
+    let program = asm::assemble(source).unwrap();
+    assert_eq!(
+        bytes(&[
+            asm::addi(Reg::A0, Reg::Zero, 0),
+            asm::addi(Reg::T0, Reg::Zero, 10),
+            asm::add(Reg::A0, Reg::A0, Reg::T0),
+            asm::addi(Reg::T0, Reg::T0, -1),
+            asm::bne(Reg::T0, Reg::Zero, -8),
+            asm::jal(Reg::Ra, 8),
+            asm::jal(Reg::Zero, 0),
+            asm::slli(Reg::A0, Reg::A0, 1),
+            asm::jalr(Reg::Zero, Reg::Ra, 0),
+            0x0000007f,
+            0x00003003,
+            0,
+        ]),
+        program
+    );
+
+    let mut emu = Emulator::new();
+    emu.initialize_dram(program);
+    emu.initialize_pc(DRAM_BASE);
+    for _ in 0..50 {
+        emu.step().unwrap();
+    }
+    assert_eq!(110, emu.harts[0].xregs.read(Reg::A0.index()));
+    assert_eq!(DRAM_BASE + 24, emu.harts[0].pc);
+}
+
+#[test]
+fn errors() {
+    let error = |source: &str| asm::assemble(source).unwrap_err().to_string();
+
+    assert_eq!(
+        "line 2: unknown instruction `fadd.s`",
+        error("nop\nfadd.s f0, f1, f2")
+    );
+    assert_eq!(
+        "line 1: unknown instruction `addi.aq`",
+        error("addi.aq a0, a1, 1")
+    );
+    assert_eq!(
+        "line 1: invalid operands for `add`: `a0, a1`",
+        error("add a0, a1")
+    );
+    assert_eq!(
+        "line 1: invalid operands for `li`: `a0, 1, 2`",
+        error("li a0, 1, 2")
+    );
+    assert_eq!("line 1: invalid register `x32`", error("add a0, a1, x32"));
+    assert_eq!(
+        "line 1: invalid CSR `0x1000`",
+        error("csrrw a0, 0x1000, a1")
+    );
+    assert_eq!("line 1: invalid number `1O`", error("addi a0, a1, 1O"));
+    assert_eq!(
+        "line 1: 2048 is out of range -2048..=2047",
+        error("lw a0, 2048(a1)")
+    );
+    assert_eq!("line 1: unknown label `nowhere`", error("beqz a0, nowhere"));
+    assert_eq!(
+        "line 2: duplicate label `here`",
+        error("here: nop\nhere: nop")
+    );
+    assert_eq!("line 1: invalid label `a b`", error("a b: nop"));
+    assert_eq!(
+        "line 1: expected `offset(register)`, found `a1`",
+        error("lw a0, a1")
+    );
+    assert_eq!(
+        "line 1: expected `(register)`, found `4(a1)`",
+        error("lr.w a0, 4(a1)")
+    );
+}
@@ -0,0 +1,326 @@
+//! Runs the riscv-tests and riscv-arch-test style programs on the emulator.
+//!
+//! See `tests/compliance/README.md` for where the binaries come from and how to rebuild them.
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use elf::abi::PT_LOAD;
+use elf::endian::LittleEndian;
+use elf::ElfBytes;
+use riscv::bus::DRAM_BASE;
+use riscv::config::Extension;
+use riscv::cpu::WORD;
+use riscv::csr::MISA;
+use riscv::emulator::Emulator;
+
+/// The environment variable that names the file to append the coverage of the tests to.
+const COVERAGE_VAR: &str = "RISCV_COVERAGE";
+
+/// The maximum number of instructions a test program may execute before it is considered hung.
+const MAX_INSTRUCTIONS: usize = 1_000_000;
+
+/// A test program loaded into a fresh emulator.
+struct Program {
+    emu: Emulator,
+    symbols: HashMap<String, u32>,
+}
+
+impl Program {
+    /// Load the ELF at `path` into the DRAM and point the program counter at its entry point.
+    fn load(path: &Path) -> Program {
+        let data = fs::read(path).unwrap_or_else(|e| panic!("failed to read {:?}: {}", path, e));
+        let file = ElfBytes::<LittleEndian>::minimal_parse(&data).expect("invalid ELF file");
+
+        let mut image = Vec::new();
+        for segment in file.segments().expect("no program headers") {
+            if segment.p_type != PT_LOAD {
+                continue;
+            }
+            let start = (segment.p_paddr as u32 - DRAM_BASE) as usize;
+            let end = start + segment.p_memsz as usize;
+            if image.len() < end {
+                image.resize(end, 0);
+            }
+            let bytes = file.segment_data(&segment).expect("invalid segment");
+            image[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+
+        let mut symbols = HashMap::new();
+        let (symtab, strtab) = file
+            .symbol_table()
+            .expect("invalid symbol table")
+            .expect("no symbol table");
+        for symbol in symtab.iter() {
+            let name = strtab
+                .get(symbol.st_name as usize)
+                .expect("invalid symbol name");
+            symbols.insert(name.to_string(), symbol.st_value as u32);
+        }
+
+        let mut emu = Emulator::new();
+        emu.initialize_dram(image);
+        emu.initialize_pc(file.ehdr.e_entry as u32);
+        if std::env::var_os(COVERAGE_VAR).is_some() {
+            emu.enable_coverage();
+        }
+
+        Program { emu, symbols }
+    }
+
+    /// Return the address of the symbol `name`.
+    fn symbol(&self, name: &str) -> u32 {
+        *self
+            .symbols
+            .get(name)
+            .unwrap_or_else(|| panic!("missing symbol `{}`", name))
+    }
+
+    /// Run until the program writes a non-zero value to `tohost`, and return that value.
+    fn run(&mut self) -> u32 {
+        let tohost = self.symbol("tohost");
+        for _ in 0..MAX_INSTRUCTIONS {
+            if let Err(exception) = self.emu.step() {
+                panic!(
+                    "fatal exception {:?} at pc {:#x}",
+                    exception, self.emu.harts[0].pc
+                );
+            }
+
+            let value = self.emu.bus.read(tohost, WORD).unwrap();
+            if value != 0 {
+                self.save_coverage();
+                return value;
+            }
+        }
+        panic!("no result after {} instructions", MAX_INSTRUCTIONS);
+    }
+
+    /// Append the coverage of the program to the file the tests record their coverage to.
+    fn save_coverage(&self) {
+        let (Some(path), Some(coverage)) = (std::env::var_os(COVERAGE_VAR), self.emu.coverage())
+        else {
+            return;
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .unwrap_or_else(|e| panic!("failed to open {:?}: {}", path, e));
+        file.write_all(coverage.encode().as_bytes())
+            .unwrap_or_else(|e| panic!("failed to write {:?}: {}", path, e));
+    }
+}
+
+fn compliance_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/compliance")
+}
+
+/// Run a riscv-tests program, which reports 1 on success and `(testnum << 1) | 1` on failure.
+fn run_riscv_test(name: &str) {
+    let path = compliance_dir().join("riscv-tests/bin").join(name);
+    let mut program = Program::load(&path);
+
+    let result = program.run();
+    assert_eq!(result, 1, "{}: test case {} failed", name, result >> 1);
+}
+
+/// Run a riscv-arch-test program and compare its signature with the reference signature.
+fn run_arch_test(extension: &str, name: &str) {
+    let dir = compliance_dir()
+        .join("riscv-arch-test/rv32i_m")
+        .join(extension);
+    let mut program = Program::load(&dir.join("bin").join(format!("{}.elf", name)));
+
+    program.run();
+
+    let begin = program.symbol("begin_signature");
+    let end = program.symbol("end_signature");
+    let signature: Vec<u32> = (begin..end)
+        .step_by(4)
+        .map(|addr| program.emu.bus.read(addr, WORD).unwrap())
+        .collect();
+
+    let reference = fs::read_to_string(
+        dir.join("references")
+            .join(format!("{}.reference_output", name)),
+    )
+    .expect("missing reference signature");
+    let reference: Vec<u32> = reference
+        .lines()
+        .map(|line| u32::from_str_radix(line.trim(), 16).expect("invalid reference signature"))
+        .collect();
+
+    assert!(
+        signature.len() >= reference.len(),
+        "{}: signature is shorter than the reference",
+        name
+    );
+    for (i, (actual, expected)) in signature.iter().zip(reference.iter()).enumerate() {
+        assert_eq!(
+            expected,
+            actual,
+            "{}: signature mismatch at word {} ({:#x})",
+            name,
+            i,
+            begin + 4 * i as u32
+        );
+    }
+}
+
+/// Generate a test function per riscv-tests program. `rv32ui_p_add` runs `bin/rv32ui-p-add`.
+macro_rules! riscv_tests {
+    ($($(#[$meta:meta])* $name:ident),* $(,)?) => {
+        $(
+            #[test]
+            $(#[$meta])*
+            fn $name() {
+                run_riscv_test(&stringify!($name).replacen('_', "-", 2));
+            }
+        )*
+    };
+}
+
+/// Generate a test function per riscv-arch-test program.
+macro_rules! arch_tests {
+    ($($name:ident: $extension:literal / $test:literal),* $(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                run_arch_test($extension, $test);
+            }
+        )*
+    };
+}
+
+riscv_tests! {
+    rv32ui_p_add,
+    rv32ui_p_addi,
+    rv32ui_p_and,
+    rv32ui_p_andi,
+    rv32ui_p_auipc,
+    rv32ui_p_beq,
+    rv32ui_p_bge,
+    rv32ui_p_bgeu,
+    rv32ui_p_blt,
+    rv32ui_p_bltu,
+    rv32ui_p_bne,
+    rv32ui_p_fence_i,
+    rv32ui_p_jal,
+    rv32ui_p_jalr,
+    rv32ui_p_lb,
+    rv32ui_p_lbu,
+    rv32ui_p_lh,
+    rv32ui_p_lhu,
+    rv32ui_p_lui,
+    rv32ui_p_lw,
+    rv32ui_p_or,
+    rv32ui_p_ori,
+    rv32ui_p_sb,
+    rv32ui_p_sh,
+    rv32ui_p_simple,
+    rv32ui_p_sll,
+    rv32ui_p_slli,
+    rv32ui_p_slt,
+    rv32ui_p_slti,
+    rv32ui_p_sltiu,
+    rv32ui_p_sltu,
+    rv32ui_p_sra,
+    rv32ui_p_srai,
+    rv32ui_p_srl,
+    rv32ui_p_srli,
+    rv32ui_p_sub,
+    rv32ui_p_sw,
+    rv32ui_p_xor,
+    rv32ui_p_xori,
+
+    rv32um_p_div,
+    rv32um_p_divu,
+    rv32um_p_mul,
+    rv32um_p_mulh,
+    rv32um_p_mulhsu,
+    rv32um_p_mulhu,
+    rv32um_p_rem,
+    rv32um_p_remu,
+
+    rv32ua_p_amoadd_w,
+    rv32ua_p_amoand_w,
+    rv32ua_p_amomax_w,
+    rv32ua_p_amomaxu_w,
+    rv32ua_p_amomin_w,
+    rv32ua_p_amominu_w,
+    rv32ua_p_amoor_w,
+    rv32ua_p_amoswap_w,
+    rv32ua_p_amoxor_w,
+    rv32ua_p_lrsc,
+
+    rv32mi_p_breakpoint,
+    rv32mi_p_csr,
+    rv32mi_p_illegal,
+    rv32mi_p_ma_addr,
+    rv32mi_p_ma_fetch,
+    rv32mi_p_mcsr,
+    rv32mi_p_sbreak,
+    rv32mi_p_scall,
+    rv32mi_p_shamt,
+}
+
+arch_tests! {
+    rv32i_m_i_add_01: "I" / "add-01",
+    rv32i_m_i_addi_01: "I" / "addi-01",
+    rv32i_m_i_and_01: "I" / "and-01",
+    rv32i_m_i_andi_01: "I" / "andi-01",
+    rv32i_m_i_lb_align_01: "I" / "lb-align-01",
+    rv32i_m_i_lbu_align_01: "I" / "lbu-align-01",
+    rv32i_m_i_lh_align_01: "I" / "lh-align-01",
+    rv32i_m_i_lhu_align_01: "I" / "lhu-align-01",
+    rv32i_m_i_lw_align_01: "I" / "lw-align-01",
+    rv32i_m_i_or_01: "I" / "or-01",
+    rv32i_m_i_ori_01: "I" / "ori-01",
+    rv32i_m_i_sb_align_01: "I" / "sb-align-01",
+    rv32i_m_i_sh_align_01: "I" / "sh-align-01",
+    rv32i_m_i_sll_01: "I" / "sll-01",
+    rv32i_m_i_slli_01: "I" / "slli-01",
+    rv32i_m_i_slt_01: "I" / "slt-01",
+    rv32i_m_i_slti_01: "I" / "slti-01",
+    rv32i_m_i_sltiu_01: "I" / "sltiu-01",
+    rv32i_m_i_sltu_01: "I" / "sltu-01",
+    rv32i_m_i_sra_01: "I" / "sra-01",
+    rv32i_m_i_srai_01: "I" / "srai-01",
+    rv32i_m_i_srl_01: "I" / "srl-01",
+    rv32i_m_i_srli_01: "I" / "srli-01",
+    rv32i_m_i_sub_01: "I" / "sub-01",
+    rv32i_m_i_sw_align_01: "I" / "sw-align-01",
+    rv32i_m_i_xor_01: "I" / "xor-01",
+    rv32i_m_i_xori_01: "I" / "xori-01",
+
+    rv32i_m_m_div_01: "M" / "div-01",
+    rv32i_m_m_divu_01: "M" / "divu-01",
+    rv32i_m_m_mul_01: "M" / "mul-01",
+    rv32i_m_m_mulh_01: "M" / "mulh-01",
+    rv32i_m_m_mulhsu_01: "M" / "mulhsu-01",
+    rv32i_m_m_mulhu_01: "M" / "mulhu-01",
+    rv32i_m_m_rem_01: "M" / "rem-01",
+    rv32i_m_m_remu_01: "M" / "remu-01",
+
+    rv32i_m_a_amoadd_w_01: "A" / "amoadd.w-01",
+    rv32i_m_a_amoand_w_01: "A" / "amoand.w-01",
+    rv32i_m_a_amomax_w_01: "A" / "amomax.w-01",
+    rv32i_m_a_amomaxu_w_01: "A" / "amomaxu.w-01",
+    rv32i_m_a_amomin_w_01: "A" / "amomin.w-01",
+    rv32i_m_a_amominu_w_01: "A" / "amominu.w-01",
+    rv32i_m_a_amoor_w_01: "A" / "amoor.w-01",
+    rv32i_m_a_amoswap_w_01: "A" / "amoswap.w-01",
+    rv32i_m_a_amoxor_w_01: "A" / "amoxor.w-01",
+}
+
+/// `misa` advertises only the extensions the suites above cover: RV32I, M and A, with U-mode.
+#[test]
+fn misa() {
+    let emu = Emulator::new();
+    let covered = [Extension::A, Extension::I, Extension::M, Extension::U];
+    let extensions = covered.iter().fold(0, |bits, &ext| bits | 1 << ext as u32);
+    assert_eq!((1 << 30) | extensions, emu.harts[0].state.read(MISA));
+}
@@ -0,0 +1,62 @@
+// Subset of riscv-arch-test `riscv-test-suite/env/arch_test.h` used by the test programs.
+//
+// Every test case computes one value and stores it to the signature through `RVTEST_SIGUPD`.
+// The signature is then compared word by word against the reference output.
+
+#ifndef _ARCH_TEST_H
+#define _ARCH_TEST_H
+
+#include "model_test.h"
+
+#define MASK_XLEN(x) ((x) & 0xffffffff)
+
+#define RVTEST_ISA(_STR)
+
+#define RVTEST_CODE_BEGIN                                               \
+        .section .text.init;                                            \
+        .align 4;                                                       \
+        .globl rvtest_entry_point;                                      \
+rvtest_entry_point:                                                     \
+        RVMODEL_BOOT
+
+#define RVTEST_CODE_END
+
+#define RVTEST_SIGBASE(_R, _TAG)                                        \
+        la _R, _TAG;
+
+#define RVTEST_SIGUPD(_BR, _R, _OFF)                                    \
+        sw _R, _OFF(_BR);
+
+#define TEST_RR_OP(inst, destreg, reg1, reg2, correctval, val1, val2, swreg, offset) \
+        li reg1, MASK_XLEN(val1);                                       \
+        li reg2, MASK_XLEN(val2);                                       \
+        inst destreg, reg1, reg2;                                       \
+        RVTEST_SIGUPD(swreg, destreg, offset)
+
+#define TEST_IMM_OP(inst, destreg, reg, correctval, val, imm, swreg, offset) \
+        li reg, MASK_XLEN(val);                                         \
+        inst destreg, reg, imm;                                         \
+        RVTEST_SIGUPD(swreg, destreg, offset)
+
+#define TEST_LOAD(swreg, testreg, index, rs1, destreg, imm_val, offset, inst) \
+        la rs1, rvtest_data + (index);                                  \
+        addi rs1, rs1, -(imm_val);                                      \
+        inst destreg, imm_val(rs1);                                     \
+        RVTEST_SIGUPD(swreg, destreg, offset)
+
+#define TEST_STORE(swreg, testreg, index, rs1, rs2, rs2_val, imm_val, offset, inst) \
+        li rs2, MASK_XLEN(rs2_val);                                     \
+        addi rs1, swreg, (offset) + (index) - (imm_val);                \
+        inst rs2, imm_val(rs1);
+
+#define TEST_AMO_OP(inst, destreg, origptr, reg2, origval, updval, swreg, offset) \
+        la origptr, rvtest_data;                                        \
+        li reg2, MASK_XLEN(origval);                                    \
+        sw reg2, 0(origptr);                                            \
+        li reg2, MASK_XLEN(updval);                                     \
+        inst destreg, reg2, (origptr);                                  \
+        RVTEST_SIGUPD(swreg, destreg, offset)                           \
+        lw reg2, 0(origptr);                                            \
+        RVTEST_SIGUPD(swreg, reg2, (offset) + 4)
+
+#endif
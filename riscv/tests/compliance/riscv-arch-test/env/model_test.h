@@ -0,0 +1,30 @@
+// Model-specific macros for the riscv-arch-test style programs.
+//
+// The emulator has no host interface beyond memory, so the test halts by writing 1 to `tohost`
+// and spinning. The signature lives between `begin_signature` and `end_signature`.
+
+#ifndef _COMPLIANCE_MODEL_H
+#define _COMPLIANCE_MODEL_H
+
+#define RVMODEL_DATA_SECTION                                            \
+        .pushsection .tohost,"aw",@progbits;                            \
+        .align 6; .global tohost; tohost: .word 0; .word 0;             \
+        .align 6; .global fromhost; fromhost: .word 0; .word 0;         \
+        .popsection;
+
+#define RVMODEL_HALT                                                    \
+        li x1, 1;                                                       \
+write_tohost:                                                           \
+        sw x1, tohost, t5;                                              \
+        j write_tohost;
+
+#define RVMODEL_BOOT
+
+#define RVMODEL_DATA_BEGIN                                              \
+        RVMODEL_DATA_SECTION                                            \
+        .align 4; .global begin_signature; begin_signature:
+
+#define RVMODEL_DATA_END                                                \
+        .align 4; .global end_signature; end_signature:
+
+#endif
@@ -0,0 +1,50 @@
+// Subset of riscv-tests `env/encoding.h` used by the adapted test programs.
+
+#ifndef __riscv_xlen
+#define __riscv_xlen 32
+#endif
+
+#define MSTATUS_SIE         0x00000002
+#define MSTATUS_MIE         0x00000008
+#define MSTATUS_SPIE        0x00000020
+#define MSTATUS_MPIE        0x00000080
+#define MSTATUS_SPP         0x00000100
+#define MSTATUS_MPP         0x00001800
+#define MSTATUS_MPRV        0x00020000
+#define MSTATUS_TVM         0x00100000
+#define MSTATUS_TSR         0x00400000
+
+#define SSTATUS_SPIE        MSTATUS_SPIE
+#define SSTATUS_SPP         MSTATUS_SPP
+
+#define MIP_SSIP            (1 << 1)
+#define MIP_MSIP            (1 << 3)
+#define MIP_MTIP            (1 << 7)
+#define MIP_MEIP            (1 << 11)
+
+#define PRV_U 0
+#define PRV_S 1
+#define PRV_M 3
+
+#define CSR_TINFO 0x7a4
+
+#define MCONTROL_TYPE_MATCH  2
+#define MCONTROL_TYPE_MATCH6 6
+#define MCONTROL_M           (1 << 6)
+#define MCONTROL_S           (1 << 4)
+#define MCONTROL_U           (1 << 3)
+#define MCONTROL_EXECUTE     (1 << 2)
+#define MCONTROL_STORE       (1 << 1)
+#define MCONTROL_LOAD        (1 << 0)
+
+#define CAUSE_MISALIGNED_FETCH    0x0
+#define CAUSE_FETCH_ACCESS        0x1
+#define CAUSE_ILLEGAL_INSTRUCTION 0x2
+#define CAUSE_BREAKPOINT          0x3
+#define CAUSE_MISALIGNED_LOAD     0x4
+#define CAUSE_LOAD_ACCESS         0x5
+#define CAUSE_MISALIGNED_STORE    0x6
+#define CAUSE_STORE_ACCESS        0x7
+#define CAUSE_USER_ECALL          0x8
+#define CAUSE_SUPERVISOR_ECALL    0x9
+#define CAUSE_MACHINE_ECALL       0xb
@@ -0,0 +1,147 @@
+use riscv::bus::DRAM_BASE;
+use riscv::config::*;
+use riscv::cpu::Mode;
+use riscv::csr::*;
+use riscv::emulator::Emulator;
+use riscv::exception::Exception;
+
+/// RV32IM with Zicsr and Zifencei, in M-mode only.
+fn rv32im() -> CpuConfig {
+    use Extension::*;
+    CpuConfig::new(&[I, M, Zicsr, Zifencei])
+}
+
+#[test]
+fn misa() {
+    let mut emu = Emulator::new();
+    // RV32IMA with U-mode.
+    assert_eq!(0x40101101, emu.harts[0].state.read(MISA));
+
+    emu.set_config(rv32im());
+    assert_eq!(0x40001100, emu.harts[0].state.read(MISA));
+
+    emu.reset();
+    assert_eq!(0x40001100, emu.harts[0].state.read(MISA));
+}
+
+#[test]
+fn unimplemented_extension() {
+    assert!(CpuConfig::is_implemented(Extension::M));
+    assert!(!CpuConfig::is_implemented(Extension::C));
+    assert!(!CpuConfig::is_implemented(Extension::Zbb));
+}
+
+#[test]
+#[should_panic(expected = "the C extension is not implemented")]
+fn enable_unimplemented_extension() {
+    CpuConfig::new(&[Extension::I, Extension::C]);
+}
+
+#[test]
+#[should_panic(expected = "a configuration needs the I or E base ISA")]
+fn no_base_isa() {
+    CpuConfig::new(&[Extension::M]);
+}
+
+#[test]
+#[should_panic(expected = "a configuration needs the I or E base ISA")]
+fn without_base_isa() {
+    CpuConfig::default().without(Extension::I);
+}
+
+#[test]
+fn disabled_extension() {
+    let mut emu = Emulator::new();
+    emu.set_config(rv32im());
+
+    let data = vec![
+        0x93, 0x02, 0x60, 0x00, // addi x5, x0, 6
+        0x13, 0x03, 0x70, 0x00, // addi x6, x0, 7
+        0xb3, 0x83, 0x62, 0x02, // mul x7, x5, x6
+        0x2f, 0xa4, 0x54, 0x00, // amoadd.w x8, x5, (x9)
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    let cpu = &mut emu.harts[0];
+    for _ in 0..3 {
+        cpu.execute(&mut emu.bus).unwrap();
+    }
+    assert_eq!(42, cpu.xregs.read(7));
+    assert_eq!(
+        Err(Exception::IllegalInstruction(0x0054a42f)),
+        cpu.execute(&mut emu.bus)
+    );
+
+    // Without Zicsr, CSR instructions are illegal too.
+    cpu.set_config(rv32im().without(Extension::Zicsr));
+    emu.bus.write(DRAM_BASE, 0x30002573, 32).unwrap(); // csrrs x10, mstatus, x0
+    cpu.pc = DRAM_BASE;
+    assert_eq!(
+        Err(Exception::IllegalInstruction(0x30002573)),
+        cpu.execute(&mut emu.bus)
+    );
+}
+
+#[test]
+fn mret_without_user_mode() {
+    let mut emu = Emulator::new();
+    emu.set_config(rv32im());
+
+    let data = vec![
+        0x73, 0x00, 0x20, 0x30, // mret
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    let cpu = &mut emu.harts[0];
+    cpu.state.write(MEPC, DRAM_BASE + 0x100);
+    cpu.state.write_mstatus(MSTATUS_MPP, Mode::User as u32);
+    cpu.execute(&mut emu.bus).unwrap();
+
+    // MPP falls back to M-mode, the only implemented mode.
+    assert_eq!(Mode::Machine, cpu.mode);
+    assert_eq!(DRAM_BASE + 0x100, cpu.pc);
+    assert_eq!(Mode::Machine as u32, cpu.state.read_mstatus(MSTATUS_MPP));
+}
+
+#[test]
+fn rv32e() {
+    let mut emu = Emulator::new();
+    emu.set_config(CpuConfig::new(&[
+        Extension::E,
+        Extension::M,
+        Extension::Zicsr,
+    ]));
+    assert_eq!(0x40001010, emu.harts[0].state.read(MISA));
+    assert_eq!(16, emu.harts[0].xregs.count());
+    assert_eq!(DRAM_BASE + 0x8000, emu.harts[0].xregs.read(2));
+
+    let data = vec![
+        0x93, 0x07, 0x50, 0x00, // addi x15, x0, 5
+        0x73, 0xd7, 0x0f, 0x34, // csrrwi x14, mscratch, 31
+        0x13, 0x08, 0x50, 0x00, // addi x16, x0, 5
+        0xb3, 0x82, 0x08, 0x00, // add x5, x17, x0
+        0x23, 0x20, 0x41, 0x01, // sw x20, 0(x2)
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    let cpu = &mut emu.harts[0];
+    cpu.execute(&mut emu.bus).unwrap();
+    // The immediate of csrrwi isn't a register.
+    cpu.execute(&mut emu.bus).unwrap();
+    assert_eq!(5, cpu.xregs.read(15));
+    assert_eq!(31, cpu.state.read(0x340)); // mscratch
+
+    for inst in [0x00500813, 0x000882b3, 0x01412023] {
+        assert_eq!(
+            Err(Exception::IllegalInstruction(inst)),
+            cpu.execute(&mut emu.bus)
+        );
+        cpu.pc += 4;
+    }
+}
@@ -0,0 +1,168 @@
+use riscv::bus::DRAM_BASE;
+use riscv::clint::CLINT_MTIME;
+use riscv::cpu::{Mode, WORD};
+use riscv::csr::*;
+use riscv::emulator::Emulator;
+use riscv::exception::Exception;
+
+#[test]
+fn cycle_time_and_instret() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x93, 0x02, 0x10, 0x00, // addi x5, x0, 1
+        0x93, 0x02, 0x20, 0x00, // addi x5, x0, 2
+        0xf3, 0x20, 0x20, 0xc0, // csrrs x1, instret, x0
+        0x73, 0x21, 0x00, 0xc0, // csrrs x2, cycle, x0
+        0xf3, 0x21, 0x20, 0xc8, // csrrs x3, instreth, x0
+        0x73, 0x22, 0x10, 0xc0, // csrrs x4, time, x0
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    for _ in 0..6 {
+        emu.step().unwrap();
+    }
+
+    assert_eq!(2, emu.harts[0].xregs.read(1));
+    assert_eq!(4, emu.harts[0].xregs.read(2));
+    assert_eq!(0, emu.harts[0].xregs.read(3));
+    assert_eq!(6, emu.harts[0].xregs.read(4));
+    assert_eq!(6, emu.harts[0].state.read(MINSTRET));
+}
+
+#[test]
+fn counters_carry_into_high_halves() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x13, 0x00, 0x00, 0x00, // addi x0, x0, 0
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    emu.harts[0].state.write(MCYCLE, u32::MAX);
+    emu.harts[0].state.write(MINSTRET, u32::MAX);
+
+    emu.step().unwrap();
+
+    assert_eq!(0, emu.harts[0].state.read(CYCLE));
+    assert_eq!(1, emu.harts[0].state.read(CYCLEH));
+    assert_eq!(0, emu.harts[0].state.read(INSTRET));
+    assert_eq!(1, emu.harts[0].state.read(INSTRETH));
+}
+
+#[test]
+fn time_reads_mtime() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x73, 0x25, 0x10, 0xc0, // csrrs x10, time, x0
+        0xf3, 0x25, 0x10, 0xc8, // csrrs x11, timeh, x0
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    emu.bus.write(CLINT_MTIME, 0x1234, WORD).unwrap();
+    emu.bus.write(CLINT_MTIME + 4, 5, WORD).unwrap();
+
+    // The time CSR is the MTIME register of the CLINT, which the embedder just wrote.
+    let cpu = &mut emu.harts[0];
+    cpu.execute(&mut emu.bus).unwrap();
+    cpu.execute(&mut emu.bus).unwrap();
+    assert_eq!(0x1234, cpu.xregs.read(10));
+    assert_eq!(5, cpu.xregs.read(11));
+}
+
+#[test]
+fn mcountinhibit() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x13, 0x00, 0x00, 0x00, // addi x0, x0, 0
+        0x13, 0x00, 0x00, 0x00, // addi x0, x0, 0
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    // Inhibit cycle and instret. The TM bit is read-only zero.
+    emu.harts[0].state.write(MCOUNTINHIBIT, 0b111);
+
+    emu.step().unwrap();
+    emu.step().unwrap();
+
+    assert_eq!(0b101, emu.harts[0].state.read(MCOUNTINHIBIT));
+    assert_eq!(0, emu.harts[0].state.read(MCYCLE));
+    assert_eq!(0, emu.harts[0].state.read(MINSTRET));
+    assert_eq!(2, emu.bus.read(CLINT_MTIME, WORD).unwrap());
+}
+
+#[test]
+fn hpm_events() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0xb7, 0x00, 0x01, 0x00, // lui x1, 16
+        0x03, 0xa1, 0x00, 0x10, // lw x2, 256(x1)
+        0x23, 0xa2, 0x20, 0x10, // sw x2, 260(x1)
+        0x03, 0xa1, 0x80, 0x10, // lw x2, 264(x1)
+        0x63, 0x04, 0x00, 0x00, // beq x0, x0, 8
+        0x13, 0x00, 0x00, 0x00, // addi x0, x0, 0
+        0x63, 0x14, 0x00, 0x00, // bne x0, x0, 8
+        0x73, 0x00, 0x00, 0x00, // ecall
+    ];
+    let len = data.len() as u32;
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    emu.harts[0].state.write(MHPMEVENT3, HPM_EVENT_LOAD);
+    emu.harts[0].state.write(MHPMEVENT3 + 1, HPM_EVENT_STORE);
+    emu.harts[0]
+        .state
+        .write(MHPMEVENT3 + 2, HPM_EVENT_BRANCH_TAKEN);
+    emu.harts[0].state.write(MHPMEVENT3 + 3, HPM_EVENT_TRAP);
+    // Unknown events are not selectable.
+    emu.harts[0].state.write(MHPMEVENT3 + 4, 0x1234);
+    // mhpmcounter8 counts loads but is inhibited.
+    emu.harts[0].state.write(MHPMEVENT3 + 5, HPM_EVENT_LOAD);
+    emu.harts[0].state.write(MCOUNTINHIBIT, 1 << 8);
+
+    // Stops when the ecall traps to mtvec (0).
+    emu.test_start(DRAM_BASE, DRAM_BASE + len);
+
+    assert_eq!(2, emu.harts[0].state.read(MHPMCOUNTER3));
+    assert_eq!(1, emu.harts[0].state.read(MHPMCOUNTER3 + 1));
+    assert_eq!(1, emu.harts[0].state.read(MHPMCOUNTER3 + 2));
+    assert_eq!(1, emu.harts[0].state.read(MHPMCOUNTER3 + 3));
+    assert_eq!(HPM_EVENT_NONE, emu.harts[0].state.read(MHPMEVENT3 + 4));
+    assert_eq!(0, emu.harts[0].state.read(MHPMCOUNTER3 + 5));
+    // The user-level counters shadow the machine-level counters.
+    assert_eq!(2, emu.harts[0].state.read(HPMCOUNTER3));
+    assert_eq!(0, emu.harts[0].state.read(HPMCOUNTER3H));
+}
+
+#[test]
+fn mcounteren_gates_user_mode() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0xf3, 0x20, 0x00, 0xc0, // csrrs x1, cycle, x0
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    emu.harts[0].mode = Mode::User;
+    emu.harts[0].state.write(MCYCLE, 42);
+
+    assert_eq!(
+        Err(Exception::IllegalInstruction(0xc00020f3)),
+        emu.harts[0].execute(&mut emu.bus)
+    );
+
+    // Enable the CY bit.
+    emu.harts[0].state.write(MCOUNTEREN, 1);
+    emu.harts[0].execute(&mut emu.bus).unwrap();
+
+    assert_eq!(42, emu.harts[0].xregs.read(1));
+}
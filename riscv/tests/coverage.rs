@@ -0,0 +1,103 @@
+use riscv::bus::DRAM_BASE;
+use riscv::coverage::*;
+use riscv::emulator::Emulator;
+
+/// Add, execute two illegal instructions and an ecall whose trap handler skips them, and add again.
+const PROGRAM: [u8; 56] = [
+    0x37, 0x03, 0x01, 0x00, // lui x6, 16
+    0x13, 0x03, 0x83, 0x02, // addi x6, x6, 40
+    0x73, 0x10, 0x53, 0x30, // csrrw x0, mtvec, x6
+    0x93, 0x02, 0x30, 0x00, // addi x5, x0, 3
+    0xb3, 0x83, 0x52, 0x00, // add x7, x5, x5
+    0x7f, 0x00, 0x00, 0x00, // .word 0x0000007f (unknown opcode)
+    0x03, 0x30, 0x00, 0x00, // .word 0x00003003 (load with funct3 3)
+    0x73, 0x00, 0x00, 0x00, // ecall
+    0xb3, 0x83, 0x73, 0x00, // add x7, x7, x7
+    0x6f, 0x00, 0x00, 0x00, // jal x0, 0
+    // trap handler
+    0x73, 0x2e, 0x10, 0x34, // csrrs x28, mepc, x0
+    0x13, 0x0e, 0x4e, 0x00, // addi x28, x28, 4
+    0x73, 0x10, 0x1e, 0x34, // csrrw x0, mepc, x28
+    0x73, 0x00, 0x20, 0x30, // mret
+];
+
+fn run() -> Emulator {
+    let mut emu = Emulator::new();
+    emu.initialize_dram(PROGRAM.to_vec());
+    emu.initialize_pc(DRAM_BASE);
+    emu.enable_coverage();
+    for _ in 0..25 {
+        emu.step().unwrap();
+    }
+    assert_eq!(12, emu.harts[0].xregs.read(7));
+    emu
+}
+
+#[test]
+fn hits() {
+    let coverage = run().coverage().unwrap();
+
+    assert_eq!(2, coverage.hits(Kind::Instruction, "add"));
+    assert_eq!(3, coverage.hits(Kind::Instruction, "mret"));
+    assert_eq!(0, coverage.hits(Kind::Instruction, "sub"));
+    assert_eq!(1, coverage.hits(Kind::Illegal, "unknown opcode"));
+    assert_eq!(1, coverage.hits(Kind::Illegal, "load funct3"));
+    assert_eq!(0, coverage.hits(Kind::Illegal, "store funct3"));
+    assert_eq!(2, coverage.hits(Kind::Trap, "IllegalInstruction"));
+    assert_eq!(1, coverage.hits(Kind::Trap, "EnvironmentCallFromMMode"));
+    assert_eq!(0, coverage.hits(Kind::Trap, "Breakpoint"));
+    // An unknown point is never hit.
+    assert_eq!(0, coverage.hits(Kind::Instruction, "fadd.s"));
+
+    let missed: Vec<&str> = coverage.missed(Kind::Instruction).collect();
+    assert_eq!(INSTRUCTIONS.len() - 8, missed.len());
+    assert!(missed.contains(&"sub"));
+    assert!(!missed.contains(&"add"));
+}
+
+#[test]
+fn disabled() {
+    let mut emu = Emulator::new();
+    emu.initialize_dram(PROGRAM.to_vec());
+    emu.initialize_pc(DRAM_BASE);
+    emu.step().unwrap();
+    assert_eq!(None, emu.coverage());
+}
+
+#[test]
+fn merge_and_encode() {
+    let coverage = run().coverage().unwrap();
+
+    let mut merged = coverage.clone();
+    merged.merge(&coverage);
+    assert_eq!(4, merged.hits(Kind::Instruction, "add"));
+
+    // Concatenated encodings add up like a merge.
+    let text = coverage.encode();
+    assert!(text.contains("instruction 2 add\n"));
+    assert_eq!(Some(coverage.clone()), Coverage::decode(&text));
+    assert_eq!(Some(merged), Coverage::decode(&(text.clone() + &text)));
+
+    assert_eq!(None, Coverage::decode("instruction 1 fadd.s"));
+    assert_eq!(None, Coverage::decode("opcode 1 add"));
+    assert_eq!(None, Coverage::decode("instruction x add"));
+}
+
+#[test]
+fn report() {
+    let report = run().coverage().unwrap().to_string();
+
+    assert!(report.contains(&format!(
+        "instructions: 8/{} exercised\n",
+        INSTRUCTIONS.len()
+    )));
+    assert!(report.contains(&format!(
+        "illegal encodings: 2/{} exercised\n",
+        ILLEGAL_ENCODINGS.len()
+    )));
+    assert!(report.contains(&format!("trap causes: 2/{} exercised\n", TRAP_CAUSES.len())));
+    assert!(report.contains("  not exercised: sub\n"));
+    assert!(report.contains("  not exercised: store funct3\n"));
+    assert!(report.contains("  not exercised: Breakpoint\n"));
+    assert!(!report.contains("  not exercised: add\n"));
+}
@@ -0,0 +1,163 @@
+use riscv::bus::DRAM_BASE;
+use riscv::config::{CpuConfig, Extension};
+use riscv::cpu::Mode;
+use riscv::csr::*;
+use riscv::emulator::Emulator;
+use riscv::exception::Exception;
+
+const DATA: [u8; 48] = [
+    0xf3, 0x22, 0x10, 0xf1, // csrrs x5, mvendorid, x0
+    0x73, 0x10, 0x10, 0xf1, // csrrw x0, mvendorid, x0
+    0x73, 0x23, 0x00, 0xc0, // csrrs x6, cycle, x0
+    0x73, 0x60, 0x00, 0xc0, // csrrsi x0, cycle, 0
+    0x73, 0x90, 0x02, 0xc0, // csrrw x0, cycle, x5
+    0x73, 0x90, 0x52, 0x30, // csrrw x0, mtvec, x5
+    0xf3, 0x23, 0x00, 0x34, // csrrs x7, mscratch, x0
+    0x73, 0x10, 0x04, 0x34, // csrrw x0, mscratch, x8
+    0x73, 0x90, 0x04, 0x30, // csrrw x0, mstatus, x9
+    0x73, 0x10, 0x10, 0x30, // csrrw x0, misa, x0
+    0x73, 0x10, 0x55, 0x30, // csrrw x0, mtvec, x10
+    0x73, 0x90, 0x45, 0x34, // csrrw x0, mip, x11
+];
+
+#[test]
+fn access_permissions() {
+    let mut emu = Emulator::new();
+    emu.initialize_dram(DATA.to_vec());
+    emu.initialize_pc(DRAM_BASE);
+
+    let cpu = &mut emu.harts[0];
+    cpu.execute(&mut emu.bus).unwrap();
+    assert_eq!(
+        Err(Exception::IllegalInstruction(0xf1101073)),
+        cpu.execute(&mut emu.bus)
+    );
+    cpu.pc += 4;
+    // Reading a read-only CSR is allowed, including with an instruction that doesn't write.
+    cpu.execute(&mut emu.bus).unwrap();
+    cpu.execute(&mut emu.bus).unwrap();
+    assert_eq!(
+        Err(Exception::IllegalInstruction(0xc0029073)),
+        cpu.execute(&mut emu.bus)
+    );
+    cpu.pc += 4;
+
+    // U-mode can't access machine-level CSRs.
+    cpu.mode = Mode::User;
+    cpu.xregs.write(5, DRAM_BASE);
+    assert_eq!(
+        Err(Exception::IllegalInstruction(0x30529073)),
+        cpu.execute(&mut emu.bus)
+    );
+    assert_eq!(0, cpu.state.read(MTVEC));
+}
+
+#[test]
+fn warl_fields() {
+    let mut emu = Emulator::new();
+    emu.initialize_dram(DATA.to_vec());
+    emu.initialize_pc(DRAM_BASE + 24);
+
+    let cpu = &mut emu.harts[0];
+    let misa = cpu.state.read(MISA);
+    cpu.xregs.write(8, u32::MAX);
+    // MPP=2 is reserved.
+    cpu.xregs.write(9, (2 << 11) | (1 << 3));
+    // Mode 2 is reserved.
+    cpu.xregs.write(10, DRAM_BASE | 2);
+    cpu.xregs.write(11, u32::MAX);
+
+    cpu.execute(&mut emu.bus).unwrap();
+    assert_eq!(0, cpu.xregs.read(7));
+
+    // MSCRATCH has no WARL fields.
+    cpu.execute(&mut emu.bus).unwrap();
+    assert_eq!(u32::MAX, cpu.state.read(0x340));
+    assert_eq!(0, cpu.state.read_mstatus(MSTATUS_MIE));
+
+    cpu.execute(&mut emu.bus).unwrap();
+    assert_eq!(1, cpu.state.read_mstatus(MSTATUS_MIE));
+    assert_eq!(Mode::User as u32, cpu.state.read_mstatus(MSTATUS_MPP));
+
+    for _ in 0..3 {
+        cpu.execute(&mut emu.bus).unwrap();
+    }
+    assert_eq!(misa, cpu.state.read(MISA));
+    assert_eq!(DRAM_BASE, cpu.state.read(MTVEC));
+    // The pending bits are owned by the devices.
+    assert_eq!(0, cpu.state.read(MIP));
+}
+
+#[test]
+fn unknown_csr() {
+    let mut emu = Emulator::new();
+    let data = vec![
+        0xf3, 0x22, 0xf0, 0xb1, // csrrs x5, mhpmcounter31, x0
+        0xf3, 0x22, 0x00, 0x7c, // csrrs x5, 0x7c0, x0
+    ];
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    let cpu = &mut emu.harts[0];
+    cpu.execute(&mut emu.bus).unwrap();
+    // A CSR this emulator doesn't implement doesn't exist, even in M-mode.
+    assert_eq!(
+        Err(Exception::IllegalInstruction(0x7c0022f3)),
+        cpu.execute(&mut emu.bus)
+    );
+}
+
+#[test]
+fn csrs_of_missing_extensions() {
+    let mut emu = Emulator::new();
+    let data = vec![
+        0x73, 0x25, 0x30, 0x00, // csrrs x10, fcsr, x0
+        0x73, 0x10, 0x05, 0x18, // csrrw x0, satp, x10
+        0xf3, 0x22, 0x00, 0x00, // csrrs x5, ustatus, x0
+        0xf3, 0x22, 0x60, 0x30, // csrrs x5, mcounteren, x0
+    ];
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    // The F, S and N extensions aren't enabled, so their CSRs don't exist.
+    let cpu = &mut emu.harts[0];
+    for inst in [0x00302573, 0x18051073, 0x000022f3] {
+        assert_eq!(
+            Err(Exception::IllegalInstruction(inst)),
+            cpu.execute(&mut emu.bus)
+        );
+        cpu.pc += 4;
+    }
+    cpu.execute(&mut emu.bus).unwrap();
+
+    // Without U-mode, mcounteren doesn't exist either.
+    cpu.set_config(CpuConfig::default().without(Extension::U));
+    cpu.pc -= 4;
+    assert_eq!(
+        Err(Exception::IllegalInstruction(0x306022f3)),
+        cpu.execute(&mut emu.bus)
+    );
+}
+
+#[test]
+fn mret_in_user_mode() {
+    let mut emu = Emulator::new();
+    let data = vec![
+        0x73, 0x00, 0x20, 0x30, // mret
+    ];
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    let cpu = &mut emu.harts[0];
+    cpu.mode = Mode::User;
+    cpu.state.write(MEPC, DRAM_BASE + 0x100);
+    cpu.state.write_mstatus(MSTATUS_MPP, Mode::Machine as u32);
+
+    // U-mode can't return to M-mode with mret.
+    assert_eq!(
+        Err(Exception::IllegalInstruction(0x30200073)),
+        cpu.execute(&mut emu.bus)
+    );
+    assert_eq!(Mode::User, cpu.mode);
+    assert_eq!(DRAM_BASE, cpu.pc);
+}
@@ -0,0 +1,117 @@
+use riscv::bus::DRAM_BASE;
+use riscv::coverage::Kind;
+use riscv::cpu::BYTE;
+use riscv::csr::*;
+use riscv::custom::*;
+use riscv::emulator::Emulator;
+use riscv::exception::Exception;
+
+/// A custom-0 multiply and a custom-1 fill of `x[rs1]` bytes at `x[rd]`.
+struct Extension;
+
+impl CustomExtension for Extension {
+    fn execute(
+        &mut self,
+        inst: &CustomInstruction,
+        context: &mut CustomContext,
+    ) -> Result<(), Exception> {
+        match (inst.index, inst.funct3) {
+            (0, 0) => {
+                let value = context.read_register(inst.rs1)? * context.read_register(inst.rs2)?;
+                context.write_register(inst.rd, value)
+            }
+            (1, 1) => {
+                let dst = context.read_register(inst.rd)?;
+                for i in 0..context.read_register(inst.rs1)? {
+                    context.store(dst + i, 0xaa, BYTE)?;
+                }
+                Ok(())
+            }
+            _ => Err(Exception::IllegalInstruction(inst.inst)),
+        }
+    }
+}
+
+const PROGRAM: [u8; 28] = [
+    0x13, 0x03, 0x60, 0x00, // addi x6, x0, 6
+    0x93, 0x03, 0x70, 0x00, // addi x7, x0, 7
+    0x8b, 0x02, 0x73, 0x00, // .insn r 0x0b, 0, 0, x5, x6, x7
+    0x37, 0x15, 0x01, 0x00, // lui x10, 17
+    0x93, 0x05, 0x80, 0x00, // addi x11, x0, 8
+    0x2b, 0x95, 0x05, 0x00, // .insn r 0x2b, 1, 0, x10, x11, x0
+    0x7b, 0x70, 0x00, 0x00, // .insn r 0x7b, 7, 0, x0, x0, x0
+];
+
+#[test]
+fn extension() {
+    let mut emu = Emulator::new();
+    emu.bus.custom_extension = Some(Box::new(Extension));
+    emu.initialize_dram(PROGRAM.to_vec());
+    emu.initialize_pc(DRAM_BASE);
+    emu.enable_coverage();
+
+    for _ in 0..6 {
+        emu.step().unwrap();
+    }
+    let cpu = &emu.harts[0];
+    assert_eq!(42, cpu.xregs.read(5));
+    assert_eq!(DRAM_BASE + 24, cpu.pc);
+    assert_eq!(6, cpu.state.read(MINSTRET));
+    for addr in 0x11000..0x11008 {
+        assert_eq!(0xaa, emu.bus.read(addr, BYTE).unwrap());
+    }
+    assert_eq!(0, emu.bus.read(0x11008, BYTE).unwrap());
+
+    // An encoding the extension doesn't implement is illegal.
+    emu.step().unwrap();
+    let cpu = &emu.harts[0];
+    assert_eq!(2, cpu.state.read(MCAUSE));
+    assert_eq!(0x707b, cpu.state.read(MTVAL));
+    assert_eq!(DRAM_BASE + 24, cpu.state.read(MEPC));
+
+    // The custom opcodes are decoded like any other instruction.
+    let coverage = emu.coverage().unwrap();
+    assert_eq!(3, coverage.hits(Kind::Instruction, "custom"));
+    assert_eq!(0, coverage.hits(Kind::Illegal, "unknown opcode"));
+}
+
+#[test]
+fn access_fault() {
+    let mut emu = Emulator::new();
+    emu.bus.custom_extension = Some(Box::new(Extension));
+    emu.initialize_dram(PROGRAM.to_vec());
+    emu.initialize_pc(DRAM_BASE + 20);
+    emu.harts[0].xregs.write(10, 0x8000_0000);
+    emu.harts[0].xregs.write(11, 4);
+
+    emu.step().unwrap();
+    let cpu = &emu.harts[0];
+    assert_eq!(7, cpu.state.read(MCAUSE));
+    assert_eq!(0x8000_0000, cpu.state.read(MTVAL));
+    assert_eq!(DRAM_BASE + 20, cpu.state.read(MEPC));
+    assert_eq!(0, cpu.state.read(MINSTRET));
+}
+
+#[test]
+fn without_extension() {
+    let mut emu = Emulator::new();
+    emu.initialize_dram(PROGRAM.to_vec());
+    emu.initialize_pc(DRAM_BASE);
+    emu.enable_coverage();
+
+    for _ in 0..3 {
+        emu.step().unwrap();
+    }
+    let cpu = &emu.harts[0];
+    assert_eq!(0, cpu.xregs.read(5));
+    assert_eq!(2, cpu.state.read(MCAUSE));
+    assert_eq!(0x73028b, cpu.state.read(MTVAL));
+    assert_eq!(DRAM_BASE + 8, cpu.state.read(MEPC));
+
+    let coverage = emu.coverage().unwrap();
+    assert_eq!(
+        1,
+        coverage.hits(Kind::Illegal, "custom opcode without extension")
+    );
+    assert_eq!(0, coverage.hits(Kind::Instruction, "custom"));
+}
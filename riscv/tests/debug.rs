@@ -0,0 +1,132 @@
+use riscv::bus::DRAM_BASE;
+use riscv::cpu::Mode;
+use riscv::csr::*;
+use riscv::emulator::Emulator;
+use riscv::exception::{Exception, Trap};
+
+/// `dret`
+const DRET: u32 = 0x7b200073;
+/// `csrrw x0, dpc, x6`
+const CSRW_DPC_X6: u32 = 0x7b131073;
+/// `csrrs x7, dcsr, x0`
+const CSRR_X7_DCSR: u32 = 0x7b0023f3;
+
+#[test]
+fn ebreak_enters_debug_mode() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x93, 0x02, 0x10, 0x00, // addi x5, x0, 1
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x93, 0x02, 0x20, 0x00, // addi x5, x0, 2
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    emu.harts[0].state.write_bits(DCSR, DCSR_EBREAKM, 1);
+
+    for _ in 0..4 {
+        emu.step().unwrap();
+    }
+
+    // The hart stays halted at the ebreak.
+    let cpu = &mut emu.harts[0];
+    assert!(cpu.is_halted());
+    assert_eq!(1, cpu.xregs.read(5));
+    assert_eq!(DRAM_BASE + 4, cpu.state.read(DPC));
+    assert_eq!(1, cpu.state.read_bits(DCSR, DCSR_CAUSE));
+    assert_eq!(Mode::Machine as u32, cpu.state.read_bits(DCSR, DCSR_PRV));
+    // The debugger moves DPC past the ebreak and resumes the hart.
+    cpu.xregs.write(6, DRAM_BASE + 8);
+    cpu.execute_debug(&mut emu.bus, CSRW_DPC_X6).unwrap();
+    cpu.execute_debug(&mut emu.bus, DRET).unwrap();
+    assert_eq!(Mode::Machine, cpu.mode);
+    assert_eq!(DRAM_BASE + 8, cpu.pc);
+
+    emu.step().unwrap();
+
+    assert_eq!(2, emu.harts[0].xregs.read(5));
+    assert_eq!(0, emu.harts[0].state.read(MCAUSE));
+}
+
+#[test]
+fn halt_and_single_step() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x93, 0x02, 0x10, 0x00, // addi x5, x0, 1
+        0x93, 0x82, 0x12, 0x00, // addi x5, x5, 1
+        0x93, 0x82, 0x12, 0x00, // addi x5, x5, 1
+        0x93, 0x82, 0x12, 0x00, // addi x5, x5, 1
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    emu.step().unwrap();
+    emu.harts[0].halt();
+    emu.step().unwrap();
+
+    let cpu = &mut emu.harts[0];
+    assert!(cpu.is_halted());
+    assert_eq!(1, cpu.xregs.read(5));
+    cpu.execute_debug(&mut emu.bus, CSRR_X7_DCSR).unwrap();
+    assert_eq!(4 << 28, cpu.xregs.read(7) & (0xf << 28));
+    assert_eq!(3, cpu.state.read_bits(DCSR, DCSR_CAUSE));
+    assert_eq!(DRAM_BASE + 4, cpu.state.read(DPC));
+
+    // Step a single instruction.
+    cpu.state.write_bits(DCSR, DCSR_STEP, 1);
+    cpu.execute_debug(&mut emu.bus, DRET).unwrap();
+    emu.step().unwrap();
+    emu.step().unwrap();
+
+    let cpu = &mut emu.harts[0];
+    assert!(cpu.is_halted());
+    assert_eq!(2, cpu.xregs.read(5));
+    assert_eq!(4, cpu.state.read_bits(DCSR, DCSR_CAUSE));
+    assert_eq!(DRAM_BASE + 8, cpu.state.read(DPC));
+}
+
+#[test]
+fn debug_registers_outside_debug_mode() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0xf3, 0x22, 0x00, 0x7b, // csrrs x5, dcsr, x0
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    emu.step().unwrap();
+
+    assert_eq!(2, emu.harts[0].state.read(MCAUSE));
+    assert_eq!(0x7b0022f3, emu.harts[0].state.read(MTVAL));
+    // dret is illegal outside debug mode, too.
+    let cpu = &mut emu.harts[0];
+    assert!(cpu.execute_debug(&mut emu.bus, DRET).is_err());
+}
+
+#[test]
+fn exception_in_debug_mode() {
+    let mut emu = Emulator::new();
+    emu.initialize_dram(vec![0x13, 0x00, 0x00, 0x00]); // addi x0, x0, 0
+    emu.initialize_pc(DRAM_BASE);
+    emu.harts[0].state.write(MTVEC, 0x1000);
+    emu.harts[0].halt();
+    emu.step().unwrap();
+
+    // An exception doesn't update any register, and the hart stays in debug mode.
+    let cpu = &mut emu.harts[0];
+    let mstatus = cpu.state.read(MSTATUS);
+    let trap = Exception::IllegalInstruction(0).take_trap(cpu);
+    assert!(matches!(trap, Trap::Invisible));
+    assert!(cpu.is_halted());
+    assert_eq!(Mode::Debug, cpu.mode);
+    assert_eq!(DRAM_BASE, cpu.pc);
+    assert_eq!(0, cpu.state.read(MCAUSE));
+    assert_eq!(0, cpu.state.read(MEPC));
+    assert_eq!(mstatus, cpu.state.read(MSTATUS));
+    assert_eq!(DRAM_BASE, cpu.state.read(DPC));
+}
@@ -0,0 +1,118 @@
+use riscv::bus::{overlapping_device, DRAM_BASE};
+use riscv::clint::{CLINT_BASE, CLINT_SIZE};
+use riscv::cpu::{BYTE, HALFWORD, WORD};
+use riscv::dram::*;
+use riscv::emulator::Emulator;
+use riscv::exception::Exception;
+use riscv::framebuffer::FRAMEBUFFER_VRAM;
+
+const DRAM_END: u32 = DRAM_BASE + DRAM_SIZE;
+
+#[test]
+fn sparse() {
+    let base = 0x8000_0000;
+    let size = 16 * 1024 * 1024;
+    let mut emu = Emulator::with_memory(1, base, size);
+    assert_eq!(size, emu.bus.dram_size());
+    assert_eq!(0, emu.bus.dram().allocated_pages());
+
+    // Untouched pages read as zero without being allocated.
+    assert_eq!(0, emu.bus.read(base, WORD).unwrap());
+    assert_eq!(0, emu.bus.read(base + size - 4, WORD).unwrap());
+    assert_eq!(0, emu.bus.dram().allocated_pages());
+
+    emu.bus.write(base + size - 4, 0xdeadbeef, WORD).unwrap();
+    assert_eq!(1, emu.bus.dram().allocated_pages());
+    assert_eq!(0xdeadbeef, emu.bus.read(base + size - 4, WORD).unwrap());
+    assert_eq!(
+        Err(Exception::StoreAMOAccessFault(base + size - 2)),
+        emu.bus.write(base + size - 2, 0, WORD)
+    );
+
+    // An access that crosses a page boundary touches both pages.
+    emu.bus
+        .write(base + PAGE_SIZE - 1, 0x1234, HALFWORD)
+        .unwrap();
+    assert_eq!(3, emu.bus.dram().allocated_pages());
+    assert_eq!(0x34, emu.bus.read(base + PAGE_SIZE - 1, BYTE).unwrap());
+    assert_eq!(0x12, emu.bus.read(base + PAGE_SIZE, BYTE).unwrap());
+    assert_eq!(
+        0x1234,
+        emu.bus.read(base + PAGE_SIZE - 1, HALFWORD).unwrap()
+    );
+    emu.bus
+        .write(base + 2 * PAGE_SIZE - 3, 0xcafef00d, WORD)
+        .unwrap();
+    assert_eq!(4, emu.bus.dram().allocated_pages());
+    assert_eq!(0xca, emu.bus.read(base + 2 * PAGE_SIZE, BYTE).unwrap());
+    assert_eq!(
+        0xcafef00d,
+        emu.bus.read(base + 2 * PAGE_SIZE - 3, WORD).unwrap()
+    );
+}
+
+#[test]
+fn initialize() {
+    let mut dram = Dram::new();
+    dram.write(DRAM_END - 4, 0xffff_ffff, WORD).unwrap();
+
+    // The size doesn't change, and zero pages of the image aren't allocated.
+    let mut image = vec![0; PAGE_SIZE as usize + 4];
+    image[PAGE_SIZE as usize..].copy_from_slice(&[1, 2, 3, 4]);
+    dram.initialize(image);
+    assert_eq!(DRAM_SIZE, dram.size());
+    assert_eq!(2, dram.allocated_pages());
+    assert_eq!(0, dram.read(dram.base(), WORD).unwrap());
+    assert_eq!(
+        0x04030201,
+        dram.read(dram.base() + PAGE_SIZE, WORD).unwrap()
+    );
+    assert_eq!(0xffff_ffff, dram.read(DRAM_END - 4, WORD).unwrap());
+}
+
+#[test]
+fn device_overlap() {
+    assert_eq!(None, overlapping_device(DRAM_BASE, DRAM_SIZE));
+    assert_eq!(None, overlapping_device(0, CLINT_BASE));
+    assert_eq!(None, overlapping_device(CLINT_BASE + CLINT_SIZE, 0x10000));
+    assert_eq!(
+        Some(("CLINT", CLINT_BASE..=CLINT_BASE + CLINT_SIZE - 1)),
+        overlapping_device(0, 64 * 1024 * 1024)
+    );
+    assert_eq!(
+        Some(("CLINT", CLINT_BASE..=CLINT_BASE + CLINT_SIZE - 1)),
+        overlapping_device(CLINT_BASE + CLINT_SIZE - 1, 1)
+    );
+}
+
+#[test]
+#[should_panic(expected = "overlaps the CLINT")]
+fn dram_over_clint() {
+    Emulator::with_memory(1, 0, 64 * 1024 * 1024);
+}
+
+#[test]
+#[should_panic(expected = "overlaps the framebuffer VRAM")]
+fn dram_over_vram() {
+    Emulator::with_memory(1, FRAMEBUFFER_VRAM, 64 * 1024);
+}
+
+#[test]
+fn end_of_address_space() {
+    let base = 0xffff_0000;
+    let mut dram = Dram::with_size(base, 0x10000);
+    dram.write(0xffff_fffc, 0x11223344, WORD).unwrap();
+    assert_eq!(0x11223344, dram.read(0xffff_fffc, WORD).unwrap());
+    assert_eq!(0x1122, dram.read(0xffff_fffe, HALFWORD).unwrap());
+    assert_eq!(
+        Err(Exception::LoadAccessFault(0xffff_fffe)),
+        dram.read(0xffff_fffe, WORD)
+    );
+    assert_eq!(Err(Exception::LoadAccessFault(0)), dram.read(0, BYTE));
+}
+
+#[test]
+#[should_panic(expected = "doesn't fit in the address space")]
+fn wrapping_memory() {
+    Dram::with_size(0xffff_0000, 0x20000);
+}
@@ -1,6 +1,30 @@
 use riscv::bus::DRAM_BASE;
-use riscv::csr::MEPC;
+use riscv::cpu::Mode;
+use riscv::csr::{MCAUSE, MEPC, MTVAL, MTVEC};
 use riscv::emulator::Emulator;
+use riscv::exception::Exception;
+
+/// Execute instructions until the first exception, check that it is `expected`, and take the trap.
+fn run_until_trap(emu: &mut Emulator, data: Vec<u8>, expected: Exception) {
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    let exception = loop {
+        if let Err(exception) = emu.harts[0].execute(&mut emu.bus) {
+            break exception;
+        }
+    };
+    assert_eq!(expected, exception);
+
+    exception.take_trap(&mut emu.harts[0]);
+}
+
+/// Check the CSRs written by taking a trap.
+fn assert_trap(emu: &Emulator, mcause: u32, mepc: u32, mtval: u32) {
+    assert_eq!(mcause, emu.harts[0].state.read(MCAUSE), "mcause");
+    assert_eq!(mepc, emu.harts[0].state.read(MEPC), "mepc");
+    assert_eq!(mtval, emu.harts[0].state.read(MTVAL), "mtval");
+}
 
 #[test]
 fn illegal_isa() {
@@ -17,7 +41,264 @@ fn illegal_isa() {
 
     emu.start();
 
-    // TODO: correct?
-    // assert_eq!(4 + DRAM_BASE, emu.cpu.state.read(MEPC));
-    assert_eq!(8 + DRAM_BASE, emu.cpu.state.read(MEPC));
+    assert_trap(&emu, 2, 4 + DRAM_BASE, 0xaaaaaaaa);
+}
+
+#[test]
+fn instruction_address_misaligned() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x93, 0x00, 0x50, 0x00, // addi x1, x0, 5
+        0xef, 0x00, 0x60, 0x00, // jal x1, 6
+    ];
+
+    run_until_trap(
+        &mut emu,
+        data,
+        Exception::InstructionAddressMisaligned(10 + DRAM_BASE),
+    );
+
+    assert_trap(&emu, 0, 4 + DRAM_BASE, 10 + DRAM_BASE);
+    // The jump doesn't write the link register.
+    assert_eq!(5, emu.harts[0].xregs.read(1));
+}
+
+#[test]
+fn instruction_access_fault() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x67, 0x00, 0x00, 0x10, // jalr x0, 256(x0)
+        0x93, 0x0f, 0x50, 0x00, // addi x31, x0, 5
+    ];
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    emu.harts[0].state.write(MTVEC, DRAM_BASE + 4);
+
+    // The fault is taken, and the trap handler runs.
+    emu.step().unwrap();
+    emu.step().unwrap();
+    assert_trap(&emu, 1, 0x100, 0x100);
+    assert_eq!(DRAM_BASE + 4, emu.harts[0].pc);
+    emu.step().unwrap();
+    assert_eq!(5, emu.harts[0].xregs.read(31));
+}
+
+#[test]
+fn jump_to_zero() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x67, 0x00, 0x00, 0x00, // jalr x0, 0(x0)
+    ];
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    // The program counter wraps around to address 0, which isn't memory.
+    emu.harts[0].execute(&mut emu.bus).unwrap();
+    assert_eq!(0, emu.harts[0].pc);
+    assert_eq!(
+        Err(Exception::InstructionAccessFault(0)),
+        emu.harts[0].execute(&mut emu.bus)
+    );
+
+    // Without a trap handler, mtvec is 0 as well, so the fault is fatal.
+    emu.initialize_pc(DRAM_BASE);
+    emu.step().unwrap();
+    assert_eq!(Err(Exception::InstructionAccessFault(0)), emu.step());
+    assert_eq!(0, emu.harts[0].pc);
+}
+
+#[test]
+fn illegal_instruction() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x93, 0x0f, 0x50, 0x00, // addi x31, x0, 5
+        0xff, 0xff, 0xff, 0xff, // Invalid ISA
+    ];
+
+    run_until_trap(&mut emu, data, Exception::IllegalInstruction(0xffffffff));
+
+    assert_trap(&emu, 2, 4 + DRAM_BASE, 0xffffffff);
+}
+
+#[test]
+fn unimplemented_privileged_instructions() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x73, 0x00, 0x20, 0x00, // uret
+        0x73, 0x00, 0xb5, 0x12, // sfence.vma a0, a1
+        0x73, 0x00, 0x00, 0x22, // hfence.bvma x0, x0
+        0x73, 0x00, 0x00, 0xa2, // hfence.gvma x0, x0
+    ];
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    // The N, S and H extensions aren't implemented.
+    let cpu = &mut emu.harts[0];
+    for inst in [0x00200073, 0x12b50073, 0x22000073, 0xa2000073] {
+        assert_eq!(
+            Err(Exception::IllegalInstruction(inst)),
+            cpu.execute(&mut emu.bus)
+        );
+        cpu.pc += 4;
+    }
+}
+
+#[test]
+fn breakpoint() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x93, 0x00, 0x50, 0x00, // addi x1, x0, 5
+        0x73, 0x00, 0x10, 0x00, // ebreak
+    ];
+
+    run_until_trap(&mut emu, data, Exception::Breakpoint(4 + DRAM_BASE));
+
+    assert_trap(&emu, 3, 4 + DRAM_BASE, 4 + DRAM_BASE);
+}
+
+#[test]
+fn load_address_misaligned() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0xb7, 0x00, 0x01, 0x00, // lui x1, 16
+        0x93, 0x80, 0x20, 0x10, // addi x1, x1, 258
+        0x2f, 0xa1, 0x00, 0x10, // lr.w x2, (x1)
+    ];
+
+    run_until_trap(
+        &mut emu,
+        data,
+        Exception::LoadAddressMisaligned(0x102 + DRAM_BASE),
+    );
+
+    assert_trap(&emu, 4, 8 + DRAM_BASE, 0x102 + DRAM_BASE);
+}
+
+#[test]
+fn load_access_fault() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x03, 0x21, 0x00, 0x10, // lw x2, 256(x0)
+    ];
+
+    run_until_trap(&mut emu, data, Exception::LoadAccessFault(0x100));
+
+    assert_trap(&emu, 5, DRAM_BASE, 0x100);
+}
+
+#[test]
+fn store_amo_address_misaligned() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0xb7, 0x00, 0x01, 0x00, // lui x1, 16
+        0x93, 0x80, 0x20, 0x10, // addi x1, x1, 258
+        0x2f, 0xa1, 0x00, 0x00, // amoadd.w x2, x0, (x1)
+    ];
+
+    run_until_trap(
+        &mut emu,
+        data,
+        Exception::StoreAMOAddressMisaligned(0x102 + DRAM_BASE),
+    );
+
+    assert_trap(&emu, 6, 8 + DRAM_BASE, 0x102 + DRAM_BASE);
+}
+
+#[test]
+fn store_amo_access_fault() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x23, 0x20, 0x00, 0x10, // sw x0, 256(x0)
+    ];
+
+    run_until_trap(&mut emu, data, Exception::StoreAMOAccessFault(0x100));
+
+    assert_trap(&emu, 7, DRAM_BASE, 0x100);
+}
+
+#[test]
+fn environment_call_from_u_mode() {
+    let mut emu = Emulator::new();
+    emu.harts[0].mode = Mode::User;
+
+    let data = vec![
+        0x93, 0x00, 0x50, 0x00, // addi x1, x0, 5
+        0x73, 0x00, 0x00, 0x00, // ecall
+    ];
+
+    run_until_trap(&mut emu, data, Exception::EnvironmentCallFromUMode);
+
+    assert_trap(&emu, 8, 4 + DRAM_BASE, 0);
+}
+
+#[test]
+fn environment_call_from_m_mode() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x93, 0x00, 0x50, 0x00, // addi x1, x0, 5
+        0x73, 0x00, 0x00, 0x00, // ecall
+    ];
+
+    run_until_trap(&mut emu, data, Exception::EnvironmentCallFromMMode);
+
+    assert_trap(&emu, 11, 4 + DRAM_BASE, 0);
+}
+
+#[test]
+fn page_faults() {
+    // The emulator has no MMU, so page faults are only raised by an embedder.
+    for (exception, mcause) in [
+        (Exception::InstructionPageFault(0x1234), 12),
+        (Exception::LoadPageFault(0x1234), 13),
+        (Exception::StoreAMOPageFault(0x1234), 15),
+    ] {
+        let mut emu = Emulator::new();
+        emu.initialize_pc(8 + DRAM_BASE);
+
+        exception.take_trap(&mut emu.harts[0]);
+
+        assert_trap(&emu, mcause, 8 + DRAM_BASE, 0x1234);
+    }
+}
+
+#[test]
+fn mret_jumps_to_mepc() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0xb7, 0x00, 0x01, 0x00, // lui x1, 16
+        0x93, 0x80, 0x00, 0x02, // addi x1, x1, 32
+        0x73, 0x90, 0x50, 0x30, // csrrw x0, mtvec, x1
+        0x73, 0x00, 0x00, 0x00, // ecall
+        0x93, 0x01, 0x70, 0x00, // addi x3, x0, 7
+        0x6f, 0x00, 0x00, 0x10, // jal x0, 256
+        0x13, 0x00, 0x00, 0x00, // addi x0, x0, 0
+        0x13, 0x00, 0x00, 0x00, // addi x0, x0, 0
+        // mtvec: skip the ecall.
+        0x73, 0x21, 0x10, 0x34, // csrrs x2, mepc, x0
+        0x13, 0x01, 0x41, 0x00, // addi x2, x2, 4
+        0x73, 0x10, 0x11, 0x34, // csrrw x0, mepc, x2
+        0x73, 0x00, 0x20, 0x30, // mret
+    ];
+    let len = data.len() as u32;
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    emu.test_start(DRAM_BASE, DRAM_BASE + len);
+
+    assert_eq!(16 + DRAM_BASE, emu.harts[0].xregs.read(2));
+    assert_eq!(7, emu.harts[0].xregs.read(3));
+    assert_eq!(0x114 + DRAM_BASE, emu.harts[0].pc);
 }
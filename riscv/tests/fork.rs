@@ -0,0 +1,45 @@
+use riscv::bus::DRAM_BASE;
+use riscv::cpu::WORD;
+use riscv::emulator::Emulator;
+
+#[test]
+fn copy_on_write() {
+    let mut emu = Emulator::new();
+    let data = vec![
+        0xb7, 0x22, 0x01, 0x00, // lui x5, 18
+        0x03, 0xa3, 0x02, 0x00, // lw x6, 0(x5)
+        0x13, 0x03, 0x13, 0x00, // addi x6, x6, 1
+        0x23, 0xa0, 0x62, 0x00, // sw x6, 0(x5)
+    ];
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    emu.bus.write(0x12000, 41, WORD).unwrap();
+    emu.step().unwrap();
+
+    let mut fork = emu.fork();
+    assert_eq!(DRAM_BASE + 4, fork.harts[0].pc);
+    assert_eq!(0x12000, fork.harts[0].xregs.read(5));
+    assert_eq!(2, emu.bus.dram().shared_pages());
+    assert_eq!(2, fork.bus.dram().shared_pages());
+
+    for _ in 0..3 {
+        fork.step().unwrap();
+    }
+    assert_eq!(42, fork.bus.read(0x12000, WORD).unwrap());
+    assert_eq!(41, emu.bus.read(0x12000, WORD).unwrap());
+    assert_eq!(DRAM_BASE + 4, emu.harts[0].pc);
+    // Only the written page was copied.
+    assert_eq!(1, emu.bus.dram().shared_pages());
+    assert_eq!(1, fork.bus.dram().shared_pages());
+    assert_eq!(2, fork.bus.dram().allocated_pages());
+
+    for _ in 0..3 {
+        emu.step().unwrap();
+    }
+    assert_eq!(42, emu.bus.read(0x12000, WORD).unwrap());
+    assert_eq!(42, fork.bus.read(0x12000, WORD).unwrap());
+
+    // Dropping the fork leaves the pages to the original.
+    drop(fork);
+    assert_eq!(0, emu.bus.dram().shared_pages());
+}
@@ -0,0 +1,106 @@
+use riscv::bus::DRAM_BASE;
+use riscv::csr::*;
+use riscv::emulator::Emulator;
+use riscv::framebuffer::*;
+
+#[test]
+fn flush_dirty_rect() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x37, 0x03, 0x00, 0x04, // lui x6, 16384
+        0xb7, 0x03, 0x01, 0x00, // lui x7, 16
+        0x93, 0x83, 0x03, 0x80, // addi x7, x7, -2048
+        0x23, 0x11, 0x73, 0x00, // sh x7, 2(x6)
+        0x23, 0x15, 0x73, 0x00, // sh x7, 10(x6)
+        0x37, 0x04, 0x00, 0x03, // lui x8, 12288
+        0x93, 0x04, 0x10, 0x00, // addi x9, x0, 1
+        0x23, 0x24, 0x94, 0x00, // sw x9, 8(x8)
+        0x23, 0x26, 0x04, 0x00, // sw x0, 12(x8)
+        0x23, 0x28, 0x94, 0x00, // sw x9, 16(x8)
+        0x93, 0x04, 0x20, 0x00, // addi x9, x0, 2
+        0x23, 0x2a, 0x94, 0x00, // sw x9, 20(x8)
+        0x93, 0x04, 0x10, 0x00, // addi x9, x0, 1
+        0x23, 0x2c, 0x94, 0x00, // sw x9, 24(x8)
+        0x03, 0x25, 0xc4, 0x01, // lw x10, 28(x8)
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    emu.bus.framebuffer = Some(Framebuffer::new(4, 3));
+
+    for _ in 0..15 {
+        emu.step().unwrap();
+    }
+
+    assert_eq!(FRAMEBUFFER_STATUS_FLUSH, emu.harts[0].xregs.read(10));
+    let framebuffer = emu.bus.framebuffer.as_mut().unwrap();
+    assert_eq!(
+        Some(Rect {
+            x: 1,
+            y: 0,
+            width: 1,
+            height: 2
+        }),
+        framebuffer.take_flush()
+    );
+    assert_eq!(None, framebuffer.take_flush());
+    assert_eq!(0xf800, framebuffer.pixel(1, 0));
+    assert_eq!(0xf800, framebuffer.pixel(1, 1));
+    assert_eq!(0, framebuffer.pixel(0, 0));
+    // The pixel buffer ends after the last pixel.
+    assert!(emu.bus.read(FRAMEBUFFER_VRAM + 24, 16).is_err());
+}
+
+#[test]
+fn vsync_interrupt() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x37, 0x04, 0x00, 0x03, // lui x8, 12288
+        0x93, 0x04, 0x20, 0x00, // addi x9, x0, 2
+        0x23, 0x2c, 0x94, 0x00, // sw x9, 24(x8)
+        0x37, 0x03, 0x01, 0x00, // lui x6, 16
+        0x13, 0x03, 0xc3, 0x02, // addi x6, x6, 44
+        0x73, 0x10, 0x53, 0x30, // csrrw x0, mtvec, x6
+        0xb7, 0x13, 0x00, 0x00, // lui x7, 1
+        0x93, 0x83, 0x03, 0x80, // addi x7, x7, -2048
+        0x73, 0xa0, 0x43, 0x30, // csrrs x0, mie, x7
+        0x73, 0x60, 0x04, 0x30, // csrrsi x0, mstatus, 8
+        0x73, 0x00, 0x50, 0x10, // wfi
+        // trap handler
+        0x73, 0x25, 0x20, 0x34, // csrrs x10, mcause, x0
+        0x83, 0x25, 0x04, 0x02, // lw x11, 32(x8)
+        0x23, 0x2e, 0x94, 0x00, // sw x9, 28(x8)
+        0x03, 0x26, 0xc4, 0x01, // lw x12, 28(x8)
+        0x6f, 0x00, 0x00, 0x00, // jal x0, 0
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    let mut framebuffer = Framebuffer::new(4, 3);
+    framebuffer.set_vsync_period(20);
+    emu.bus.framebuffer = Some(framebuffer);
+
+    for _ in 0..15 {
+        emu.step().unwrap();
+    }
+    assert!(emu.harts[0].idle);
+
+    for _ in 0..15 {
+        emu.step().unwrap();
+    }
+
+    let cpu = &emu.harts[0];
+    assert_eq!(0x8000000b, cpu.xregs.read(10));
+    assert_eq!(1, cpu.xregs.read(11));
+    assert_eq!(0, cpu.xregs.read(12));
+    assert_eq!(0, cpu.state.read(MIP) & MEIP_BIT);
+}
+
+#[test]
+#[should_panic(expected = "pixel buffer doesn't fit")]
+fn oversized() {
+    // 65536 x 65536 pixels overflow the size computation.
+    Framebuffer::new(0x10000, 0x10000);
+}
@@ -1,3 +1,6 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
 use riscv::bus::DRAM_BASE;
 use riscv::cpu::REGISTERS_COUNT;
 use riscv::dram::DRAM_SIZE;
@@ -23,13 +26,39 @@ pub fn run(emu: &mut Emulator, data: Vec<u8>, expected_xregs: &[u32; 32]) {
     let len = data.len() as u32;
 
     emu.is_debug = true;
+    enable_coverage(emu);
 
     emu.initialize_dram(data);
     emu.initialize_pc(DRAM_BASE);
 
     emu.test_start(DRAM_BASE, DRAM_BASE + len);
+    save_coverage(emu);
 
     for (i, e) in expected_xregs.iter().enumerate() {
-        assert_eq!(*e, emu.cpu.xregs.read(i as u32), "fails at {}", i);
+        assert_eq!(*e, emu.harts[0].xregs.read(i as u32), "fails at {}", i);
     }
 }
+
+/// The environment variable that names the file to append the coverage of the tests to.
+const COVERAGE_VAR: &str = "RISCV_COVERAGE";
+
+/// Enable coverage on `emu` if the tests record their coverage.
+fn enable_coverage(emu: &mut Emulator) {
+    if std::env::var_os(COVERAGE_VAR).is_some() {
+        emu.enable_coverage();
+    }
+}
+
+/// Append the coverage of `emu` to the file the tests record their coverage to.
+fn save_coverage(emu: &Emulator) {
+    let (Some(path), Some(coverage)) = (std::env::var_os(COVERAGE_VAR), emu.coverage()) else {
+        return;
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .unwrap_or_else(|e| panic!("failed to open {:?}: {}", path, e));
+    file.write_all(coverage.encode().as_bytes())
+        .unwrap_or_else(|e| panic!("failed to write {:?}: {}", path, e));
+}
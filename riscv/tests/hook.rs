@@ -0,0 +1,127 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use riscv::bus::DRAM_BASE;
+use riscv::cpu::{AccessType, WORD};
+use riscv::csr::*;
+use riscv::emulator::Emulator;
+use riscv::hook::*;
+
+const PROGRAM: [u8; 20] = [
+    0xb7, 0x12, 0x01, 0x00, // lui x5, 17
+    0x13, 0x03, 0x50, 0x00, // addi x6, x0, 5
+    0x23, 0xa0, 0x62, 0x00, // sw x6, 0(x5)
+    0x83, 0xa3, 0x02, 0x00, // lw x7, 0(x5)
+    0x03, 0xa4, 0x42, 0x00, // lw x8, 4(x5)
+];
+
+/// Records every access it sees.
+struct Log(Rc<RefCell<Vec<Access>>>);
+
+impl MemoryHook for Log {
+    fn read(&mut self, access: &Access, value: u32) -> Option<u32> {
+        self.0.borrow_mut().push(*access);
+        Some(value)
+    }
+
+    fn write(&mut self, access: &Access, value: u32) -> Option<u32> {
+        self.0.borrow_mut().push(*access);
+        Some(value)
+    }
+}
+
+/// Doubles stored values and increments loaded values.
+struct Rewrite;
+
+impl MemoryHook for Rewrite {
+    fn read(&mut self, _access: &Access, value: u32) -> Option<u32> {
+        Some(value + 1)
+    }
+
+    fn write(&mut self, _access: &Access, value: u32) -> Option<u32> {
+        Some(value * 2)
+    }
+}
+
+/// Faults every load and fetch.
+struct Fault;
+
+impl MemoryHook for Fault {
+    fn read(&mut self, _access: &Access, _value: u32) -> Option<u32> {
+        None
+    }
+}
+
+fn access(access_type: AccessType, addr: u32) -> Access {
+    Access {
+        hartid: 0,
+        access_type,
+        addr,
+        size: WORD,
+    }
+}
+
+#[test]
+fn observe_rewrite_and_fault() {
+    let mut emu = Emulator::new();
+    emu.initialize_dram(PROGRAM.to_vec());
+    emu.initialize_pc(DRAM_BASE);
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    emu.bus
+        .add_hook(DRAM_BASE..=0x1ffff, Box::new(Log(log.clone())));
+    emu.bus.add_hook(0x11000..=0x11003, Box::new(Rewrite));
+    emu.bus.add_hook(0x11004..=0x11007, Box::new(Fault));
+
+    for _ in 0..5 {
+        emu.step().unwrap();
+    }
+
+    let cpu = &emu.harts[0];
+    assert_eq!(11, cpu.xregs.read(7));
+    assert_eq!(0, cpu.xregs.read(8));
+    assert_eq!(5, cpu.state.read(MCAUSE));
+    assert_eq!(0x11004, cpu.state.read(MTVAL));
+    // The embedder's accesses bypass the hooks.
+    assert_eq!(10, emu.bus.read(0x11000, WORD).unwrap());
+
+    use AccessType::*;
+    assert_eq!(
+        vec![
+            access(Instruction, DRAM_BASE),
+            access(Instruction, DRAM_BASE + 4),
+            access(Instruction, DRAM_BASE + 8),
+            access(Store, 0x11000),
+            access(Instruction, DRAM_BASE + 12),
+            access(Load, 0x11000),
+            access(Instruction, DRAM_BASE + 16),
+            access(Load, 0x11004),
+        ],
+        *log.borrow()
+    );
+}
+
+#[test]
+fn fetch_fault() {
+    let mut emu = Emulator::new();
+    emu.initialize_dram(PROGRAM.to_vec());
+    emu.initialize_pc(DRAM_BASE);
+
+    // An access that overlaps the range is hooked.
+    let id = emu
+        .bus
+        .add_hook(DRAM_BASE + 7..=DRAM_BASE + 7, Box::new(Fault));
+    emu.step().unwrap();
+    // The instruction access fault is taken like the other access faults.
+    emu.step().unwrap();
+    assert_eq!(1, emu.harts[0].state.read(MCAUSE));
+    assert_eq!(DRAM_BASE + 4, emu.harts[0].state.read(MEPC));
+    assert_eq!(DRAM_BASE + 4, emu.harts[0].state.read(MTVAL));
+    assert_eq!(0, emu.harts[0].xregs.read(6));
+
+    assert!(emu.bus.remove_hook(id).is_some());
+    assert!(emu.bus.remove_hook(id).is_none());
+    emu.initialize_pc(DRAM_BASE + 4);
+    emu.step().unwrap();
+    assert_eq!(5, emu.harts[0].xregs.read(6));
+}
@@ -0,0 +1,62 @@
+use riscv::bus::DRAM_BASE;
+use riscv::clint::CLINT_MTIME;
+use riscv::cpu::WORD;
+use riscv::csr::*;
+use riscv::emulator::{Emulator, RunStatus};
+
+#[test]
+fn fast_forward_to_timer() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x37, 0x44, 0x00, 0x02, // lui x8, 8196
+        0x93, 0x04, 0x80, 0x3e, // addi x9, x0, 1000
+        0x23, 0x20, 0x94, 0x00, // sw x9, 0(x8)
+        0x23, 0x22, 0x04, 0x00, // sw x0, 4(x8)
+        0x37, 0x03, 0x01, 0x00, // lui x6, 16
+        0x13, 0x03, 0xc3, 0x02, // addi x6, x6, 44
+        0x73, 0x10, 0x53, 0x30, // csrrw x0, mtvec, x6
+        0x93, 0x03, 0x00, 0x08, // addi x7, x0, 128
+        0x73, 0xa0, 0x43, 0x30, // csrrs x0, mie, x7
+        0x73, 0x60, 0x04, 0x30, // csrrsi x0, mstatus, 8
+        0x73, 0x00, 0x50, 0x10, // wfi
+        // trap handler
+        0x73, 0x25, 0x20, 0x34, // csrrs x10, mcause, x0
+        0x6f, 0x00, 0x00, 0x00, // jal x0, 0
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    assert_eq!(
+        Ok(RunStatus::Idle {
+            next_event: Some(989)
+        }),
+        emu.run(100)
+    );
+    assert_eq!(11, emu.bus.read(CLINT_MTIME, WORD).unwrap());
+
+    emu.fast_forward(989);
+    assert_eq!(1000, emu.bus.read(CLINT_MTIME, WORD).unwrap());
+    assert_eq!(1000, emu.harts[0].state.read(MCYCLE));
+
+    assert_eq!(Ok(RunStatus::Paused), emu.run(2));
+    assert_eq!(0x80000007, emu.harts[0].xregs.read(10));
+}
+
+#[test]
+fn idle_without_event() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x73, 0x00, 0x50, 0x10, // wfi
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    assert_eq!(Ok(RunStatus::Idle { next_event: None }), emu.run(100));
+    // Nothing can wake the hart up, so `start` returns.
+    emu.start();
+    assert!(emu.harts[0].idle);
+}
@@ -0,0 +1,88 @@
+use riscv::bus::DRAM_BASE;
+use riscv::cpu::WORD;
+use riscv::emulator::Emulator;
+use riscv::input::*;
+
+#[test]
+fn key_event_interrupt() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x37, 0x14, 0x00, 0x03, // lui x8, 12289
+        0x93, 0x04, 0x10, 0x00, // addi x9, x0, 1
+        0x23, 0x22, 0x94, 0x00, // sw x9, 4(x8)
+        0x37, 0x03, 0x01, 0x00, // lui x6, 16
+        0x13, 0x03, 0xc3, 0x02, // addi x6, x6, 44
+        0x73, 0x10, 0x53, 0x30, // csrrw x0, mtvec, x6
+        0xb7, 0x13, 0x00, 0x00, // lui x7, 1
+        0x93, 0x83, 0x03, 0x80, // addi x7, x7, -2048
+        0x73, 0xa0, 0x43, 0x30, // csrrs x0, mie, x7
+        0x73, 0x60, 0x04, 0x30, // csrrsi x0, mstatus, 8
+        0x73, 0x00, 0x50, 0x10, // wfi
+        // trap handler
+        0x03, 0x25, 0xc4, 0x00, // lw x10, 12(x8)
+        0x83, 0x25, 0x04, 0x01, // lw x11, 16(x8)
+        0x03, 0x26, 0x44, 0x01, // lw x12, 20(x8)
+        0x23, 0x2c, 0x04, 0x00, // sw x0, 24(x8)
+        0x83, 0x26, 0x84, 0x00, // lw x13, 8(x8)
+        0x6f, 0x00, 0x00, 0x00, // jal x0, 0
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    emu.bus.input = Some(Input::new());
+
+    for _ in 0..15 {
+        emu.step().unwrap();
+    }
+    assert!(emu.harts[0].idle);
+
+    let input = emu.bus.input.as_mut().unwrap();
+    assert!(input.push(InputEvent::KeyDown {
+        keycode: 0x41,
+        modifiers: INPUT_MODIFIER_SHIFT,
+    }));
+    assert!(input.push(InputEvent::Scroll { dx: -1, dy: 2 }));
+
+    for _ in 0..8 {
+        emu.step().unwrap();
+    }
+
+    let cpu = &emu.harts[0];
+    assert_eq!(0x8000000b, cpu.state.read(riscv::csr::MCAUSE));
+    assert_eq!(INPUT_KIND_KEY_DOWN, cpu.xregs.read(10));
+    assert_eq!(0x41, cpu.xregs.read(11));
+    assert_eq!(INPUT_MODIFIER_SHIFT, cpu.xregs.read(12));
+    assert_eq!(1, cpu.xregs.read(13));
+    assert_eq!(
+        INPUT_KIND_SCROLL,
+        emu.bus.read(INPUT_EVENT_KIND, WORD).unwrap()
+    );
+    assert_eq!(-1, emu.bus.read(INPUT_EVENT_DATA0, WORD).unwrap() as i32);
+}
+
+#[test]
+fn fifo_overflow() {
+    let mut emu = Emulator::new();
+    let mut input = Input::new();
+
+    for x in 0..INPUT_FIFO_DEPTH as u32 {
+        assert!(input.push(InputEvent::PointerMove { x, y: 0 }));
+    }
+    assert!(!input.push(InputEvent::PointerUp { x: 0, y: 0 }));
+    emu.bus.input = Some(input);
+
+    assert_eq!(
+        INPUT_STATUS_READY | INPUT_STATUS_OVERFLOW,
+        emu.bus.read(INPUT_STATUS, WORD).unwrap()
+    );
+    emu.bus
+        .write(INPUT_STATUS, INPUT_STATUS_OVERFLOW, WORD)
+        .unwrap();
+    assert_eq!(
+        INPUT_STATUS_READY,
+        emu.bus.read(INPUT_STATUS, WORD).unwrap()
+    );
+    // Interrupts are disabled.
+    assert!(!emu.bus.is_external_interrupting(0));
+}
@@ -0,0 +1,69 @@
+use riscv::cpu::{Cpu, Mode};
+use riscv::csr::*;
+use riscv::exception::Exception;
+use riscv::interrupt::Interrupt;
+
+#[test]
+fn priority() {
+    let mut cpu = Cpu::new();
+    let all = MEIP_BIT | MSIP_BIT | MTIP_BIT;
+    cpu.state.write(MIE, u32::MAX);
+    // S-mode isn't implemented, so the supervisor-level interrupts can't be enabled.
+    assert_eq!(all, cpu.state.read(MIE));
+    cpu.state.write_mstatus(MSTATUS_MIE, 1);
+
+    let mut mip = all;
+    for interrupt in [
+        Interrupt::MachineExternalInterrupt,
+        Interrupt::MachineSoftwareInterrupt,
+        Interrupt::MachineTimerInterrupt,
+    ] {
+        cpu.state.set_mip(mip);
+        assert_eq!(Some(interrupt), cpu.check_pending_interrupt());
+        // The pending bit is owned by the source.
+        assert_eq!(mip, cpu.state.read(MIP));
+        mip &= !interrupt.pending_bit();
+    }
+    cpu.state.set_mip(mip);
+    assert_eq!(None, cpu.check_pending_interrupt());
+}
+
+#[test]
+fn global_enable() {
+    let mut cpu = Cpu::new();
+    cpu.state.write(MIE, MSIP_BIT);
+    cpu.state.set_mip(MSIP_BIT);
+
+    // M-mode interrupts are disabled in M-mode while MIE is clear.
+    assert_eq!(None, cpu.check_pending_interrupt());
+
+    // They are always enabled in U-mode.
+    cpu.mode = Mode::User;
+    assert_eq!(
+        Some(Interrupt::MachineSoftwareInterrupt),
+        cpu.check_pending_interrupt()
+    );
+
+    // S-mode isn't implemented, so nothing can be delegated.
+    cpu.state.write(MIDELEG, u32::MAX);
+    assert_eq!(0, cpu.state.read(MIDELEG));
+    assert_eq!(
+        Some(Interrupt::MachineSoftwareInterrupt),
+        cpu.check_pending_interrupt()
+    );
+}
+
+#[test]
+fn vectored_mtvec() {
+    let mut cpu = Cpu::new();
+    cpu.state.write(MTVEC, 0x1000 | 1);
+
+    Interrupt::MachineTimerInterrupt.take_trap(&mut cpu);
+    assert_eq!(0x1000 + 4 * 7, cpu.pc);
+    assert_eq!(0x80000007, cpu.state.read(MCAUSE));
+
+    // Exceptions always jump to the base address.
+    Exception::Breakpoint(cpu.pc).take_trap(&mut cpu);
+    assert_eq!(0x1000, cpu.pc);
+    assert_eq!(3, cpu.state.read(MCAUSE));
+}
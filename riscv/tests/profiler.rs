@@ -0,0 +1,80 @@
+use std::fs;
+use std::path::Path;
+
+use riscv::bus::DRAM_BASE;
+use riscv::emulator::Emulator;
+use riscv::profiler::{Profiler, Symbols};
+
+#[test]
+fn folded_stacks() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        // main
+        0xef, 0x00, 0x00, 0x01, // jal x1, 16
+        0xef, 0x00, 0xc0, 0x00, // jal x1, 12
+        0x6f, 0x00, 0x00, 0x00, // jal x0, 0
+        0x13, 0x00, 0x00, 0x00, // addi x0, x0, 0
+        // foo
+        0x13, 0x01, 0xc1, 0xff, // addi x2, x2, -4
+        0x23, 0x20, 0x11, 0x00, // sw x1, 0(x2)
+        0xef, 0x00, 0x00, 0x01, // jal x1, 16
+        0x83, 0x20, 0x01, 0x00, // lw x1, 0(x2)
+        0x13, 0x01, 0x41, 0x00, // addi x2, x2, 4
+        0x67, 0x80, 0x00, 0x00, // jalr x0, 0(x1)
+        // bar
+        0x13, 0x03, 0x13, 0x00, // addi x6, x6, 1
+        0x67, 0x80, 0x00, 0x00, // jalr x0, 0(x1)
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    emu.profiler = Some(Profiler::new());
+
+    // Run until main reaches the infinite loop.
+    while emu.harts[0].pc != 8 + DRAM_BASE {
+        emu.step().unwrap();
+    }
+
+    let mut symbols = Symbols::new();
+    symbols.insert("main", DRAM_BASE, 16);
+    symbols.insert("foo", 16 + DRAM_BASE, 24);
+    symbols.insert("bar", 40 + DRAM_BASE, 8);
+
+    let profiler = emu.profiler.as_ref().unwrap();
+    let mut folded = String::new();
+    profiler.write_folded(&symbols, &mut folded).unwrap();
+    assert_eq!("main 2\nmain;foo 12\nmain;foo;bar 4\n", folded);
+
+    assert_eq!(Some(&2), profiler.pc_counts().get(&(16 + DRAM_BASE)));
+    let functions = profiler.function_counts(&symbols);
+    assert_eq!(Some(&2), functions.get("main"));
+    assert_eq!(Some(&12), functions.get("foo"));
+    assert_eq!(Some(&4), functions.get("bar"));
+}
+
+#[test]
+fn unknown_addresses() {
+    let mut profiler = Profiler::new();
+
+    profiler.record(0x100, 0x00000013, 0x104); // addi x0, x0, 0
+
+    let mut folded = String::new();
+    profiler.write_folded(&Symbols::new(), &mut folded).unwrap();
+    assert_eq!("0x00000100 1\n", folded);
+}
+
+#[test]
+fn symbols_from_elf() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/compliance/riscv-tests/bin/rv32ui-p-simple");
+    let data = fs::read(path).unwrap();
+
+    let symbols = Symbols::from_elf(&data).unwrap();
+
+    let start = symbols.lookup(DRAM_BASE).unwrap();
+    assert_eq!("_start", start.name);
+    assert_eq!(DRAM_BASE, start.addr);
+    // Data objects aren't resolved.
+    assert!(symbols.lookup(0).is_none());
+}
@@ -0,0 +1,287 @@
+use riscv::bus::DRAM_BASE;
+use riscv::csr::*;
+use riscv::custom::{CustomContext, CustomExtension, CustomInstruction};
+use riscv::emulator::Emulator;
+use riscv::exception::Exception;
+use riscv::input::{Input, InputEvent};
+use riscv::replay::*;
+use riscv::semihosting::{Host, Stream};
+
+/// A host whose clock advances by 7 on every read.
+struct Clock(u32);
+
+impl Host for Clock {
+    fn write(&mut self, _stream: Stream, _data: &[u8]) {}
+
+    fn read_char(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn clock(&mut self) -> Option<u32> {
+        self.0 += 7;
+        Some(self.0)
+    }
+}
+
+/// Enable the input interrupt, then sum the keycodes of the input events in x11 and the host's
+/// clock in x13 in the handler, and read mcycle and time.
+fn program() -> Vec<u8> {
+    vec![
+        0xb7, 0x12, 0x00, 0x03, // lui x5, 12289
+        0x13, 0x03, 0x10, 0x00, // addi x6, x0, 1
+        0x23, 0xa2, 0x62, 0x00, // sw x6, 4(x5)
+        0x37, 0x03, 0x01, 0x00, // lui x6, 16
+        0x13, 0x03, 0x43, 0x03, // addi x6, x6, 52
+        0x73, 0x10, 0x53, 0x30, // csrrw x0, mtvec, x6
+        0xb7, 0x13, 0x00, 0x00, // lui x7, 1
+        0x93, 0x83, 0x03, 0x80, // addi x7, x7, -2048
+        0x73, 0xa0, 0x43, 0x30, // csrrs x0, mie, x7
+        0x73, 0x60, 0x04, 0x30, // csrrsi x0, mstatus, 8
+        // loop
+        0x73, 0x2a, 0x00, 0xb0, // csrrs x20, mcycle, x0
+        0x73, 0x00, 0x50, 0x10, // wfi
+        0x6f, 0xf0, 0x9f, 0xff, // jal x0, -8
+        // trap handler
+        0x03, 0xa7, 0x02, 0x01, // lw x14, 16(x5)
+        0x23, 0xac, 0x02, 0x00, // sw x0, 24(x5)
+        0xb3, 0x85, 0xe5, 0x00, // add x11, x11, x14
+        0x73, 0x26, 0x10, 0xc0, // csrrs x12, time, x0
+        0x13, 0x05, 0x00, 0x01, // addi x10, x0, 16
+        0x13, 0x10, 0xf0, 0x01, // slli x0, x0, 31
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x13, 0x50, 0x70, 0x40, // srai x0, x0, 7
+        0xb3, 0x86, 0xa6, 0x00, // add x13, x13, x10
+        0x73, 0x00, 0x20, 0x30, // mret
+    ]
+}
+
+#[test]
+fn record_and_replay() {
+    let mut emu = Emulator::new();
+    emu.initialize_dram(program());
+    emu.initialize_pc(DRAM_BASE);
+    emu.bus.input = Some(Input::new());
+
+    // The replay starts from the state the recording starts from.
+    let mut replay = emu.fork();
+
+    emu.semihosting = Some(Box::new(Clock(100)));
+    emu.start_recording();
+    for step in 0..300 {
+        if step == 50 || step == 200 {
+            let keycode = step / 10;
+            let event = InputEvent::KeyDown {
+                keycode,
+                modifiers: 0,
+            };
+            emu.bus.input.as_mut().unwrap().push(event);
+        }
+        // The embedder sleeps for a while when the hart waits.
+        if emu.is_idle() {
+            emu.fast_forward(1000);
+        }
+        emu.step().unwrap();
+    }
+    let log = emu.stop_journal().unwrap();
+    let recorded = &emu.harts[0];
+    assert_eq!(25, recorded.xregs.read(11));
+    assert_eq!(107 + 114, recorded.xregs.read(13));
+    assert!(log.events().contains(&Event::Mmio(5)));
+    assert!(log.events().contains(&Event::Host(Some(114))));
+    // The interrupt wakes the hart up from the WFI after the first 12 instructions.
+    assert_eq!(
+        Event::Interrupt {
+            hart: 0,
+            instret: 12,
+            cause: 11
+        },
+        log.events()[1]
+    );
+
+    // The log survives encoding.
+    let log = Log::decode(&log.encode()).unwrap();
+
+    // The replay has neither the input events, the fast-forwards nor the same host clock.
+    replay.semihosting = Some(Box::new(Clock(5000)));
+    replay.start_replay(log);
+    let instret = recorded.state.read(MINSTRET);
+    let mut steps = 0;
+    while replay.harts[0].state.read(MINSTRET) < instret {
+        replay.step().unwrap();
+        steps += 1;
+        assert!(steps < 1000, "the replay doesn't make progress");
+    }
+    assert!(!replay.is_replaying());
+    assert!(!replay.has_diverged());
+
+    let replayed = &replay.harts[0];
+    assert_eq!(recorded.pc, replayed.pc);
+    for i in 0..32 {
+        assert_eq!(recorded.xregs.read(i), replayed.xregs.read(i), "x{}", i);
+    }
+    // The counters only match where the guest read them.
+    assert!(recorded.state.read(MCYCLE) > replayed.state.read(MCYCLE));
+}
+
+#[test]
+fn wake() {
+    let mut emu = Emulator::new();
+    let data = vec![
+        0xb7, 0x12, 0x00, 0x03, // lui x5, 12289
+        0x13, 0x03, 0x10, 0x00, // addi x6, x0, 1
+        0x23, 0xa2, 0x62, 0x00, // sw x6, 4(x5)
+        0xb7, 0x13, 0x00, 0x00, // lui x7, 1
+        0x93, 0x83, 0x03, 0x80, // addi x7, x7, -2048
+        0x73, 0xa0, 0x43, 0x30, // csrrs x0, mie, x7
+        0x73, 0x00, 0x50, 0x10, // wfi
+        0x73, 0x2a, 0x00, 0xb0, // csrrs x20, mcycle, x0
+        0x6f, 0x00, 0x00, 0x00, // jal x0, 0
+    ];
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    emu.bus.input = Some(Input::new());
+    let mut replay = emu.fork();
+
+    // Interrupts are disabled, so the pending interrupt only wakes the hart up.
+    emu.start_recording();
+    for step in 0..20 {
+        if step == 10 {
+            let event = InputEvent::KeyDown {
+                keycode: 1,
+                modifiers: 0,
+            };
+            emu.bus.input.as_mut().unwrap().push(event);
+        }
+        emu.step().unwrap();
+    }
+    let log = emu.stop_journal().unwrap();
+    assert_eq!(
+        &[
+            Event::Wake {
+                hart: 0,
+                instret: 7
+            },
+            Event::Csr(11)
+        ],
+        log.events()
+    );
+
+    replay.start_replay(log);
+    for _ in 0..20 {
+        replay.step().unwrap();
+    }
+    assert_eq!(DRAM_BASE + 32, replay.harts[0].pc);
+    assert_eq!(11, replay.harts[0].xregs.read(20));
+    assert!(!replay.has_diverged());
+}
+
+/// A custom instruction that writes the next value of a counter to `x[rd]` and to the word at
+/// `x[rs1]`, or raises an exception if `trap` is set.
+struct Counter {
+    next: u32,
+    trap: bool,
+}
+
+impl CustomExtension for Counter {
+    fn execute(
+        &mut self,
+        inst: &CustomInstruction,
+        context: &mut CustomContext,
+    ) -> Result<(), Exception> {
+        if self.trap {
+            return Err(Exception::IllegalInstruction(inst.inst));
+        }
+        self.next += 1;
+        context.write_register(inst.rd, self.next)?;
+        let addr = context.read_register(inst.rs1)?;
+        context.store(addr, self.next, 32)
+    }
+}
+
+/// Run the counter instruction twice with a recording, and return the log and a fork of the
+/// emulator from before the recording.
+fn record_counter() -> (Log, Emulator) {
+    let mut emu = Emulator::new();
+    let data = vec![
+        0x8b, 0x02, 0x73, 0x00, // .insn r 0x0b, 0, 0, x5, x6, x7
+        0x8b, 0x02, 0x73, 0x00, // .insn r 0x0b, 0, 0, x5, x6, x7
+        0x6f, 0x00, 0x00, 0x00, // jal x0, 0
+    ];
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+    emu.harts[0].xregs.write(6, DRAM_BASE + 0x100);
+    let replay = emu.fork();
+
+    emu.bus.custom_extension = Some(Box::new(Counter {
+        next: 0,
+        trap: false,
+    }));
+    emu.start_recording();
+    for _ in 0..3 {
+        emu.step().unwrap();
+    }
+    let log = emu.stop_journal().unwrap();
+    assert_eq!(2, emu.harts[0].xregs.read(5));
+    (log, replay)
+}
+
+#[test]
+fn custom_extension() {
+    let (log, mut replay) = record_counter();
+    assert_eq!(
+        &[
+            Event::Custom(1),
+            Event::Custom(1),
+            Event::CustomResult(None),
+            Event::Custom(2),
+            Event::Custom(2),
+            Event::CustomResult(None),
+        ],
+        log.events()
+    );
+    let log = Log::decode(&log.encode()).unwrap();
+
+    // The extension of the replay counts from elsewhere, but the guest sees the recorded values.
+    replay.bus.custom_extension = Some(Box::new(Counter {
+        next: 100,
+        trap: false,
+    }));
+    replay.start_replay(log);
+    for _ in 0..3 {
+        replay.step().unwrap();
+    }
+    assert_eq!(2, replay.harts[0].xregs.read(5));
+    assert_eq!(2, replay.bus.read(DRAM_BASE + 0x100, 32).unwrap());
+    assert!(!replay.has_diverged());
+
+    // An extension that raises an exception the recording didn't diverges the replay.
+    let (log, mut replay) = record_counter();
+    replay.bus.custom_extension = Some(Box::new(Counter {
+        next: 0,
+        trap: true,
+    }));
+    replay.start_replay(log);
+    replay.step().unwrap();
+    assert!(replay.has_diverged());
+    assert_eq!(2, replay.harts[0].state.read(MCAUSE));
+}
+
+#[test]
+fn diverge() {
+    let mut emu = Emulator::new();
+    emu.initialize_dram(program());
+    emu.initialize_pc(DRAM_BASE);
+    emu.bus.input = Some(Input::new());
+
+    // The guest reads mcycle where the log has a host answer.
+    emu.start_replay(Log::decode(&[5, 42]).unwrap());
+    for _ in 0..11 {
+        emu.step().unwrap();
+    }
+    assert!(emu.has_diverged());
+    assert!(!emu.is_replaying());
+    assert_eq!(11, emu.harts[0].xregs.read(20));
+
+    assert_eq!(None, Log::decode(&[5, 0x80]));
+    assert_eq!(None, Log::decode(&[9]));
+}
@@ -5,77 +5,79 @@ use riscv::emulator::Emulator;
 
 #[test]
 fn lb_rd_offset_rs1() {
-    let mut _emu = Emulator::new();
+    let mut emu = Emulator::new();
 
-    let _data = vec![
+    let data = vec![
+        0xb7, 0x02, 0x01, 0x00, // lui x5, 16
         0x13, 0x08, 0x50, 0x00, // addi x16, x0, 5
         0x93, 0x08, 0x30, 0x00, // addi x17, x0, 3
-        0x03, 0x09, 0x40, 0x00, // lb x18, 4(x0)
+        0x03, 0x89, 0x82, 0x00, // lb x18, 8(x5)
     ];
-    let _expected_xregs = helper::create_xregs(vec![(16, 5), (17, 3), (18, -109i64 as u32)]);
+    let expected_xregs =
+        helper::create_xregs(vec![(5, DRAM_BASE), (16, 5), (17, 3), (18, -109i64 as u32)]);
 
-    // TODO: fix LoadAccessFault
-    //helper::run(&mut emu, data, &expected_xregs);
+    helper::run(&mut emu, data, &expected_xregs);
 }
 
 #[test]
 fn lh_rd_offset_rs1() {
-    let mut _emu = Emulator::new();
+    let mut emu = Emulator::new();
 
-    let _data = vec![
+    let data = vec![
+        0xb7, 0x02, 0x01, 0x00, // lui x5, 16
         0x13, 0x08, 0x50, 0x00, // addi x16, x0, 5
         0x93, 0x08, 0x30, 0x00, // addi x17, x0, 3
-        0x03, 0x19, 0x40, 0x00, // lh x18, 4(x0)
+        0x03, 0x99, 0x82, 0x00, // lh x18, 8(x5)
     ];
-    let _expected_xregs = helper::create_xregs(vec![(16, 5), (17, 3), (18, 0x0893)]);
+    let expected_xregs = helper::create_xregs(vec![(5, DRAM_BASE), (16, 5), (17, 3), (18, 0x0893)]);
 
-    // TODO: fix LoadAccessFault
-    //helper::run(&mut emu, data, &expected_xregs);
+    helper::run(&mut emu, data, &expected_xregs);
 }
 
 #[test]
 fn lw_rd_offset_rs1() {
-    let mut _emu = Emulator::new();
+    let mut emu = Emulator::new();
 
-    let _data = vec![
+    let data = vec![
+        0xb7, 0x02, 0x01, 0x00, // lui x5, 16
         0x13, 0x08, 0x50, 0x00, // addi x16, x0, 5
         0x93, 0x08, 0x30, 0x00, // addi x17, x0, 3
-        0x03, 0x29, 0x40, 0x00, // lw x18, 4(x0)
+        0x03, 0xa9, 0x82, 0x00, // lw x18, 8(x5)
     ];
-    let _expected_xregs = helper::create_xregs(vec![(16, 5), (17, 3), (18, 0x300893)]);
+    let expected_xregs =
+        helper::create_xregs(vec![(5, DRAM_BASE), (16, 5), (17, 3), (18, 0x300893)]);
 
-    // TODO: fix LoadAccessFault
-    //helper::run(&mut emu, data, &expected_xregs);
+    helper::run(&mut emu, data, &expected_xregs);
 }
 
 #[test]
 fn lbu_rd_offset_rs1() {
-    let mut _emu = Emulator::new();
+    let mut emu = Emulator::new();
 
-    let _data = vec![
+    let data = vec![
+        0xb7, 0x02, 0x01, 0x00, // lui x5, 16
         0x13, 0x08, 0x50, 0x00, // addi x16, x0, 5
         0x93, 0x08, 0x30, 0x00, // addi x17, x0, 3
-        0x03, 0x49, 0x40, 0x00, // lbu x18, 4(x0)
+        0x03, 0xc9, 0x82, 0x00, // lbu x18, 8(x5)
     ];
-    let _expected_xregs = helper::create_xregs(vec![(16, 5), (17, 3), (18, 0x93)]);
+    let expected_xregs = helper::create_xregs(vec![(5, DRAM_BASE), (16, 5), (17, 3), (18, 0x93)]);
 
-    // TODO: fix LoadAccessFault
-    //helper::run(&mut emu, data, &expected_xregs);
+    helper::run(&mut emu, data, &expected_xregs);
 }
 
 #[test]
 fn lhu_rd_offset_rs1() {
-    let mut _emu = Emulator::new();
+    let mut emu = Emulator::new();
 
-    let _data = vec![
+    let data = vec![
+        0xb7, 0x02, 0x01, 0x00, // lui x5, 16
         0x13, 0x08, 0x50, 0x00, // addi x16, x0, 5
         0x93, 0x08, 0x30, 0x00, // addi x17, x0, 3
-        0x03, 0x59, 0x40, 0x00, // lbu x18, 4(x0)
+        0x03, 0xd9, 0x82, 0x00, // lhu x18, 8(x5)
     ];
-    let _expected_xregs = helper::create_xregs(vec![(16, 5), (17, 3), (18, 0x0893)]);
+    let expected_xregs = helper::create_xregs(vec![(5, DRAM_BASE), (16, 5), (17, 3), (18, 0x0893)]);
 
-    // TODO: fix LoadAccessFault
-    //helper::run(&mut emu, data, &expected_xregs);
+    helper::run(&mut emu, data, &expected_xregs);
 }
 
 #[test]
@@ -208,53 +210,65 @@ fn auipc_rd_imm() {
 
 #[test]
 fn sb_rs2_offset_rs1() {
-    let mut _emu = Emulator::new();
+    let mut emu = Emulator::new();
 
-    let _data = vec![
+    let data = vec![
+        0xb7, 0x02, 0x01, 0x00, // lui x5, 16
         0x13, 0x08, 0xb0, 0xff, // addi x16, x0, -5
         0x93, 0x08, 0x30, 0x00, // addi x17, x0, 3
-        0x23, 0x02, 0x00, 0x01, // sb x16, 4(x0)
-        0x03, 0x09, 0x40, 0x00, // lb x18, 4(x0)
+        0x23, 0x80, 0x02, 0x03, // sb x16, 32(x5)
+        0x03, 0x89, 0x02, 0x02, // lb x18, 32(x5)
     ];
-    let _expected_xregs =
-        helper::create_xregs(vec![(16, -5i64 as u32), (17, 3), (18, -5i64 as u32)]);
+    let expected_xregs = helper::create_xregs(vec![
+        (5, DRAM_BASE),
+        (16, -5i64 as u32),
+        (17, 3),
+        (18, -5i64 as u32),
+    ]);
 
-    // TODO: fix StoreAMOAccessFault
-    //helper::run(&mut emu, data, &expected_xregs);
+    helper::run(&mut emu, data, &expected_xregs);
 }
 
 #[test]
 fn sh_rs2_offset_rs1() {
-    let mut _emu = Emulator::new();
+    let mut emu = Emulator::new();
 
-    let _data = vec![
+    let data = vec![
+        0xb7, 0x02, 0x01, 0x00, // lui x5, 16
         0x13, 0x08, 0x00, 0xc0, // addi x16, x0, -1024
         0x93, 0x08, 0x30, 0x00, // addi x17, x0, 3
-        0x23, 0x12, 0x00, 0x01, // sh x16, 4(x0)
-        0x03, 0x19, 0x40, 0x00, // lh x18, 4(x0)
+        0x23, 0x90, 0x02, 0x03, // sh x16, 32(x5)
+        0x03, 0x99, 0x02, 0x02, // lh x18, 32(x5)
     ];
-    let _expected_xregs =
-        helper::create_xregs(vec![(16, -1024i64 as u32), (17, 3), (18, -1024i64 as u32)]);
+    let expected_xregs = helper::create_xregs(vec![
+        (5, DRAM_BASE),
+        (16, -1024i64 as u32),
+        (17, 3),
+        (18, -1024i64 as u32),
+    ]);
 
-    // TODO: fix StoreAMOAccessFault
-    //helper::run(&mut emu, data, &expected_xregs);
+    helper::run(&mut emu, data, &expected_xregs);
 }
 
 #[test]
 fn sw_rs2_offset_rs1() {
-    let mut _emu = Emulator::new();
+    let mut emu = Emulator::new();
 
-    let _data = vec![
+    let data = vec![
+        0xb7, 0x02, 0x01, 0x00, // lui x5, 16
         0x13, 0x08, 0x00, 0x80, // addi x16, x0, -2048
         0x93, 0x08, 0x30, 0x00, // addi x17, x0, 3
-        0x23, 0x22, 0x00, 0x01, // sw x16, 4(x0)
-        0x03, 0x29, 0x40, 0x00, // lw x18, 4(x0)
+        0x23, 0xa0, 0x02, 0x03, // sw x16, 32(x5)
+        0x03, 0xa9, 0x02, 0x02, // lw x18, 32(x5)
     ];
-    let _expected_xregs =
-        helper::create_xregs(vec![(16, -2048i64 as u32), (17, 3), (18, -2048i64 as u32)]);
+    let expected_xregs = helper::create_xregs(vec![
+        (5, DRAM_BASE),
+        (16, -2048i64 as u32),
+        (17, 3),
+        (18, -2048i64 as u32),
+    ]);
 
-    // TODO: fix StoreAMOAccessFault
-    //helper::run(&mut emu, data, &expected_xregs);
+    helper::run(&mut emu, data, &expected_xregs);
 }
 
 #[test]
@@ -423,7 +437,7 @@ fn beq_rs1_rs2_imm() {
 
     helper::run(&mut emu, data, &expected_xregs);
 
-    assert_eq!(20 + DRAM_BASE, emu.cpu.pc);
+    assert_eq!(20 + DRAM_BASE, emu.harts[0].pc);
 }
 
 #[test]
@@ -439,7 +453,7 @@ fn bne_rs1_rs2_imm() {
 
     helper::run(&mut emu, data, &expected_xregs);
 
-    assert_eq!(20 + DRAM_BASE, emu.cpu.pc);
+    assert_eq!(20 + DRAM_BASE, emu.harts[0].pc);
 }
 
 #[test]
@@ -455,7 +469,7 @@ fn blt_rs1_rs2_imm() {
 
     helper::run(&mut emu, data, &expected_xregs);
 
-    assert_eq!(20 + DRAM_BASE, emu.cpu.pc);
+    assert_eq!(20 + DRAM_BASE, emu.harts[0].pc);
 }
 
 #[test]
@@ -471,7 +485,7 @@ fn bge_rs1_rs2_imm() {
 
     helper::run(&mut emu, data, &expected_xregs);
 
-    assert_eq!(20 + DRAM_BASE, emu.cpu.pc);
+    assert_eq!(20 + DRAM_BASE, emu.harts[0].pc);
 }
 
 #[test]
@@ -487,7 +501,7 @@ fn bltu_rs1_rs2_imm() {
 
     helper::run(&mut emu, data, &expected_xregs);
 
-    assert_eq!(20 + DRAM_BASE, emu.cpu.pc);
+    assert_eq!(20 + DRAM_BASE, emu.harts[0].pc);
 }
 
 #[test]
@@ -503,7 +517,7 @@ fn bgeu_rs1_rs2_imm() {
 
     helper::run(&mut emu, data, &expected_xregs);
 
-    assert_eq!(20 + DRAM_BASE, emu.cpu.pc);
+    assert_eq!(20 + DRAM_BASE, emu.harts[0].pc);
 }
 
 #[test]
@@ -519,7 +533,7 @@ fn jalr_rd_imm() {
 
     helper::run(&mut emu, data, &expected_xregs);
 
-    assert_eq!(44, emu.cpu.pc);
+    assert_eq!(44, emu.harts[0].pc);
 }
 
 #[test]
@@ -535,5 +549,5 @@ fn jal_rd_imm() {
 
     helper::run(&mut emu, data, &expected_xregs);
 
-    assert_eq!(20 + DRAM_BASE, emu.cpu.pc);
+    assert_eq!(20 + DRAM_BASE, emu.harts[0].pc);
 }
@@ -0,0 +1,226 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use riscv::bus::DRAM_BASE;
+use riscv::csr::{MCAUSE, MEPC};
+use riscv::emulator::Emulator;
+use riscv::semihosting::{Host, Stream};
+
+/// The console of the test host, shared with the test.
+#[derive(Default)]
+struct Console {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    stdin: VecDeque<u8>,
+}
+
+struct TestHost(Rc<RefCell<Console>>);
+
+impl Host for TestHost {
+    fn write(&mut self, stream: Stream, data: &[u8]) {
+        let mut console = self.0.borrow_mut();
+        match stream {
+            Stream::Stdout => console.stdout.extend_from_slice(data),
+            Stream::Stderr => console.stderr.extend_from_slice(data),
+        }
+    }
+
+    fn read_char(&mut self) -> Option<u8> {
+        self.0.borrow_mut().stdin.pop_front()
+    }
+
+    fn elapsed(&mut self) -> Option<u64> {
+        Some(0x1_0000_0002)
+    }
+
+    fn tick_frequency(&mut self) -> Option<u32> {
+        Some(1000)
+    }
+}
+
+/// Create an emulator with a semihosting host, and return it with the host console.
+fn emulator_with_host(data: Vec<u8>) -> (Emulator, Rc<RefCell<Console>>) {
+    let console = Rc::new(RefCell::new(Console::default()));
+
+    let mut emu = Emulator::new();
+    emu.semihosting = Some(Box::new(TestHost(console.clone())));
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    (emu, console)
+}
+
+#[test]
+fn write0_and_exit() {
+    let mut data = vec![
+        0xb7, 0x05, 0x01, 0x00, // lui x11, 16
+        0x93, 0x85, 0x05, 0x03, // addi x11, x11, 48
+        0x13, 0x05, 0x40, 0x00, // addi x10, x0, 4
+        0x13, 0x10, 0xf0, 0x01, // slli x0, x0, 31
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x13, 0x50, 0x70, 0x40, // srai x0, x0, 7
+        0x13, 0x05, 0x80, 0x01, // addi x10, x0, 24
+        0xb7, 0x05, 0x02, 0x00, // lui x11, 32
+        0x93, 0x85, 0x65, 0x02, // addi x11, x11, 38
+        0x13, 0x10, 0xf0, 0x01, // slli x0, x0, 31
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x13, 0x50, 0x70, 0x40, // srai x0, x0, 7
+    ];
+    data.extend_from_slice(b"hello\n\0");
+    let (mut emu, console) = emulator_with_host(data);
+
+    emu.start();
+
+    assert_eq!(b"hello\n", console.borrow().stdout.as_slice());
+    assert_eq!(Some(0), emu.exit_code);
+}
+
+#[test]
+fn open_console_and_write() {
+    let mut data = vec![
+        0xb7, 0x02, 0x01, 0x00, // lui x5, 16
+        0x13, 0x83, 0x02, 0x20, // addi x6, x5, 512
+        0x23, 0xa0, 0x62, 0x10, // sw x6, 256(x5)
+        0x13, 0x03, 0x40, 0x00, // addi x6, x0, 4
+        0x23, 0xa2, 0x62, 0x10, // sw x6, 260(x5)
+        0x13, 0x03, 0x30, 0x00, // addi x6, x0, 3
+        0x23, 0xa4, 0x62, 0x10, // sw x6, 264(x5)
+        0x13, 0x05, 0x10, 0x00, // addi x10, x0, 1
+        0x93, 0x85, 0x02, 0x10, // addi x11, x5, 256
+        0x13, 0x10, 0xf0, 0x01, // slli x0, x0, 31
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x13, 0x50, 0x70, 0x40, // srai x0, x0, 7
+        0x23, 0xa0, 0xa2, 0x10, // sw x10, 256(x5)
+        0x13, 0x83, 0x02, 0x21, // addi x6, x5, 528
+        0x23, 0xa2, 0x62, 0x10, // sw x6, 260(x5)
+        0x13, 0x03, 0x50, 0x00, // addi x6, x0, 5
+        0x23, 0xa4, 0x62, 0x10, // sw x6, 264(x5)
+        0x13, 0x05, 0x50, 0x00, // addi x10, x0, 5
+        0x93, 0x85, 0x02, 0x10, // addi x11, x5, 256
+        0x13, 0x10, 0xf0, 0x01, // slli x0, x0, 31
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x13, 0x50, 0x70, 0x40, // srai x0, x0, 7
+        0x13, 0x04, 0x05, 0x00, // addi x8, x10, 0
+        0x13, 0x05, 0x80, 0x01, // addi x10, x0, 24
+        0xb7, 0x05, 0x02, 0x00, // lui x11, 32
+        0x93, 0x85, 0x65, 0x02, // addi x11, x11, 38
+        0x13, 0x10, 0xf0, 0x01, // slli x0, x0, 31
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x13, 0x50, 0x70, 0x40, // srai x0, x0, 7
+    ];
+    data.resize(0x200, 0);
+    data.extend_from_slice(b":tt\0");
+    data.resize(0x210, 0);
+    data.extend_from_slice(b"hello");
+    let (mut emu, console) = emulator_with_host(data);
+
+    emu.start();
+
+    assert_eq!(b"hello", console.borrow().stdout.as_slice());
+    // SYS_WRITE returns the number of bytes that were not written.
+    assert_eq!(0, emu.harts[0].xregs.read(8));
+    assert_eq!(Some(0), emu.exit_code);
+}
+
+#[test]
+fn readc_elapsed_and_exit_failure() {
+    let data = vec![
+        0x13, 0x05, 0x70, 0x00, // addi x10, x0, 7
+        0x13, 0x10, 0xf0, 0x01, // slli x0, x0, 31
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x13, 0x50, 0x70, 0x40, // srai x0, x0, 7
+        0x13, 0x04, 0x05, 0x00, // addi x8, x10, 0
+        0xb7, 0x02, 0x01, 0x00, // lui x5, 16
+        0x13, 0x05, 0x00, 0x03, // addi x10, x0, 48
+        0x93, 0x85, 0x02, 0x10, // addi x11, x5, 256
+        0x13, 0x10, 0xf0, 0x01, // slli x0, x0, 31
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x13, 0x50, 0x70, 0x40, // srai x0, x0, 7
+        0x93, 0x04, 0x05, 0x00, // addi x9, x10, 0
+        0x03, 0xa9, 0x02, 0x10, // lw x18, 256(x5)
+        0x83, 0xa9, 0x42, 0x10, // lw x19, 260(x5)
+        0x13, 0x05, 0x10, 0x03, // addi x10, x0, 49
+        0x13, 0x10, 0xf0, 0x01, // slli x0, x0, 31
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x13, 0x50, 0x70, 0x40, // srai x0, x0, 7
+        0x13, 0x0a, 0x05, 0x00, // addi x20, x10, 0
+        0x13, 0x05, 0x80, 0x01, // addi x10, x0, 24
+        0x93, 0x05, 0x00, 0x00, // addi x11, x0, 0
+        0x13, 0x10, 0xf0, 0x01, // slli x0, x0, 31
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x13, 0x50, 0x70, 0x40, // srai x0, x0, 7
+    ];
+    let (mut emu, console) = emulator_with_host(data);
+    console.borrow_mut().stdin.push_back(b'x');
+
+    emu.start();
+
+    assert_eq!(b'x' as u32, emu.harts[0].xregs.read(8));
+    assert_eq!(0, emu.harts[0].xregs.read(9));
+    assert_eq!(2, emu.harts[0].xregs.read(18));
+    assert_eq!(1, emu.harts[0].xregs.read(19));
+    assert_eq!(1000, emu.harts[0].xregs.read(20));
+    assert_eq!(Some(1), emu.exit_code);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn std_host_elapsed() {
+    let mut host = riscv::semihosting::StdHost::new();
+    let before = host.elapsed().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    // The ticks are microseconds.
+    assert_eq!(Some(1_000_000), host.tick_frequency());
+    assert!(host.elapsed().unwrap() >= before + 2000);
+}
+
+#[test]
+fn ebreak_without_host() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x13, 0x10, 0xf0, 0x01, // slli x0, x0, 31
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x13, 0x50, 0x70, 0x40, // srai x0, x0, 7
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    emu.start();
+
+    // The semihosting sequence is an ordinary breakpoint if no host is installed.
+    assert_eq!(3, emu.harts[0].state.read(MCAUSE));
+    assert_eq!(4 + DRAM_BASE, emu.harts[0].state.read(MEPC));
+    assert_eq!(None, emu.exit_code);
+}
+
+#[test]
+fn write_past_dram() {
+    let mut data = vec![
+        0x13, 0x10, 0xf0, 0x01, // slli x0, x0, 31
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x13, 0x50, 0x70, 0x40, // srai x0, x0, 7
+        0x13, 0x04, 0x05, 0x00, // addi x8, x10, 0
+        0x13, 0x05, 0x80, 0x01, // addi x10, x0, 24
+        0xb7, 0x05, 0x02, 0x00, // lui x11, 32
+        0x93, 0x85, 0x65, 0x02, // addi x11, x11, 38
+        0x13, 0x10, 0xf0, 0x01, // slli x0, x0, 31
+        0x73, 0x00, 0x10, 0x00, // ebreak
+        0x13, 0x50, 0x70, 0x40, // srai x0, x0, 7
+    ];
+    data.resize(0x100, 0);
+    // The parameter block of SYS_WRITE: stdout, the buffer at 0x200, and the largest length.
+    data.extend_from_slice(&[1, 0, 0, 0, 0x00, 0x02, 0x01, 0x00, 0xff, 0xff, 0xff, 0xff]);
+    let (mut emu, console) = emulator_with_host(data);
+    emu.harts[0].xregs.write(10, 5);
+    emu.harts[0].xregs.write(11, DRAM_BASE + 0x100);
+
+    emu.start();
+
+    // The buffer runs past the end of DRAM, so the write fails without writing anything.
+    assert_eq!(u32::MAX, emu.harts[0].xregs.read(8));
+    assert!(console.borrow().stdout.is_empty());
+    assert_eq!(Some(0), emu.exit_code);
+}
@@ -0,0 +1,103 @@
+use riscv::bus::DRAM_BASE;
+use riscv::clint::CLINT_MSIP;
+use riscv::cpu::WORD;
+use riscv::csr::*;
+use riscv::emulator::Emulator;
+
+#[test]
+fn mhartid() {
+    let mut emu = Emulator::with_harts(2);
+
+    let data = vec![
+        0xf3, 0x22, 0x40, 0xf1, // csrrs x5, mhartid, x0
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    emu.step().unwrap();
+
+    assert_eq!(0, emu.harts[0].xregs.read(5));
+    assert_eq!(1, emu.harts[1].xregs.read(5));
+    // The hart ID survives a reset.
+    emu.reset();
+    assert_eq!(1, emu.harts[1].state.read(MHARTID));
+}
+
+#[test]
+fn store_from_other_hart_invalidates_reservation() {
+    let mut emu = Emulator::with_harts(2);
+
+    let data = vec![
+        0xf3, 0x22, 0x40, 0xf1, // csrrs x5, mhartid, x0
+        0x37, 0x03, 0x01, 0x00, // lui x6, 16
+        0x13, 0x03, 0x03, 0x10, // addi x6, x6, 256
+        0x63, 0x98, 0x02, 0x00, // bne x5, x0, 16
+        // hart 0
+        0xaf, 0x23, 0x03, 0x10, // lr.w x7, (x6)
+        0x13, 0x00, 0x00, 0x00, // addi x0, x0, 0
+        0x2f, 0x24, 0x93, 0x18, // sc.w x8, x9, (x6)
+        // hart 1
+        0x93, 0x04, 0x10, 0x00, // addi x9, x0, 1
+        0x23, 0x20, 0x93, 0x00, // sw x9, 0(x6)
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    // The harts run in lockstep, so hart 1 stores between the LR and the SC of hart 0.
+    for _ in 0..7 {
+        emu.step().unwrap();
+    }
+
+    assert_eq!(1, emu.harts[0].xregs.read(8));
+    assert_eq!(1, emu.bus.read(DRAM_BASE + 0x100, WORD).unwrap());
+}
+
+#[test]
+fn software_interrupt_wakes_other_hart() {
+    let mut emu = Emulator::with_harts(2);
+
+    let data = vec![
+        0xf3, 0x22, 0x40, 0xf1, // csrrs x5, mhartid, x0
+        0x63, 0x90, 0x02, 0x02, // bne x5, x0, 32
+        // hart 0: wait, then raise a software interrupt on hart 1
+        0x13, 0x04, 0xa0, 0x00, // addi x8, x0, 10
+        0x13, 0x04, 0xf4, 0xff, // addi x8, x8, -1
+        0xe3, 0x1e, 0x04, 0xfe, // bne x8, x0, -4
+        0x37, 0x03, 0x00, 0x02, // lui x6, 8192
+        0x93, 0x03, 0x10, 0x00, // addi x7, x0, 1
+        0x23, 0x22, 0x73, 0x00, // sw x7, 4(x6)
+        0x6f, 0x00, 0x00, 0x00, // jal x0, 0
+        // hart 1: enable the software interrupt and wait for it
+        0x37, 0x03, 0x01, 0x00, // lui x6, 16
+        0x13, 0x03, 0x03, 0x04, // addi x6, x6, 64
+        0x73, 0x10, 0x53, 0x30, // csrrw x0, mtvec, x6
+        0x93, 0x03, 0x80, 0x00, // addi x7, x0, 8
+        0x73, 0xa0, 0x43, 0x30, // csrrs x0, mie, x7
+        0x73, 0x60, 0x04, 0x30, // csrrsi x0, mstatus, 8
+        0x73, 0x00, 0x50, 0x10, // wfi
+        // hart 1: trap handler
+        0x73, 0x25, 0x20, 0x34, // csrrs x10, mcause, x0
+        0x6f, 0x00, 0x00, 0x00, // jal x0, 0
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    while !emu.harts[1].idle {
+        emu.step().unwrap();
+    }
+    assert_eq!(0, emu.bus.read(CLINT_MSIP + 4, WORD).unwrap());
+
+    for _ in 0..30 {
+        emu.step().unwrap();
+    }
+
+    assert_eq!(1, emu.bus.read(CLINT_MSIP + 4, WORD).unwrap());
+    assert!(!emu.harts[1].idle);
+    assert_eq!(0x80000003, emu.harts[1].xregs.read(10));
+    assert_eq!(DRAM_BASE + 64, emu.harts[1].state.read(MEPC));
+    // Hart 0 was never interrupted.
+    assert_eq!(0, emu.harts[0].state.read(MCAUSE));
+}
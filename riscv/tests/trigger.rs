@@ -0,0 +1,115 @@
+use riscv::bus::DRAM_BASE;
+use riscv::csr::*;
+use riscv::emulator::Emulator;
+use riscv::trigger::*;
+
+#[test]
+fn store_watchpoint() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x37, 0x03, 0x01, 0x00, // lui x6, 16
+        0x13, 0x03, 0x03, 0x10, // addi x6, x6, 256
+        0x73, 0x10, 0x23, 0x7a, // csrrw x0, tdata2, x6
+        0xb7, 0x03, 0x00, 0x68, // lui x7, 425984
+        0x93, 0x83, 0x23, 0x04, // addi x7, x7, 66
+        0x73, 0x90, 0x13, 0x7a, // csrrw x0, tdata1, x7
+        0x13, 0x04, 0x70, 0x00, // addi x8, x0, 7
+        0x73, 0x10, 0x04, 0x7a, // csrrw x0, tselect, x8
+        0x23, 0x22, 0x03, 0x00, // sw x0, 4(x6)
+        0x23, 0x20, 0x03, 0x00, // sw x0, 0(x6)
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    for _ in 0..9 {
+        emu.step().unwrap();
+    }
+    assert_eq!(0, emu.harts[0].state.read(MCAUSE));
+
+    emu.step().unwrap();
+
+    let state = &emu.harts[0].state;
+    assert_eq!(3, state.read(MCAUSE));
+    assert_eq!(DRAM_BASE + 36, state.read(MEPC));
+    // There is no trigger 7, and M-mode can't set dmode.
+    assert_eq!(0, state.read(TSELECT));
+    assert_eq!(0, state.read_bits(TDATA1, TDATA1_DMODE));
+    assert_eq!(1, state.read_bits(TDATA1, MCONTROL6_HIT0));
+    assert_eq!(
+        (1 << 24) | (1 << TRIGGER_TYPE_DISABLED) | (1 << TRIGGER_TYPE_MCONTROL6),
+        state.read(TINFO)
+    );
+}
+
+#[test]
+fn load_watchpoint_mtval() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x37, 0x03, 0x01, 0x00, // lui x6, 16
+        0x13, 0x03, 0x03, 0x10, // addi x6, x6, 256
+        0x83, 0x23, 0x43, 0x00, // lw x7, 4(x6)
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    // Match loads from DRAM_BASE + 260 in M-mode.
+    let state = &mut emu.harts[0].state;
+    state.write(
+        TDATA1,
+        (TRIGGER_TYPE_MCONTROL6 << 28) | (1 << 6) | (ACTION_BREAKPOINT << 12) | 1,
+    );
+    state.write(TDATA2, DRAM_BASE + 260);
+
+    for _ in 0..3 {
+        emu.step().unwrap();
+    }
+
+    // mtval holds the address of the load rather than the address of the instruction.
+    let state = &emu.harts[0].state;
+    assert_eq!(3, state.read(MCAUSE));
+    assert_eq!(DRAM_BASE + 8, state.read(MEPC));
+    assert_eq!(DRAM_BASE + 260, state.read(MTVAL));
+}
+
+#[test]
+fn chained_range_enters_debug_mode() {
+    let mut emu = Emulator::new();
+
+    let data = vec![
+        0x93, 0x02, 0x10, 0x00, // addi x5, x0, 1
+        0x93, 0x82, 0x12, 0x00, // addi x5, x5, 1
+        0x93, 0x82, 0x12, 0x00, // addi x5, x5, 1
+        0x93, 0x82, 0x12, 0x00, // addi x5, x5, 1
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(DRAM_BASE);
+
+    // Match instructions in [DRAM_BASE + 8, DRAM_BASE + 12) with two chained triggers.
+    let mcontrol6 = (TRIGGER_TYPE_MCONTROL6 << 28) | (1 << 27) | (1 << 6) | (1 << 2);
+    let state = &mut emu.harts[0].state;
+    state.write(TSELECT, 0);
+    state.write(TDATA1, mcontrol6 | (1 << 11) | (MATCH_GE << 7));
+    state.write(TDATA2, DRAM_BASE + 8);
+    state.write(TSELECT, 1);
+    state.write(
+        TDATA1,
+        mcontrol6 | (ACTION_DEBUG_MODE << 12) | (MATCH_LT << 7),
+    );
+    state.write(TDATA2, DRAM_BASE + 12);
+
+    for _ in 0..4 {
+        emu.step().unwrap();
+    }
+
+    let cpu = &emu.harts[0];
+    assert!(cpu.is_halted());
+    assert_eq!(2, cpu.xregs.read(5));
+    assert_eq!(DRAM_BASE + 8, cpu.state.read(DPC));
+    assert_eq!(2, cpu.state.read_bits(DCSR, DCSR_CAUSE));
+    assert_eq!(0, cpu.state.read(MCAUSE));
+}
@@ -0,0 +1,40 @@
+use riscv::cpu::BYTE;
+use riscv::emulator::Emulator;
+use riscv::exception::Exception;
+use riscv::uart::*;
+
+#[test]
+fn transmit() {
+    let base = 0x8000_0000;
+    let mut emu = Emulator::with_memory(1, base, 64 * 1024);
+
+    let data = vec![
+        0xb7, 0x02, 0x00, 0x10, // lui x5, 65536
+        0x13, 0x03, 0x80, 0x06, // addi x6, x0, 104
+        0x23, 0x80, 0x62, 0x00, // sb x6, 0(x5)
+        0x13, 0x03, 0x90, 0x06, // addi x6, x0, 105
+        0x23, 0x80, 0x62, 0x00, // sb x6, 0(x5)
+    ];
+
+    emu.initialize_dram(data);
+    emu.initialize_pc(base);
+    emu.bus.uart = Some(Uart::new());
+
+    for _ in 0..5 {
+        emu.step().unwrap();
+    }
+
+    let uart = emu.bus.uart.as_mut().unwrap();
+    assert_eq!(b"hi".to_vec(), uart.take_output());
+    assert!(uart.take_output().is_empty());
+    assert_eq!(
+        UART_LSR_THRE | UART_LSR_TEMT,
+        emu.bus.read(UART_LSR, BYTE).unwrap()
+    );
+    // The stack pointer starts at the end of DRAM, which is outside it.
+    assert_eq!(base + 64 * 1024, emu.harts[0].xregs.read(2));
+    assert_eq!(
+        Err(Exception::LoadAccessFault(base + 64 * 1024)),
+        emu.bus.read(base + 64 * 1024, BYTE)
+    );
+}
@@ -0,0 +1,321 @@
+//! Runs the test vectors in `tests/vectors`.
+//!
+//! A vector is a program with the state it starts from and the state expected after it runs. The
+//! program is loaded at the start of DRAM and runs until the program counter leaves it, so a trap
+//! to the default `mtvec` of 0 ends it too. A regression test for an emulator bug is a vector
+//! appended to one of the `.vec` files, or a new file.
+//!
+//! A `.vec` file is a list of vectors, one directive per line, and `#` starts a comment:
+//!
+//! ```text
+//! test lb_sign                  # start the vector `lb_sign`
+//! code 0x00328303               # lb x6, 3(x5); more words can follow on the same line
+//! asm addi t0, t0, 4            # an instruction; see the `asm` module for the syntax
+//! reg x5 = 0x11000              # the initial value of a register, by x<n> or its ABI name
+//! mem 0x11000 = 0x80000000      # the initial words of the memory at an address
+//! csr mscratch = 1              # the initial value of a CSR, by name or address
+//! expect reg t1 = 0xffffff80    # the value expected after the run; also `mem` and `csr`
+//! expect pc = 0x10008           # the program counter expected after the run
+//! ```
+//!
+//! The `code` words and the `asm` statements make up the program in the order they appear, and
+//! the labels of `asm` statements can be the targets of branches and jumps.
+//!
+//! `expect trap <cause>` expects a trap with a cause named like the variants of `Exception` and
+//! `Interrupt`, such as `expect trap IllegalInstruction`. A vector must list every trap cause it
+//! takes, so a vector without `expect trap` takes no trap. The values are decimal, negative
+//! decimal, or `0x`-prefixed hexadecimal numbers.
+
+use std::fs;
+use std::path::Path;
+
+use riscv::asm::{self, Reg};
+use riscv::bus::DRAM_BASE;
+use riscv::coverage::{Kind, TRAP_CAUSES};
+use riscv::cpu::WORD;
+use riscv::csr::*;
+use riscv::emulator::Emulator;
+
+/// The maximum number of steps a vector may take before it is considered hung.
+const MAX_STEPS: usize = 10_000;
+
+/// A program with its initial and expected state.
+#[derive(Default)]
+struct Vector {
+    /// Where the vector is, as `<file>:<line>: <name>`.
+    name: String,
+    /// The program in assembly, with `code` words as `.word` directives.
+    source: String,
+    /// The line of the file of each line of `source`.
+    source_lines: Vec<usize>,
+    /// The assembled program.
+    code: Vec<u8>,
+    regs: Vec<(u32, u32)>,
+    mem: Vec<(u32, u32)>,
+    csrs: Vec<(CsrAddress, u32)>,
+    expected_regs: Vec<(u32, u32)>,
+    expected_mem: Vec<(u32, u32)>,
+    expected_csrs: Vec<(CsrAddress, u32)>,
+    expected_pc: Option<u32>,
+    expected_traps: Vec<&'static str>,
+}
+
+/// Parse a decimal, negative decimal, or `0x`-prefixed hexadecimal number.
+fn parse_value(s: &str) -> Result<u32, String> {
+    let value = if let Some(hex) = s.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if s.starts_with('-') {
+        s.parse::<i32>().ok().map(|value| value as u32)
+    } else {
+        s.parse().ok()
+    };
+    value.ok_or(format!("invalid value `{}`", s))
+}
+
+/// Parse a register by `x<n>` or its ABI name.
+fn parse_reg(s: &str) -> Result<u32, String> {
+    let reg = Reg::parse(s).ok_or(format!("invalid register `{}`", s))?;
+    Ok(reg.index())
+}
+
+/// Parse a CSR by name or address.
+fn parse_csr(s: &str) -> Result<CsrAddress, String> {
+    match asm::csr_address(s) {
+        Some(addr) => Ok(addr),
+        None => parse_value(s)
+            .ok()
+            .filter(|&addr| addr < CSR_SIZE as u32)
+            .map(|addr| addr as CsrAddress)
+            .ok_or(format!("invalid CSR `{}`", s)),
+    }
+}
+
+/// Parse the values of an assignment `<target> = <value>...` and return the target.
+fn parse_assignment<'a>(words: &[&'a str]) -> Result<(&'a str, Vec<u32>), String> {
+    match words {
+        [target, "=", values @ ..] if !values.is_empty() => {
+            let values = values.iter().map(|value| parse_value(value));
+            Ok((target, values.collect::<Result<_, _>>()?))
+        }
+        _ => Err("expected `<target> = <value>`".into()),
+    }
+}
+
+/// Parse a `reg`, `mem`, or `csr` assignment of a single value, or of consecutive memory words.
+fn parse_state(
+    words: &[&str],
+    regs: &mut Vec<(u32, u32)>,
+    mem: &mut Vec<(u32, u32)>,
+    csrs: &mut Vec<(CsrAddress, u32)>,
+) -> Result<(), String> {
+    let (kind, words) = words.split_first().ok_or("missing directive")?;
+    let (target, values) = parse_assignment(words)?;
+    if *kind == "mem" {
+        let addr = parse_value(target)?;
+        for (i, value) in values.into_iter().enumerate() {
+            mem.push((addr.wrapping_add(4 * i as u32), value));
+        }
+        return Ok(());
+    }
+    let [value] = values[..] else {
+        return Err("expected a single value".into());
+    };
+    match *kind {
+        "reg" => regs.push((parse_reg(target)?, value)),
+        "csr" => csrs.push((parse_csr(target)?, value)),
+        _ => return Err(format!("unknown directive `{}`", kind)),
+    }
+    Ok(())
+}
+
+impl Vector {
+    /// Append a line of assembly from the line `line` of the file to the program.
+    fn push_source(&mut self, line: usize, source: &str) {
+        self.source.push_str(source);
+        self.source.push('\n');
+        self.source_lines.push(line);
+    }
+}
+
+/// Parse a directive of the vector on the line `line` of the file.
+fn parse_directive(vector: &mut Vector, line: usize, words: &[&str]) -> Result<(), String> {
+    match words {
+        ["code", words @ ..] if !words.is_empty() => {
+            for word in words {
+                let word = parse_value(word)?;
+                vector.push_source(line, &format!(".word {:#x}", word));
+            }
+            Ok(())
+        }
+        ["expect", "pc", "=", value] => {
+            vector.expected_pc = Some(parse_value(value)?);
+            Ok(())
+        }
+        ["expect", "trap", cause] => {
+            let cause = TRAP_CAUSES
+                .iter()
+                .find(|name| *name == cause)
+                .ok_or(format!("unknown trap cause `{}`", cause))?;
+            vector.expected_traps.push(cause);
+            Ok(())
+        }
+        ["expect", words @ ..] => parse_state(
+            words,
+            &mut vector.expected_regs,
+            &mut vector.expected_mem,
+            &mut vector.expected_csrs,
+        ),
+        _ => parse_state(words, &mut vector.regs, &mut vector.mem, &mut vector.csrs),
+    }
+}
+
+/// Parse the vectors in the file at `path`.
+fn parse(path: &Path) -> Vec<Vector> {
+    let text =
+        fs::read_to_string(path).unwrap_or_else(|e| panic!("failed to read {:?}: {}", path, e));
+    let file = path.file_name().unwrap().to_string_lossy();
+    let mut vectors: Vec<Vector> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match (&words[..], vectors.last_mut()) {
+            ([], _) => Ok(()),
+            (["test", name], _) => {
+                vectors.push(Vector {
+                    name: format!("{}:{}: {}", file, number + 1, name),
+                    ..Vector::default()
+                });
+                Ok(())
+            }
+            (_, None) => Err("expected `test <name>`".into()),
+            (["asm", ..], Some(vector)) => {
+                vector.push_source(number + 1, &line.trim_start()["asm".len()..]);
+                Ok(())
+            }
+            (words, Some(vector)) => parse_directive(vector, number + 1, words),
+        };
+        if let Err(e) = result {
+            panic!("{}:{}: {}", file, number + 1, e);
+        }
+    }
+    for vector in vectors.iter_mut() {
+        vector.code = asm::assemble(&vector.source).unwrap_or_else(|e| {
+            panic!(
+                "{}:{}: {}",
+                file,
+                vector.source_lines[e.line - 1],
+                e.message
+            )
+        });
+    }
+    vectors
+}
+
+/// Run the vector, and return how the state after the run differs from the expected state.
+fn run(vector: &Vector) -> Vec<String> {
+    let mut emu = Emulator::new();
+    emu.enable_coverage();
+    let end = DRAM_BASE + vector.code.len() as u32;
+    emu.initialize_dram(vector.code.clone());
+    emu.initialize_pc(DRAM_BASE);
+    for (reg, value) in vector.regs.iter() {
+        emu.harts[0].xregs.write(*reg, *value);
+    }
+    for (addr, value) in vector.mem.iter() {
+        if emu.bus.write(*addr, *value, WORD).is_err() {
+            return vec![format!("mem {:#x} is outside DRAM", addr)];
+        }
+    }
+    for (addr, value) in vector.csrs.iter() {
+        emu.harts[0].state.write(*addr, *value);
+    }
+
+    let mut errors = Vec::new();
+    let mut steps = 0;
+    while (DRAM_BASE..end).contains(&emu.harts[0].pc) {
+        if steps == MAX_STEPS {
+            errors.push(format!("still running after {} steps", MAX_STEPS));
+            break;
+        }
+        steps += 1;
+        // A fatal exception ends the run, and is checked like any other trap cause.
+        if emu.step().is_err() {
+            break;
+        }
+    }
+
+    let cpu = &emu.harts[0];
+    for (reg, expected) in vector.expected_regs.iter() {
+        let value = cpu.xregs.read(*reg);
+        if value != *expected {
+            errors.push(format!(
+                "x{} is {:#x}, expected {:#x}",
+                reg, value, expected
+            ));
+        }
+    }
+    for (addr, expected) in vector.expected_mem.iter() {
+        match emu.bus.read(*addr, WORD) {
+            Ok(value) if value == *expected => {}
+            Ok(value) => errors.push(format!(
+                "mem {:#x} is {:#x}, expected {:#x}",
+                addr, value, expected
+            )),
+            Err(_) => errors.push(format!("mem {:#x} is outside DRAM", addr)),
+        }
+    }
+    for (addr, expected) in vector.expected_csrs.iter() {
+        let value = cpu.state.read(*addr);
+        if value != *expected {
+            errors.push(format!(
+                "csr {:#x} is {:#x}, expected {:#x}",
+                addr, value, expected
+            ));
+        }
+    }
+    if let Some(expected) = vector.expected_pc {
+        if cpu.pc != expected {
+            errors.push(format!("pc is {:#x}, expected {:#x}", cpu.pc, expected));
+        }
+    }
+    let coverage = emu.coverage().unwrap();
+    let traps: Vec<&str> = TRAP_CAUSES
+        .iter()
+        .copied()
+        .filter(|cause| coverage.hits(Kind::Trap, cause) > 0)
+        .collect();
+    let mut expected_traps = vector.expected_traps.clone();
+    expected_traps.sort_by_key(|cause| TRAP_CAUSES.iter().position(|name| name == cause));
+    expected_traps.dedup();
+    if traps != expected_traps {
+        errors.push(format!(
+            "trapped on {:?}, expected {:?}",
+            traps, expected_traps
+        ));
+    }
+    errors
+}
+
+#[test]
+fn vectors() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/vectors");
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("failed to read {:?}: {}", dir, e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "vec"))
+        .collect();
+    paths.sort();
+
+    let mut count = 0;
+    let mut failures = Vec::new();
+    for path in paths {
+        for vector in parse(&path) {
+            count += 1;
+            for error in run(&vector) {
+                failures.push(format!("{}: {}", vector.name, error));
+            }
+        }
+    }
+    assert!(count > 0, "no test vectors in {:?}", dir);
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}